@@ -0,0 +1,186 @@
+use crate::store::Store;
+use crate::sym::Interner;
+use crate::{Storage, Sym};
+
+/// A frame in a `ScopeArena`, only meaningful to the arena that made it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FrameId(usize);
+
+struct Frame<T> {
+    data: Store<T>,
+    parent: Option<FrameId>,
+}
+
+/// A tree of frames kept together in one Vec, with frames named by `FrameId`
+/// rather than held by `Rc`. Making a frame is a push, and every frame is freed
+/// at once when the arena is dropped, so it suits an evaluator that makes many
+/// short lived frames. Frames are never freed before that.
+///
+/// Methods taking a FrameId panic if it came from another arena.
+///
+/// ```rust
+/// use scope_store::arena::ScopeArena;
+/// let mut a = ScopeArena::new();
+/// let root = a.root();
+/// a.set_local(root, "x", 1);
+/// let c = a.child(root);
+/// a.set(c, "x", 2);
+/// a.set(c, "y", 3);
+///
+/// assert_eq!(a.get(root, "x"), Some(2));
+/// assert_eq!(a.get(c, "y"), Some(3));
+/// assert_eq!(a.get(root, "y"), None);
+/// ```
+pub struct ScopeArena<T> {
+    frames: Vec<Frame<T>>,
+    names: Interner,
+}
+
+impl<T> ScopeArena<T> {
+    pub fn new() -> Self {
+        Self::with_storage(Storage::default())
+    }
+
+    /// An arena whose frames use the given map
+    pub fn with_storage(storage: Storage) -> Self {
+        ScopeArena {
+            frames: vec![Frame {
+                data: Store::new(storage),
+                parent: None,
+            }],
+            names: Interner::default(),
+        }
+    }
+
+    pub fn root(&self) -> FrameId {
+        FrameId(0)
+    }
+
+    pub fn child(&mut self, parent: FrameId) -> FrameId {
+        let kind = self.frames[parent.0].data.kind();
+        self.frames.push(Frame {
+            data: Store::new(kind),
+            parent: Some(parent),
+        });
+        FrameId(self.frames.len() - 1)
+    }
+
+    pub fn parent(&self, f: FrameId) -> Option<FrameId> {
+        self.frames[f.0].parent
+    }
+
+    /// How many frames the arena holds, including the root
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Always false, as there is always a root
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// f then each of its parents
+    fn chain(&self, f: FrameId) -> impl Iterator<Item = FrameId> + '_ {
+        std::iter::successors(Some(f), move |f| self.frames[f.0].parent)
+    }
+
+    fn owner(&self, f: FrameId, id: &str) -> Option<(FrameId, Sym)> {
+        let s = self.names.get(id)?;
+        let o = self
+            .chain(f)
+            .find(|f| self.frames[f.0].data.contains_key(s))?;
+        Some((o, s))
+    }
+
+    pub fn set_local(&mut self, f: FrameId, id: &str, val: T) {
+        let s = self.names.intern(id);
+        self.frames[f.0].data.insert(s, val);
+    }
+
+    pub fn set_global(&mut self, id: &str, val: T) {
+        self.set(FrameId(0), id, val);
+    }
+
+    /// Replaces id where it is defined, or creates it in f
+    pub fn set(&mut self, f: FrameId, id: &str, val: T) {
+        if let Some(v) = self.try_replace(f, id, val) {
+            self.set_local(f, id, v);
+        }
+    }
+
+    /// Some<T> means id is not defined, and gives back val
+    pub fn try_replace(&mut self, f: FrameId, id: &str, val: T) -> Option<T> {
+        match self.owner(f, id) {
+            Some((o, s)) => {
+                self.frames[o.0].data.insert(s, val);
+                None
+            }
+            None => Some(val),
+        }
+    }
+
+    pub fn update<F: FnOnce(&mut T) -> A, A>(
+        &mut self,
+        f: FrameId,
+        id: &str,
+        func: F,
+    ) -> Option<A> {
+        let (o, s) = self.owner(f, id)?;
+        self.frames[o.0].data.get_mut(s).map(func)
+    }
+
+    /// Removes from the nearest frame that defines id
+    pub fn remove(&mut self, f: FrameId, id: &str) -> Option<T> {
+        let (o, s) = self.owner(f, id)?;
+        self.frames[o.0].data.remove(s)
+    }
+
+    pub fn with<R, F: FnOnce(&T) -> R>(&self, f: FrameId, id: &str, func: F) -> Option<R> {
+        let (o, s) = self.owner(f, id)?;
+        self.frames[o.0].data.get(s).map(func)
+    }
+
+    pub fn contains(&self, f: FrameId, id: &str) -> bool {
+        self.owner(f, id).is_some()
+    }
+}
+
+impl<T> Default for ScopeArena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> ScopeArena<T> {
+    pub fn get(&self, f: FrameId, id: &str) -> Option<T> {
+        self.with(f, id, T::clone)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn frames_live_in_the_arena() {
+        let mut a = ScopeArena::with_storage(Storage::Hash);
+        let root = a.root();
+        a.set_global("g", 1);
+        let b = a.child(root);
+        let c = a.child(b);
+        a.set_local(b, "x", 2);
+        assert_eq!(a.len(), 3);
+        assert_eq!(a.parent(c), Some(b));
+        assert_eq!(a.parent(root), None);
+
+        assert_eq!(a.update(c, "x", |n| *n += 10), Some(()));
+        assert_eq!(a.get(b, "x"), Some(12));
+        assert_eq!(a.get(c, "g"), Some(1));
+        assert_eq!(a.try_replace(c, "z", 5), Some(5));
+        assert!(!a.contains(c, "z"));
+
+        a.set_local(c, "x", 3);
+        assert_eq!(a.remove(c, "x"), Some(3));
+        assert_eq!(a.with(c, "x", |n| n * 2), Some(24));
+        assert_eq!(a.remove(root, "x"), None);
+    }
+}
@@ -0,0 +1,85 @@
+use crate::PScope;
+
+/// Command line options as a layer of bindings, for the usual chain of
+/// defaults, then a config file, then the command line, each a child of the last.
+/// `--key=value` sets key, `--flag` on its own sets flag to "true", and
+/// everything after a bare `--` is left as it is.
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let defaults = PScope::new();
+/// defaults.set_local("level".to_string(), "info".to_string());
+/// defaults.set_local("color".to_string(), "false".to_string());
+///
+/// let args = ["run", "--level=debug", "--color", "--", "--not-an-option"];
+/// let (cli, rest) = defaults.child_from_args(args);
+/// assert_eq!(cli.get("level").as_deref(), Some("debug"));
+/// assert_eq!(cli.get("color").as_deref(), Some("true"));
+/// assert_eq!(rest, vec!["run", "--not-an-option"]);
+/// ```
+impl PScope<String> {
+    /// Sets each option in args in this frame, in order, so the last of
+    /// a repeated option wins. Gives back the arguments that are not options.
+    pub fn load_args<I, S>(&self, args: I) -> Vec<String>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut rest = Vec::new();
+        let mut args = args.into_iter().map(Into::into);
+        for a in &mut args {
+            let opt = match a.strip_prefix("--") {
+                Some("") => break,
+                Some(o) => o,
+                None => {
+                    rest.push(a);
+                    continue;
+                }
+            };
+            match opt.split_once('=') {
+                Some((k, v)) => self.set_local(k.to_string(), v.to_string()),
+                None => self.set_local(opt.to_string(), "true".to_string()),
+            }
+        }
+        rest.extend(args);
+        rest
+    }
+
+    /// A new child holding the options in args, see load_args.
+    /// Pass `std::env::args().skip(1)` for the program's own arguments.
+    pub fn child_from_args<I, S>(&self, args: I) -> (Self, Vec<String>)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let c = self.child_named("<args>");
+        let rest = c.load_args(args);
+        (c, rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn options_split_from_arguments() {
+        let root = PScope::new();
+        root.set_local("out".to_string(), "a.txt".to_string());
+        let (cli, rest) = root.child_from_args(vec![
+            "--out=b.txt".to_string(),
+            "-v".to_string(),
+            "--eq==x".to_string(),
+            "--out=c.txt".to_string(),
+            "file".to_string(),
+        ]);
+        assert_eq!(cli.name(), Some("<args>"));
+        assert_eq!(cli.get("out").as_deref(), Some("c.txt"));
+        assert_eq!(cli.get("eq").as_deref(), Some("=x"));
+        assert_eq!(root.get("out").as_deref(), Some("a.txt"));
+        assert_eq!(rest, vec!["-v", "file"]);
+
+        let none: [&str; 0] = [];
+        assert!(root.child().load_args(none).is_empty());
+        assert_eq!(root.child().load_args(["--", "--", "x"]), vec!["--", "x"]);
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::{Notify, RwLock};
+
+struct Frame<T> {
+    data: RwLock<BTreeMap<String, T>>,
+    parent: Option<AsyncPScope<T>>,
+    /// Number of parents above this frame
+    depth: usize,
+    /// Shared by the whole tree, woken by every write
+    changed: Arc<Notify>,
+}
+
+/// A scope for async interpreters, where each frame sits behind a tokio `RwLock`,
+/// so a task waiting on a frame yields instead of blocking its thread, and no
+/// std lock is ever held across an `.await`. Each frame is locked on its own,
+/// so `set` on a name another task is creating may make it in either frame.
+///
+/// ```rust
+/// use scope_store::async_scope::AsyncPScope;
+/// let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+/// rt.block_on(async {
+///     let root = AsyncPScope::new();
+///     root.set_local("x".to_string(), 1).await;
+///     let c = root.child();
+///     c.set("x".to_string(), 2).await;
+///     c.set_local("y".to_string(), 3).await;
+///     assert_eq!(c.update("y", |n| *n * 10).await, Some(30));
+///     assert_eq!(root.get("x").await, Some(2));
+///     assert_eq!(root.get("y").await, None);
+/// });
+/// ```
+pub struct AsyncPScope<T> {
+    f: Arc<Frame<T>>,
+}
+
+impl<T> Clone for AsyncPScope<T> {
+    fn clone(&self) -> Self {
+        AsyncPScope { f: self.f.clone() }
+    }
+}
+
+impl<T> AsyncPScope<T> {
+    pub fn new() -> Self {
+        Self::frame(None)
+    }
+
+    fn frame(parent: Option<Self>) -> Self {
+        let (depth, changed) = match &parent {
+            Some(p) => (p.f.depth + 1, p.f.changed.clone()),
+            None => (0, Arc::new(Notify::new())),
+        };
+        AsyncPScope {
+            f: Arc::new(Frame {
+                data: RwLock::new(BTreeMap::new()),
+                parent,
+                depth,
+                changed,
+            }),
+        }
+    }
+
+    pub fn child(&self) -> Self {
+        Self::frame(Some(self.clone()))
+    }
+
+    pub fn parent(&self) -> Option<Self> {
+        self.f.parent.clone()
+    }
+
+    /// Number of parents above this frame
+    pub fn depth(&self) -> usize {
+        self.f.depth
+    }
+
+    /// This frame then each of its parents
+    fn frames(&self) -> impl Iterator<Item = &Frame<T>> {
+        std::iter::successors(Some(&*self.f), |f| f.parent.as_ref().map(|p| &*p.f))
+    }
+
+    fn root_frame(&self) -> &Frame<T> {
+        self.frames().last().expect("a chain has a root")
+    }
+
+    pub async fn set_local(&self, id: String, val: T) {
+        self.f.data.write().await.insert(id, val);
+        self.f.changed.notify_waiters();
+    }
+
+    pub async fn set_global(&self, id: String, val: T) {
+        self.root_frame().data.write().await.insert(id, val);
+        self.f.changed.notify_waiters();
+    }
+
+    /// Replaces id where it is defined, or creates it here
+    pub async fn set(&self, id: String, val: T) {
+        if let Some(v) = self.try_replace(&id, val).await {
+            self.set_local(id, v).await;
+        }
+    }
+
+    /// Some<T> means id is not defined, and gives back val
+    pub async fn try_replace(&self, id: &str, val: T) -> Option<T> {
+        for f in self.frames() {
+            if let Some(v) = f.data.write().await.get_mut(id) {
+                *v = val;
+                f.changed.notify_waiters();
+                return None;
+            }
+        }
+        Some(val)
+    }
+
+    /// f runs while the owning frame is write locked, so it can't await
+    pub async fn update<F: FnOnce(&mut T) -> A, A>(&self, id: &str, f: F) -> Option<A> {
+        for fr in self.frames() {
+            if let Some(v) = fr.data.write().await.get_mut(id) {
+                let res = f(v);
+                fr.changed.notify_waiters();
+                return Some(res);
+            }
+        }
+        None
+    }
+
+    pub async fn with<R, F: FnOnce(&T) -> R>(&self, id: &str, f: F) -> Option<R> {
+        for fr in self.frames() {
+            if let Some(v) = fr.data.read().await.get(id) {
+                return Some(f(v));
+            }
+        }
+        None
+    }
+
+    pub async fn remove_local(&self, id: &str) -> Option<T> {
+        self.f.data.write().await.remove(id)
+    }
+
+    /// Removes from the nearest frame that defines id
+    pub async fn remove(&self, id: &str) -> Option<T> {
+        for f in self.frames() {
+            if let Some(v) = f.data.write().await.remove(id) {
+                return Some(v);
+            }
+        }
+        None
+    }
+
+    pub async fn contains(&self, id: &str) -> bool {
+        self.with(id, |_| ()).await.is_some()
+    }
+
+    /// The names set in this frame, sorted
+    pub async fn local_keys(&self) -> Vec<String> {
+        self.f.data.read().await.keys().cloned().collect()
+    }
+}
+
+impl<T> Default for AsyncPScope<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> AsyncPScope<T> {
+    pub async fn get(&self, id: &str) -> Option<T> {
+        self.with(id, T::clone).await
+    }
+
+    /// The value of id, waiting until some other task defines it if nothing does yet
+    pub async fn wait_for(&self, id: &str) -> T {
+        loop {
+            let mut next = std::pin::pin!(self.f.changed.notified());
+            // registered before looking, so a write between the two still wakes it
+            next.as_mut().enable();
+            if let Some(v) = self.get(id).await {
+                return v;
+            }
+            next.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[tokio::test]
+    async fn tasks_share_frames() {
+        let root = AsyncPScope::new();
+        root.set_local("n".to_string(), 0).await;
+        let c = root.child().child();
+        assert_eq!(c.depth(), 2);
+
+        let held = root.f.data.read().await;
+        let writer = {
+            let c = c.clone();
+            tokio::spawn(async move { c.update("n", |n| *n += 1).await })
+        };
+        tokio::task::yield_now().await;
+        // the writer is waiting on the root, without blocking this thread
+        assert!(!writer.is_finished());
+        drop(held);
+        assert_eq!(writer.await.unwrap(), Some(()));
+
+        c.set_global("g".to_string(), 5).await;
+        c.set_local("l".to_string(), 1).await;
+        assert_eq!(c.try_replace("z", 2).await, Some(2));
+        assert!(c.contains("g").await);
+        assert_eq!(c.local_keys().await, vec!["l"]);
+        assert_eq!(c.remove_local("l").await, Some(1));
+        assert_eq!(c.remove("g").await, Some(5));
+        assert_eq!(c.parent().unwrap().get("n").await, Some(1));
+    }
+
+    #[tokio::test]
+    async fn waiting_for_bindings() {
+        let root = AsyncPScope::new();
+        let c = root.child();
+        let waiter = {
+            let c = c.clone();
+            tokio::spawn(async move { c.wait_for("x").await })
+        };
+        tokio::task::yield_now().await;
+        c.set_local("other".to_string(), 0).await;
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        root.child().set_global("x".to_string(), 7).await;
+        assert_eq!(waiter.await.unwrap(), 7);
+        assert_eq!(c.wait_for("other").await, 0);
+    }
+}
@@ -0,0 +1,205 @@
+use crate::{PScope, ScopeError, Sym};
+use std::collections::BTreeSet;
+
+/// One change for `PScope::apply`
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScopeOp<T> {
+    SetLocal(String, T),
+    SetGlobal(String, T),
+    Set(String, T),
+    RemoveLocal(String),
+    Remove(String),
+}
+
+impl<T> ScopeOp<T> {
+    fn id(&self) -> &str {
+        match self {
+            ScopeOp::SetLocal(id, _)
+            | ScopeOp::SetGlobal(id, _)
+            | ScopeOp::Set(id, _)
+            | ScopeOp::RemoveLocal(id)
+            | ScopeOp::Remove(id) => id,
+        }
+    }
+
+    fn is_local(&self) -> bool {
+        matches!(self, ScopeOp::SetLocal(..) | ScopeOp::RemoveLocal(_))
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::{PScope, ScopeOp};
+/// let root = PScope::new();
+/// root.set_local("old".to_string(), 0);
+/// let c = root.child();
+///
+/// let removed = c.apply(vec![
+///     ScopeOp::SetLocal("a".to_string(), 1),
+///     ScopeOp::SetLocal("b".to_string(), 2),
+///     ScopeOp::Set("old".to_string(), 3),
+///     ScopeOp::RemoveLocal("a".to_string()),
+/// ]);
+/// assert_eq!(removed, Ok(vec![1]));
+/// assert_eq!(c.local_keys(), vec!["b"]);
+/// assert_eq!(root.get("old"), Some(3));
+/// ```
+impl<T> PScope<T> {
+    /// Runs ops in order, doing each run of local ops under a single borrow
+    /// of this frame, and gives back the values removed. Subscribers hear of
+    /// the sets in a run once it is done, so only see the values they end with.
+    ///
+    /// If the tree's SetMode is Error and a Set or SetGlobal names something
+    /// undefined, nothing is applied and `ScopeError::Undefined` is returned.
+    /// Names made by an earlier SetLocal in the same batch count as defined.
+    pub fn apply<I: IntoIterator<Item = ScopeOp<T>>>(&self, ops: I) -> Result<Vec<T>, ScopeError> {
+        let ops: Vec<ScopeOp<T>> = ops.into_iter().collect();
+        self.check_ops(&ops)?;
+        let mut removed = Vec::new();
+        let mut ops = ops.into_iter().peekable();
+        while let Some(op) = ops.next() {
+            match op {
+                ScopeOp::SetGlobal(id, v) => self.set_global(id, v),
+                ScopeOp::Set(id, v) => self.set(id, v),
+                ScopeOp::Remove(id) => removed.extend(self.remove(&id)),
+                op => {
+                    let mut run = vec![op];
+                    while let Some(op) = ops.next_if(ScopeOp::is_local) {
+                        run.push(op);
+                    }
+                    self.apply_local(run, &mut removed);
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    fn check_ops(&self, ops: &[ScopeOp<T>]) -> Result<(), ScopeError> {
+        if self.p.create_target().is_some() {
+            return Ok(());
+        }
+        let mut made = BTreeSet::new();
+        for op in ops {
+            let known = match op {
+                ScopeOp::SetLocal(id, _) => {
+                    made.insert(id.as_str());
+                    continue;
+                }
+                ScopeOp::Set(id, _) => made.contains(id.as_str()) || self.contains(id),
+                ScopeOp::SetGlobal(id, _) => made.contains(id.as_str()) || self.root().contains(id),
+                _ => continue,
+            };
+            if !known {
+                return Err(ScopeError::Undefined(op.id().to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_local(&self, run: Vec<ScopeOp<T>>, removed: &mut Vec<T>) {
+        let s = &self.p;
+        if s.frozen.get() {
+            return;
+        }
+        let keys: Vec<Sym> = {
+            let mut names = s.tree().names.borrow_mut();
+            run.iter().map(|op| names.intern(op.id())).collect()
+        };
+        // the hooks run before the frame is borrowed, and drop any set they refuse
+        let run: Vec<(Sym, ScopeOp<T>)> = (keys.into_iter().zip(run))
+            .filter_map(|(k, mut op)| {
+                let refused = match &mut op {
+                    ScopeOp::SetLocal(_, v) => !s.allow_set(k, v),
+                    _ => false,
+                };
+                (!refused).then_some((k, op))
+            })
+            .collect();
+        // Ok with the value a set replaced, or Err with the value taken out by a remove
+        let mut done: Vec<(Sym, Result<Option<T>, T>)> = Vec::with_capacity(run.len());
+        let mut moved = false;
+        {
+            let mut d = s.data_mut();
+            for (k, op) in run {
+                match op {
+                    ScopeOp::SetLocal(_, v) => {
+                        let old = d.insert(k, v);
+                        moved |= old.is_none();
+                        done.push((k, Ok(old)));
+                    }
+                    _ => {
+                        if let Some(v) = d.remove(k) {
+                            moved = true;
+                            done.push((k, Err(v)));
+                        }
+                    }
+                }
+            }
+        }
+        if moved {
+            s.touch();
+        }
+        for (k, v) in done {
+            match v {
+                Ok(old) => {
+                    s.fill(k);
+                    s.undo_value(k, old.as_ref());
+                    s.changed(k);
+                }
+                Err(v) => {
+                    s.forget(k, &v);
+                    removed.push(v);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ScopeOptions, SetMode};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    #[test]
+    fn batches_are_checked_first() {
+        let root = PScope::with_options(ScopeOptions {
+            set_mode: SetMode::Error,
+            ..Default::default()
+        });
+        root.set_local("g".to_string(), 0);
+        let c = root.child();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let s2 = seen.clone();
+        c.subscribe(move |k, v| s2.borrow_mut().push((k.to_string(), *v)));
+
+        let bad = c.apply(vec![
+            ScopeOp::SetLocal("a".to_string(), 1),
+            ScopeOp::Set("a".to_string(), 2),
+            ScopeOp::SetGlobal("nope".to_string(), 3),
+        ]);
+        assert_eq!(bad, Err(ScopeError::Undefined("nope".to_string())));
+        assert!(!c.contains("a"));
+
+        let ok = c.apply(vec![
+            ScopeOp::SetLocal("a".to_string(), 1),
+            ScopeOp::Set("a".to_string(), 2),
+            ScopeOp::SetGlobal("g".to_string(), 3),
+            ScopeOp::SetLocal("b".to_string(), 4),
+            ScopeOp::RemoveLocal("b".to_string()),
+            ScopeOp::RemoveLocal("missing".to_string()),
+            ScopeOp::Remove("g".to_string()),
+        ]);
+        assert_eq!(ok, Ok(vec![4, 3]));
+        assert_eq!(c.get("a"), Some(2));
+        assert!(!root.contains("g"));
+        assert_eq!(
+            *seen.borrow(),
+            vec![
+                ("a".to_string(), 1),
+                ("a".to_string(), 2),
+                ("b".to_string(), 4)
+            ]
+        );
+    }
+}
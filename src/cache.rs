@@ -0,0 +1,154 @@
+use crate::hash::FxBuild;
+use crate::{Scope, Sym};
+use std::collections::HashMap;
+
+/// The generation and distance up to the owner of keys found far away
+pub(crate) type KeyCache = HashMap<Sym, (u64, Option<usize>), FxBuild>;
+
+/// Lookups that walk at least this many frames are remembered
+const MIN_WALK: usize = 4;
+
+/// Each frame remembers how far up the owner of a key was, so a repeat lookup
+/// can skip checking every frame in between, and how far up the next frame
+/// that defines anything is, so lookups of new keys pass over empty frames.
+/// Both are only trusted while the tree's generation is unchanged, and it moves on
+/// whenever any frame gains or loses a key. Keys are never cached in trees that
+/// import, as a key can appear through another tree without this one changing.
+impl<T> Scope<T> {
+    /// Marks every cached lookup in the tree as stale
+    pub(crate) fn touch(&self) {
+        let g = &self.tree().generation;
+        g.set(g.get() + 1);
+        self.bump();
+    }
+
+    /// Marks this frame as changed, for PScope::generation
+    pub(crate) fn bump(&self) {
+        self.version.set(self.version.get() + 1);
+    }
+
+    fn caching(&self) -> bool {
+        !self.tree().imported.get()
+    }
+
+    /// How far up the remembered owner of k is, Some(None) if nothing defined it
+    pub(crate) fn cached(&self, k: Sym) -> Option<Option<usize>> {
+        if !self.caching() {
+            return None;
+        }
+        match self.cache.borrow().get(&k) {
+            Some((g, up)) if *g == self.tree().generation.get() => Some(*up),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn remember(&self, k: Sym, walked: usize, up: Option<usize>) {
+        if walked >= MIN_WALK && self.caching() {
+            let g = self.tree().generation.get();
+            self.cache.borrow_mut().insert(k, (g, up));
+        }
+    }
+
+    /// Whether nothing at all is defined in this frame
+    fn is_blank(&self) -> bool {
+        let a = self.attrs.borrow();
+        self.data().len() == 0
+            && a.uninit.is_empty()
+            && a.masked.is_empty()
+            && self.extras().is_none_or(|x| x.imports.borrow().is_empty())
+    }
+
+    /// The nearest parent that defines anything
+    fn next_used(&self) -> Option<&Scope<T>> {
+        let g = self.tree().generation.get();
+        if let Some((sg, up)) = self.skip.get() {
+            if sg == g {
+                return self.frames().nth(up);
+            }
+        }
+        let mut up = 0;
+        let res = self.frames().skip(1).find(|f| {
+            up += 1;
+            !f.is_blank()
+        });
+        if res.is_some() {
+            self.skip.set(Some((g, up)));
+        }
+        res
+    }
+
+    /// This frame then each parent that defines anything
+    pub(crate) fn used_frames(&self) -> impl Iterator<Item = &Scope<T>> {
+        std::iter::successors(Some(self), |s| s.next_used())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{PScope, ScopeOptions};
+    #[test]
+    fn cache_follows_changes() {
+        let root = PScope::new();
+        root.set_local("x".to_string(), 0);
+        let mid = root.child().child();
+        let mut deep = mid.clone();
+        for _ in 0..5 {
+            deep = deep.child();
+            deep.set_local("y".to_string(), 0);
+        }
+        let s = deep.intern("x");
+        assert_eq!(deep.get("x"), Some(0));
+        assert_eq!(deep.p.cached(s), Some(Some(7)));
+        assert_eq!(deep.get("x"), Some(0));
+
+        mid.set_local("x".to_string(), 1);
+        assert_eq!(deep.get("x"), Some(1));
+        mid.mask("x");
+        assert_eq!(deep.get("x"), None);
+        mid.unmask("x");
+        assert_eq!(deep.get("x"), Some(0));
+
+        root.update("x", |_| assert_eq!(deep.get("x"), None));
+        assert_eq!(deep.get("x"), Some(0));
+        root.remove_local("x");
+        assert_eq!(deep.get("x"), None);
+        assert_eq!(deep.p.cached(s), Some(None));
+        root.set_local("x".to_string(), 2);
+        assert_eq!(deep.get("x"), Some(2));
+
+        let lib = PScope::new();
+        deep.import(&lib, &[("y", "y")]);
+        assert_eq!(deep.get("x"), Some(2));
+        assert_eq!(deep.p.cached(s), None);
+        deep.import_all(&lib);
+        lib.set_local("x".to_string(), 3);
+        assert_eq!(deep.get("x"), Some(3));
+        assert_eq!(deep.p.cached(s), None);
+    }
+
+    #[test]
+    fn empty_frames_are_skipped() {
+        let root = PScope::with_options(ScopeOptions {
+            stats: true,
+            ..Default::default()
+        });
+        root.set_local("a".to_string(), 1);
+        root.set_local("b".to_string(), 2);
+        let mut deep = root.child();
+        for _ in 0..50 {
+            deep = deep.child();
+        }
+        assert_eq!(deep.get("a"), Some(1));
+        deep.reset_stats();
+        assert_eq!(deep.get("b"), Some(2));
+        assert_eq!(root.stats().unwrap().frames_walked, 2);
+
+        let mid = deep.ancestors().nth(20).unwrap();
+        mid.set_local("b".to_string(), 3);
+        assert_eq!(deep.get("b"), Some(3));
+        mid.remove_local("b");
+        mid.mask("a");
+        assert_eq!(deep.get("a"), None);
+        assert_eq!(deep.get("b"), Some(2));
+    }
+}
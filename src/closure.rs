@@ -0,0 +1,162 @@
+use crate::{PScope, Slot};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+/// The bindings a closure captured, see `PScope::capture`.
+/// Each one stays shared with the frame that defines it, so writes are seen both ways,
+/// and that frame is kept alive for as long as the closure is.
+pub struct Closure<T> {
+    slots: BTreeMap<String, Slot<T>>,
+}
+
+impl<T> Clone for Closure<T> {
+    fn clone(&self) -> Self {
+        Closure {
+            slots: self.slots.clone(),
+        }
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// let counter = {
+///     let f = root.child();
+///     f.set_local("n".to_string(), 0);
+///     f.capture(&["n"])
+/// };
+///
+/// counter.update("n", |n| *n += 1);
+/// counter.update("n", |n| *n += 1);
+/// assert_eq!(counter.get("n"), Some(2));
+/// assert_eq!(root.get("n"), None);
+/// ```
+impl<T> PScope<T> {
+    /// Names that are not visible from here are left out
+    pub fn capture(&self, names: &[&str]) -> Closure<T> {
+        Closure {
+            slots: (names.iter())
+                .filter_map(|n| Some((n.to_string(), self.resolve(n)?)))
+                .collect(),
+        }
+    }
+}
+
+impl<T> Closure<T> {
+    pub fn slot(&self, name: &str) -> Option<&Slot<T>> {
+        self.slots.get(name)
+    }
+
+    /// The captured names, sorted
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.slots.keys().map(|k| k.as_str())
+    }
+
+    /// Returns false, changing nothing, if name was not captured
+    pub fn set(&self, name: &str, val: T) -> bool {
+        match self.slots.get(name) {
+            Some(s) => {
+                s.frame().set_slot(s, val);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn update<F: FnOnce(&mut T) -> A, A>(&self, name: &str, f: F) -> Option<A> {
+        let s = self.slots.get(name)?;
+        s.frame().update_slot(s, f)
+    }
+
+    pub fn with<R, F: FnOnce(&T) -> R>(&self, name: &str, f: F) -> Option<R> {
+        let s = self.slots.get(name)?;
+        s.frame().with_slot(s, f)
+    }
+}
+
+impl<T: Clone> Closure<T> {
+    pub fn get(&self, name: &str) -> Option<T> {
+        self.with(name, T::clone)
+    }
+}
+
+/// Frames store plain values, not one cell per binding. `get_ref`, `entry` and
+/// `ScopeRef` lend out a borrow of the frame's own map, which a value inside a
+/// cell can't give without a second guard of its own, and every read and write
+/// of every scope would pay for the extra allocation and borrow check. So
+/// aliasing is opt in: a scope of `Rc<RefCell<V>>` holds a cell per binding,
+/// and these make, find and write through them. Closures over plain bindings
+/// can use `capture`, which keeps the defining frame alive instead.
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// let a = root.set_local_cell("a".to_string(), 1);
+/// let c = root.child();
+///
+/// let b = c.get_cell("a").unwrap();
+/// *b.borrow_mut() += 1;
+/// assert_eq!(*a.borrow(), 2);
+/// assert!(c.assign_cell("a", 5));
+/// assert_eq!(*b.borrow(), 5);
+/// ```
+impl<V> PScope<Rc<RefCell<V>>> {
+    /// The cell holding the visible binding, shared with every other alias
+    pub fn get_cell(&self, id: &str) -> Option<Rc<RefCell<V>>> {
+        self.with(id, Rc::clone)
+    }
+
+    /// Creates id in this frame, in a new cell that is also returned
+    pub fn set_local_cell(&self, id: String, val: V) -> Rc<RefCell<V>> {
+        let c = Rc::new(RefCell::new(val));
+        self.set_local(id, c.clone());
+        c
+    }
+
+    /// Writes into the visible cell, so every alias sees the change.
+    /// Returns false if id is not defined.
+    pub fn assign_cell(&self, id: &str, val: V) -> bool {
+        self.with(id, |c| *c.borrow_mut() = val).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn captures_outlive_frames() {
+        let root = PScope::new();
+        root.set_local("g".to_string(), 1);
+        let f = root.child();
+        f.set_local("a".to_string(), 2);
+        let inner = f.child();
+        let c = inner.capture(&["a", "g", "missing"]);
+        let c2 = c.clone();
+        drop((f, inner));
+
+        assert_eq!(c.names().collect::<Vec<_>>(), vec!["a", "g"]);
+        assert!(c.set("a", 3));
+        assert!(!c.set("missing", 3));
+        assert_eq!(c2.get("a"), Some(3));
+
+        c.set("g", 4);
+        assert_eq!(root.get("g"), Some(4));
+        root.set_local("g".to_string(), 5);
+        assert_eq!(c2.get("g"), Some(5));
+        assert!(PScope::ptr_eq(c.slot("g").unwrap().frame(), &root));
+    }
+
+    #[test]
+    fn cells_alias_across_frames() {
+        let root = PScope::new();
+        root.set_local_cell("x".to_string(), vec![1]);
+        let f = root.child();
+        let g = root.child();
+        f.get_cell("x").unwrap().borrow_mut().push(2);
+        assert_eq!(*g.get_cell("x").unwrap().borrow(), vec![1, 2]);
+        assert!(!g.assign_cell("y", vec![]));
+        assert!(g.get_cell("y").is_none());
+    }
+}
@@ -0,0 +1,202 @@
+use crate::{PScope, Scope, ScopeRef, SubId, Sym};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT_FORMULA: AtomicU64 = AtomicU64::new(0);
+
+type Formula<T> = Box<dyn Fn(&[&T]) -> T>;
+
+/// Held by the subscriptions on each frame of the chain.
+/// Frames are held weakly, so a computed binding never keeps its tree alive.
+struct Computed<T> {
+    frame: Weak<Scope<T>>,
+    id: Sym,
+    /// Matches the frame's attrs while this is still the formula for id
+    n: u64,
+    deps: Vec<String>,
+    f: Formula<T>,
+    subs: RefCell<Vec<(Weak<Scope<T>>, SubId)>>,
+}
+
+impl<T: 'static> Computed<T> {
+    fn compute(&self) {
+        let s = match self.frame.upgrade() {
+            Some(p) => PScope { p },
+            None => return self.stop(),
+        };
+        if s.p.attrs.borrow().computed.get(&self.id).map(|c| c.0) != Some(self.n) {
+            return self.stop();
+        }
+        let val = {
+            let refs: Option<Vec<ScopeRef<T>>> = self.deps.iter().map(|d| s.get_ref(d)).collect();
+            let refs = match refs {
+                Some(r) => r,
+                None => return,
+            };
+            let vals: Vec<&T> = refs.iter().map(|r| &**r).collect();
+            (self.f)(&vals)
+        };
+        s.p.set_local(self.id, val);
+    }
+
+    fn stop(&self) {
+        for (f, id) in self.subs.borrow_mut().drain(..) {
+            if let Some(p) = f.upgrade() {
+                PScope { p }.unsubscribe(id);
+            }
+        }
+    }
+}
+
+impl<T> Scope<T> {
+    /// Whether a change to id here would come back to it through deps.
+    /// A formula hears of its deps changing in its own frame or any parent,
+    /// so each dep leads to every formula for it from there up.
+    fn feeds(&self, id: Sym, deps: &[Sym]) -> bool {
+        let mut todo: Vec<(&Scope<T>, Sym)> = deps.iter().map(|&d| (self, d)).collect();
+        let mut seen = BTreeSet::new();
+        while let Some((from, d)) = todo.pop() {
+            for f in from.frames() {
+                if d == id && std::ptr::eq(f, self) {
+                    return true;
+                }
+                if !seen.insert((f as *const Scope<T>, d)) {
+                    continue;
+                }
+                if let Some((_, ds)) = f.attrs.borrow().computed.get(&d) {
+                    todo.extend(ds.iter().map(|&d2| (f, d2)));
+                }
+            }
+        }
+        false
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// root.set_local("price".to_string(), 10);
+/// let c = root.child();
+/// c.set_local("count".to_string(), 3);
+/// c.set_computed("total", &["price", "count"], |v| v[0] * v[1]);
+/// assert_eq!(c.get("total"), Some(30));
+///
+/// root.set("price".to_string(), 20);
+/// assert_eq!(c.get("total"), Some(60));
+/// c.set_local("price".to_string(), 1);
+/// assert_eq!(c.get("total"), Some(3));
+/// ```
+impl<T: 'static> PScope<T> {
+    /// Sets id here to f of the values of deps, as seen from here, and sets it
+    /// again whenever one of them changes in this frame or a parent. While any
+    /// of deps is undefined, id keeps the value it had. Deps are read like
+    /// `get_ref`, so imported names don't count.
+    ///
+    /// Removing id ends the formula, as does giving it another. Setting id by
+    /// hand does not, so the value set is replaced at the next change.
+    /// Panics if id depends on itself, directly or through other formulas.
+    pub fn set_computed<F: Fn(&[&T]) -> T + 'static>(&self, id: &str, deps: &[&str], f: F) {
+        let k = self.p.intern(id);
+        let ds: Vec<Sym> = deps.iter().map(|d| self.p.intern(d)).collect();
+        assert!(
+            !self.p.feeds(k, &ds),
+            "{} can't be computed from itself",
+            id
+        );
+        if self.is_frozen() {
+            return;
+        }
+        let n = NEXT_FORMULA.fetch_add(1, Ordering::Relaxed);
+        self.p.attrs.borrow_mut().computed.insert(k, (n, ds));
+        let c = Rc::new(Computed {
+            frame: Rc::downgrade(&self.p),
+            id: k,
+            n,
+            deps: deps.iter().map(|d| d.to_string()).collect(),
+            f: Box::new(f),
+            subs: RefCell::new(Vec::new()),
+        });
+        let mut cur = Some(self.clone());
+        while let Some(fr) = cur {
+            let c2 = c.clone();
+            let sub = fr.subscribe(move |k, _| {
+                if c2.deps.iter().any(|d| d == k) {
+                    match c2.frame.upgrade() {
+                        Some(p) => {
+                            let c3 = c2.clone();
+                            p.defer(move || c3.compute())
+                        }
+                        // only touches subscriptions, so needn't wait
+                        None => c2.stop(),
+                    }
+                }
+            });
+            c.subs.borrow_mut().push((Rc::downgrade(&fr.p), sub));
+            cur = fr.parent();
+        }
+        c.compute();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn computed_follow_their_deps() {
+        let root = PScope::new();
+        let c = root.child();
+        c.set_computed("b", &["a"], |v| v[0] + 1);
+        c.set_computed("d", &["b", "c"], |v| v[0] * v[1]);
+        assert!(!c.contains("b"));
+
+        root.set_local("a".to_string(), 1);
+        root.set_local("c".to_string(), 10);
+        assert_eq!(c.get("d"), Some(20));
+        root.update("a", |a| *a += 1);
+        assert_eq!(c.get("d"), Some(30));
+
+        // a new formula replaces the old one
+        c.set_computed("b", &["c"], |v| *v[0]);
+        root.set_local("a".to_string(), 100);
+        assert_eq!(c.get("b"), Some(10));
+
+        assert_eq!(c.remove_local("b"), Some(10));
+        root.set_local("c".to_string(), 5);
+        assert!(!c.contains("b"));
+        let subs = |s: &PScope<i32>| s.p.extras().map_or(0, |x| x.subs.borrow().len());
+        assert_eq!(subs(&root), 1);
+        assert_eq!(subs(&root), 1);
+
+        drop(c);
+        root.set_local("c".to_string(), 6);
+        assert_eq!(subs(&root), 0);
+    }
+
+    #[test]
+    fn cycles_are_refused() {
+        let root = PScope::new();
+        root.set_computed("a", &["b"], |v| v[0] + 1);
+        let c = root.child();
+        c.set_computed("x", &["a"], |v| *v[0]);
+        let cycle = |s: &PScope<i32>, id: &'static str, deps: &'static [&'static str]| {
+            let s = s.clone();
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+                s.set_computed(id, deps, |v| *v[0])
+            }))
+            .is_err()
+        };
+        assert!(cycle(&root, "b", &["a"]));
+        assert!(cycle(&c, "a", &["x"]));
+        assert!(cycle(&c, "q", &["q"]));
+        // root's formulas don't hear of changes in c, so these are no cycle
+        assert!(!cycle(&root, "b", &["x"]));
+        assert!(!cycle(&c, "b", &["x"]));
+
+        root.set_local("x".to_string(), 4);
+        assert_eq!(c.get("a"), Some(5));
+        assert_eq!(c.get("x"), Some(5));
+    }
+}
@@ -0,0 +1,277 @@
+use crate::PScope;
+use std::fmt;
+use std::path::Path;
+
+/// Why a config file could not be loaded, see `PScope::load_toml`
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    /// The file is not valid for its format, with the parser's message
+    Parse(String),
+    /// The file could not be watched for changes
+    #[cfg(feature = "watching")]
+    Watch(notify::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "could not read config: {}", e),
+            ConfigError::Parse(e) => write!(f, "could not parse config: {}", e),
+            #[cfg(feature = "watching")]
+            ConfigError::Watch(e) => write!(f, "could not watch config: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+/// The key for an entry under prefix
+#[cfg(any(feature = "toml", feature = "json"))]
+fn dotted(prefix: &str, k: &str) -> String {
+    match prefix {
+        "" => k.to_string(),
+        _ => format!("{}.{}", prefix, k),
+    }
+}
+
+#[cfg(feature = "toml")]
+fn flatten_toml(prefix: &str, v: &toml::Value, out: &mut Vec<(String, String)>) {
+    use toml::Value;
+    match v {
+        Value::String(s) => out.push((prefix.to_string(), s.clone())),
+        Value::Table(t) => {
+            for (k, v) in t {
+                flatten_toml(&dotted(prefix, k), v, out);
+            }
+        }
+        Value::Array(a) => {
+            for (n, v) in a.iter().enumerate() {
+                flatten_toml(&dotted(prefix, &n.to_string()), v, out);
+            }
+        }
+        v => out.push((prefix.to_string(), v.to_string())),
+    }
+}
+
+#[cfg(feature = "json")]
+fn flatten_json(prefix: &str, v: &serde_json::Value, out: &mut Vec<(String, String)>) {
+    use serde_json::Value;
+    match v {
+        Value::Null => {}
+        Value::String(s) => out.push((prefix.to_string(), s.clone())),
+        Value::Object(m) => {
+            for (k, v) in m {
+                flatten_json(&dotted(prefix, k), v, out);
+            }
+        }
+        Value::Array(a) => {
+            for (n, v) in a.iter().enumerate() {
+                flatten_json(&dotted(prefix, &n.to_string()), v, out);
+            }
+        }
+        v => out.push((prefix.to_string(), v.to_string())),
+    }
+}
+
+#[cfg(feature = "toml")]
+fn toml_pairs(src: &str) -> Result<Vec<(String, String)>, ConfigError> {
+    let t: toml::Table = src
+        .parse()
+        .map_err(|e: toml::de::Error| ConfigError::Parse(e.to_string()))?;
+    let mut pairs = Vec::new();
+    flatten_toml("", &toml::Value::Table(t), &mut pairs);
+    Ok(pairs)
+}
+
+#[cfg(feature = "json")]
+fn json_pairs(src: &str) -> Result<Vec<(String, String)>, ConfigError> {
+    let v: serde_json::Value =
+        serde_json::from_str(src).map_err(|e| ConfigError::Parse(e.to_string()))?;
+    let mut pairs = Vec::new();
+    flatten_json("", &v, &mut pairs);
+    Ok(pairs)
+}
+
+#[cfg(feature = "dotenvy")]
+fn dotenv_pairs(src: &str) -> Result<Vec<(String, String)>, ConfigError> {
+    (dotenvy::from_read_iter(src.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| ConfigError::Parse(e.to_string()))
+}
+
+/// The bindings in src, read as the format the extension of path names:
+/// `.toml`, `.json`, or `.env` and names starting `.env`, like `.env.local`.
+/// Each format needs its own feature.
+#[cfg(feature = "watching")]
+pub(crate) fn file_pairs(path: &Path, src: &str) -> Result<Vec<(String, String)>, ConfigError> {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    match path.extension().and_then(|e| e.to_str()) {
+        #[cfg(feature = "toml")]
+        Some("toml") => toml_pairs(src),
+        #[cfg(feature = "json")]
+        Some("json") => json_pairs(src),
+        #[cfg(feature = "dotenvy")]
+        _ if name.starts_with(".env") || name.ends_with(".env") => dotenv_pairs(src),
+        _ => Err(ConfigError::Parse(format!(
+            "no format is enabled for {}",
+            name
+        ))),
+    }
+}
+
+/// Config files flattened into bindings, for layering them between defaults
+/// in a parent and overrides in a child. Tables become dotted keys, and array
+/// items are keyed by their index, so `[server] ports = [80]` sets
+/// `server.ports.0`. Strings are set as they are, and other values as they
+/// are written, so `true` or `8080`. A `.env` file sets each of its variables.
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let defaults = PScope::new();
+/// defaults.set_local("server.port".to_string(), "80".to_string());
+/// defaults.set_local("server.host".to_string(), "localhost".to_string());
+///
+/// let file = defaults.child();
+/// # #[cfg(feature = "toml")]
+/// file.load_toml("[server]\nport = 8080\ntags = [\"a\", \"b\"]").unwrap();
+/// # #[cfg(all(feature = "json", not(feature = "toml")))]
+/// # file.load_json(r#"{"server": {"port": 8080, "tags": ["a", "b"]}}"#).unwrap();
+/// # #[cfg(not(any(feature = "toml", feature = "json")))]
+/// # file.load_dotenv("server.port=8080\nserver.tags.1=b").unwrap();
+/// let overrides = file.child();
+/// overrides.set_local("server.host".to_string(), "example.com".to_string());
+///
+/// assert_eq!(overrides.get("server.port").as_deref(), Some("8080"));
+/// assert_eq!(overrides.get("server.tags.1").as_deref(), Some("b"));
+/// assert_eq!(overrides.get("server.host").as_deref(), Some("example.com"));
+/// ```
+impl PScope<String> {
+    fn load_pairs(&self, pairs: Vec<(String, String)>) -> usize {
+        let n = pairs.len();
+        for (k, v) in pairs {
+            self.set_local(k, v);
+        }
+        n
+    }
+
+    /// Sets each value in the TOML document src in this frame,
+    /// giving the number set. Nothing is set if src does not parse.
+    #[cfg(feature = "toml")]
+    pub fn load_toml(&self, src: &str) -> Result<usize, ConfigError> {
+        Ok(self.load_pairs(toml_pairs(src)?))
+    }
+
+    /// A new root holding the TOML file at path
+    #[cfg(feature = "toml")]
+    pub fn from_toml<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let s = PScope::new();
+        s.load_toml(&std::fs::read_to_string(path)?)?;
+        Ok(s)
+    }
+
+    /// Like load_toml, for a JSON document. Nulls are left unset.
+    #[cfg(feature = "json")]
+    pub fn load_json(&self, src: &str) -> Result<usize, ConfigError> {
+        Ok(self.load_pairs(json_pairs(src)?))
+    }
+
+    /// A new root holding the JSON file at path
+    #[cfg(feature = "json")]
+    pub fn from_json<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let s = PScope::new();
+        s.load_json(&std::fs::read_to_string(path)?)?;
+        Ok(s)
+    }
+
+    /// Like load_toml, for the `KEY=value` lines of a `.env` file, with
+    /// quotes, comments and `${VAR}` substitution as `dotenvy` reads them.
+    /// The process environment itself is left alone.
+    #[cfg(feature = "dotenvy")]
+    pub fn load_dotenv(&self, src: &str) -> Result<usize, ConfigError> {
+        Ok(self.load_pairs(dotenv_pairs(src)?))
+    }
+
+    /// A new root holding the `.env` file at path
+    #[cfg(feature = "dotenvy")]
+    pub fn from_dotenv<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let s = PScope::new();
+        s.load_dotenv(&std::fs::read_to_string(path)?)?;
+        Ok(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "toml")]
+    #[test]
+    fn toml_files_flatten_to_dotted_keys() {
+        let path = std::env::temp_dir().join(format!("scope_store_{}.toml", std::process::id()));
+        let src =
+            "name = \"demo\"\n[db]\nretries = 3\nratio = 0.5\n[[db.replicas]]\nhost = \"r1\"\n";
+        std::fs::write(&path, src).unwrap();
+        let root = PScope::from_toml(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            root.flatten().into_iter().collect::<Vec<_>>(),
+            vec![
+                ("db.ratio".to_string(), "0.5".to_string()),
+                ("db.replicas.0.host".to_string(), "r1".to_string()),
+                ("db.retries".to_string(), "3".to_string()),
+                ("name".to_string(), "demo".to_string()),
+            ]
+        );
+        assert!(matches!(root.load_toml("a = "), Err(ConfigError::Parse(_))));
+        assert!(matches!(PScope::from_toml(&path), Err(ConfigError::Io(_))));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_files_flatten_to_dotted_keys() {
+        let root = PScope::new();
+        let c = root.child();
+        let n = c
+            .load_json(r#"{"a": {"b": [true, null, "x"]}, "n": 1}"#)
+            .unwrap();
+        assert_eq!(n, 3);
+        assert_eq!(c.get("a.b.0").as_deref(), Some("true"));
+        assert_eq!(c.get("a.b.1"), None);
+        assert_eq!(c.get("a.b.2").as_deref(), Some("x"));
+        assert!(c
+            .load_json("[")
+            .unwrap_err()
+            .to_string()
+            .starts_with("could not parse"));
+        assert!(PScope::from_json("/no/such/file.json").is_err());
+    }
+
+    #[cfg(feature = "dotenvy")]
+    #[test]
+    fn dotenv_files_set_each_variable() {
+        let path = std::env::temp_dir().join(format!("scope_store_{}.env", std::process::id()));
+        let src = "# a comment\nHOST=localhost\nURL=\"http://${HOST}:80\"\nexport MODE='dev'\n";
+        std::fs::write(&path, src).unwrap();
+        let root = PScope::from_dotenv(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(root.local_keys(), vec!["HOST", "MODE", "URL"]);
+        assert_eq!(root.get("URL").as_deref(), Some("http://localhost:80"));
+        assert_eq!(root.get("MODE").as_deref(), Some("dev"));
+        assert!(std::env::var("SCOPE_STORE_UNSET_HOST").is_err());
+
+        assert!(matches!(
+            root.child().load_dotenv("BAD LINE"),
+            Err(ConfigError::Parse(_))
+        ));
+        assert!(matches!(
+            PScope::from_dotenv(&path),
+            Err(ConfigError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,101 @@
+use crate::{PScope, Scope, Sym};
+use std::collections::BTreeSet;
+
+impl<T> Scope<T> {
+    /// Notes that k changed here just now, if the tree tracks changes
+    pub(crate) fn stamp(&self, k: Sym) {
+        if let Some(c) = &self.tree().changes {
+            c.set(c.get() + 1);
+            self.attrs.borrow_mut().stamps.insert(k, c.get());
+        }
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::{PScope, ScopeOptions};
+/// let root = PScope::with_options(ScopeOptions {
+///     track_changes: true,
+///     ..Default::default()
+/// });
+/// root.set_local("title".to_string(), 1);
+/// root.set_local("body".to_string(), 2);
+/// let c = root.child();
+/// let seen = c.change_generation();
+///
+/// c.set("body".to_string(), 3);
+/// c.set_local("footer".to_string(), 4);
+/// assert_eq!(c.changed_keys_since(seen), vec!["body", "footer"]);
+/// assert!(c.changed_keys_since(c.change_generation()).is_empty());
+/// ```
+impl<T> PScope<T> {
+    /// The number of changes made anywhere in the tree, to give to
+    /// changed_keys_since later. Always 0 unless the tree was made with
+    /// `track_changes`. A fork counts on from where its tree was.
+    pub fn change_generation(&self) -> u64 {
+        self.p.tree().changes.as_ref().map_or(0, |c| c.get())
+    }
+
+    /// The names set, changed or removed in this frame or any parent since
+    /// change_generation returned gen, sorted. A name is listed even if the
+    /// change was to a binding shadowed here. A frame reset drops its record
+    /// of removals made before the reset, but lists everything it emptied.
+    /// Empty unless the tree was made with `track_changes`.
+    pub fn changed_keys_since(&self, gen: u64) -> Vec<String> {
+        let keys: BTreeSet<Sym> = (self.p.frames())
+            .flat_map(|f| {
+                let a = f.attrs.borrow();
+                let ks: Vec<Sym> = (a.stamps.iter())
+                    .filter(|(_, n)| **n > gen)
+                    .map(|(k, _)| *k)
+                    .collect();
+                ks
+            })
+            .collect();
+        let mut res: Vec<String> = keys
+            .into_iter()
+            .map(|k| self.p.name(k).to_string())
+            .collect();
+        res.sort();
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{PScope, ScopeOptions};
+    #[test]
+    fn changes_are_stamped_per_key() {
+        let untracked = PScope::new();
+        untracked.set_local("a".to_string(), 1);
+        assert_eq!(untracked.change_generation(), 0);
+        assert!(untracked.changed_keys_since(0).is_empty());
+
+        let root = PScope::with_options(ScopeOptions {
+            track_changes: true,
+            ..Default::default()
+        });
+        assert!(root.options().track_changes);
+        root.set_local("a".to_string(), 1);
+        root.set_local("b".to_string(), 2);
+        let c = root.child();
+        c.set_local("a".to_string(), 10);
+        let g = root.change_generation();
+        assert_eq!(g, 3);
+
+        root.update("b", |n| *n += 1);
+        root.remove_local("a");
+        assert_eq!(c.changed_keys_since(g), vec!["a", "b"]);
+        assert_eq!(root.child().changed_keys_since(0), vec!["a", "b"]);
+
+        let g = root.change_generation();
+        c.reset_local();
+        assert_eq!(c.changed_keys_since(g), vec!["a"]);
+        assert!(root.changed_keys_since(g).is_empty());
+
+        let f = c.fork();
+        f.set_local("x".to_string(), 0);
+        assert_eq!(f.change_generation(), root.change_generation() + 1);
+        assert_eq!(f.changed_keys_since(g), vec!["a", "x"]);
+    }
+}
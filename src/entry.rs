@@ -0,0 +1,141 @@
+use crate::{PScope, Scope, Sym};
+use std::cell::RefMut;
+
+/// A view of a key as seen from one frame, see `PScope::entry`
+pub struct ScopeEntry<'a, T> {
+    local: &'a Scope<T>,
+    id: String,
+    /// None if the tree has never seen id
+    sym: Option<Sym>,
+    /// The frame holding a value for id, a frozen or hidden one counts as vacant
+    owner: Option<&'a Scope<T>>,
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// root.set_local("a".to_string(), 1);
+/// let c = root.child();
+///
+/// c.entry("a").and_modify(|n| *n += 1).or_insert(0);
+/// c.entry("b").and_modify(|n| *n += 1).or_insert(0);
+/// assert_eq!(root.get("a"), Some(2));
+/// assert_eq!(c.get("b"), Some(0));
+/// assert_eq!(root.get("b"), None);
+///
+/// *c.entry("b").or_insert(10).unwrap() += 5;
+/// assert_eq!(c.get("b"), Some(5));
+///
+/// c.freeze();
+/// assert!(c.entry("d").or_insert(0).is_none());
+/// ```
+impl<T> PScope<T> {
+    pub fn entry(&self, id: &str) -> ScopeEntry<'_, T> {
+        let sym = self.p.sym(id);
+        let owner = sym.filter(|&s| self.p.allow_get(s)).and_then(|s| {
+            let o = self.p.owner(s)?;
+            match !o.frozen.get() && o.data().contains_key(s) {
+                true => Some(o),
+                false => None,
+            }
+        });
+        ScopeEntry {
+            local: &self.p,
+            id: id.to_string(),
+            sym,
+            owner,
+        }
+    }
+}
+
+impl<'a, T> ScopeEntry<'a, T> {
+    pub fn key(&self) -> &str {
+        &self.id
+    }
+
+    pub fn is_occupied(&self) -> bool {
+        self.owner.is_some()
+    }
+
+    /// Changes the value where it is defined, if it is defined.
+    /// The change is undone if an `on_set` hook refuses it.
+    pub fn and_modify<F: FnOnce(&mut T)>(self, f: F) -> Self {
+        if let (Some(o), Some(s)) = (self.owner, self.sym) {
+            let old = o.keep_for_check(self.local, s);
+            if let Some(v) = o.data_mut().get_mut(s) {
+                o.undo_value(s, Some(v));
+                f(v);
+            }
+            if o.check_change(self.local, s, old).is_ok() {
+                o.updated(s);
+            }
+        }
+        self
+    }
+
+    pub fn or_insert(self, default: T) -> Option<RefMut<'a, T>> {
+        self.or_insert_with(|| default)
+    }
+
+    /// Borrows the visible value, first creating it in the local frame if needed.
+    /// Changes made through the returned guard are not sent to subscribers.
+    /// None if the value is missing and can't be stored, as when the local
+    /// frame is frozen, or an `on_set` hook or validator refuses it.
+    pub fn or_insert_with<F: FnOnce() -> T>(self, f: F) -> Option<RefMut<'a, T>> {
+        let (frame, s) = match (self.owner, self.sym) {
+            (Some(o), Some(s)) => (o, s),
+            _ => {
+                let s = self.local.intern(&self.id);
+                self.local.set_local(s, f());
+                (self.local, s)
+            }
+        };
+        RefMut::filter_map(frame.data_mut(), |d| d.get_mut(s)).ok()
+    }
+}
+
+impl<'a, T: Default> ScopeEntry<'a, T> {
+    pub fn or_default(self) -> Option<RefMut<'a, T>> {
+        self.or_insert_with(T::default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::ControlFlow;
+    #[test]
+    fn frozen_owner_is_vacant() {
+        let root = PScope::new();
+        root.set_local("a".to_string(), 1);
+        root.freeze();
+        let c = root.child();
+
+        assert!(!c.entry("a").is_occupied());
+        *c.entry("a").and_modify(|n| *n += 1).or_default().unwrap() += 4;
+        assert_eq!(c.get("a"), Some(4));
+        assert_eq!(root.get("a"), Some(1));
+        assert!(c.entry("a").is_occupied());
+    }
+
+    #[test]
+    fn refused_inserts_give_none() {
+        let root = PScope::new();
+        root.on_set(|k, _: &mut i32| match k {
+            "no" => ControlFlow::Break(()),
+            _ => ControlFlow::Continue(()),
+        });
+        let c = root.child();
+        assert!(c.entry("no").or_insert(1).is_none());
+        c.set_validator("small", |n| match *n < 10 {
+            true => Ok(()),
+            false => Err("too big".to_string()),
+        });
+        assert!(c.entry("small").or_insert(10).is_none());
+        assert_eq!(c.entry("small").or_insert(5).as_deref(), Some(&5));
+        c.freeze();
+        assert!(c.entry("new").or_default().is_none());
+        assert!(!c.contains("no") && !c.contains("new"));
+    }
+}
@@ -0,0 +1,208 @@
+use crate::{PScope, Scope, Sym};
+use std::fmt::Display;
+use std::rc::Rc;
+
+/// Where a tree looks for names that no frame defines, see `PScope::env_fallback`
+pub(crate) struct EnvFallback<T> {
+    prefix: String,
+    convert: Rc<dyn Fn(String) -> Option<T>>,
+}
+
+impl<T> Clone for EnvFallback<T> {
+    fn clone(&self) -> Self {
+        EnvFallback {
+            prefix: self.prefix.clone(),
+            convert: self.convert.clone(),
+        }
+    }
+}
+
+impl<T> Scope<T> {
+    /// Whether the tree falls back to the environment, and it has a variable for k
+    pub(crate) fn env_has(&self, k: &str) -> bool {
+        match &*self.hooks().env.borrow() {
+            Some(fb) => std::env::var_os(format!("{}{}", fb.prefix, k)).is_some(),
+            None => false,
+        }
+    }
+
+    /// Copies the variable for k from the OS environment into the root, as
+    /// set_local would, giving back the root, if the tree falls back to the
+    /// environment and the variable is set, converts, and is let through
+    pub(crate) fn copy_from_env(&self, k: Sym) -> Option<&Scope<T>> {
+        let fb = self.hooks().env.borrow().clone()?;
+        let root = self.root_frame();
+        if root.frozen.get() {
+            return None;
+        }
+        let var = std::env::var(format!("{}{}", fb.prefix, self.name(k))).ok()?;
+        let mut v = (fb.convert)(var)?;
+        if !root.allow_set(k, &mut v) || root.data.try_borrow_mut().is_err() {
+            return None;
+        }
+        root.store_local(k, v);
+        Some(root)
+    }
+}
+
+/// Exported bindings are the ones a shell would pass to a child process.
+/// The flag belongs to the binding, so a plain binding shadowing it is not exported.
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// root.set_exported("PATH".to_string(), "/bin");
+/// root.set_local("secret".to_string(), "x");
+/// let c = root.child();
+/// c.set_exported("HOME".to_string(), "/root");
+///
+/// assert_eq!(
+///     c.as_env_vars(),
+///     vec![
+///         ("HOME".to_string(), "/root".to_string()),
+///         ("PATH".to_string(), "/bin".to_string())
+///     ]
+/// );
+/// ```
+impl<T> PScope<T> {
+    /// Like set_local, also marking id as exported
+    pub fn set_exported(&self, id: String, val: T) {
+        if self.is_frozen() {
+            return;
+        }
+        let s = self.p.intern(&id);
+        self.p.attrs.borrow_mut().env.insert(s);
+        self.p.set_local(s, val);
+    }
+
+    /// Whether the visible binding for id is exported
+    pub fn is_exported(&self, id: &str) -> bool {
+        let s = match self.p.sym(id) {
+            Some(s) => s,
+            None => return false,
+        };
+        match self.p.owner(s) {
+            Some(o) => o.attrs.borrow().env.contains(&s),
+            None => false,
+        }
+    }
+
+    /// Every visible exported binding, converted by f and sorted by name
+    pub fn env_vars_with<F: Fn(&T) -> String>(&self, f: F) -> Vec<(String, String)> {
+        let mut res: Vec<(String, String)> = (self.p.visible_keys().into_iter())
+            .filter_map(|k| {
+                let o = self.p.owner(k)?;
+                if !o.attrs.borrow().env.contains(&k) {
+                    return None;
+                }
+                let v = o.data().get(k).map(&f)?;
+                Some((self.p.name(k).to_string(), v))
+            })
+            .collect();
+        res.sort();
+        res
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// std::env::set_var("MYAPP_PORT", "8080");
+/// let root = PScope::new();
+/// root.env_fallback("MYAPP_", |v| v.parse::<u16>().ok());
+/// let c = root.child();
+/// c.set_local("port".to_string(), 1);
+///
+/// assert_eq!(c.get("PORT"), Some(8080));
+/// assert_eq!(c.get("port"), Some(1));
+/// assert_eq!(c.get("MISSING"), None);
+/// assert!(root.contains_local("PORT"));
+/// ```
+impl<T> PScope<T> {
+    /// Makes the OS environment the outermost scope: a name no frame defines is
+    /// looked up as the variable prefix followed by the name, and converted by f,
+    /// which may refuse it. A variable found is copied into the root the first
+    /// time it is looked up, as by `set_local`, so hooks, validators and
+    /// subscribers see it, later changes to the environment are not seen,
+    /// and it can be changed like any other binding there. Lists of bindings,
+    /// like `visible`, only hold the variables copied so far. Replaces any
+    /// fallback the tree had, and forks keep it.
+    pub fn env_fallback<F: Fn(String) -> Option<T> + 'static>(&self, prefix: &str, f: F) {
+        *self.p.hooks().env.borrow_mut() = Some(EnvFallback {
+            prefix: prefix.to_string(),
+            convert: Rc::new(f),
+        });
+    }
+
+    /// Stops looking in the environment. Variables already copied stay in the root.
+    pub fn clear_env_fallback(&self) {
+        *self.p.hooks().env.borrow_mut() = None;
+    }
+}
+
+impl<T: Display> PScope<T> {
+    pub fn as_env_vars(&self) -> Vec<(String, String)> {
+        self.env_vars_with(T::to_string)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn shadowing_hides_export() {
+        let root = PScope::new();
+        root.set_exported("A".to_string(), 1);
+        root.set_exported("B".to_string(), 2);
+        let c = root.child();
+        c.set_local("A".to_string(), 3);
+        assert!(!c.is_exported("A"));
+        assert!(c.is_exported("B"));
+        assert_eq!(
+            c.env_vars_with(|n| format!("{}!", n)),
+            vec![("B".to_string(), "2!".to_string())]
+        );
+
+        root.remove("B");
+        root.set_local("B".to_string(), 4);
+        assert!(c.as_env_vars().is_empty());
+    }
+
+    // Reads the variables cargo sets for every test run, as changing the
+    // environment while other tests run is not safe
+    #[test]
+    fn environment_is_the_outermost_scope() {
+        let name = env!("CARGO_PKG_NAME").to_string();
+        let root = PScope::new();
+        root.env_fallback("CARGO_PKG_", |v| (!v.contains('.')).then_some(v));
+        let seen = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let s2 = seen.clone();
+        root.subscribe(move |k, _| s2.borrow_mut().push(k.to_string()));
+        let c = root.child();
+        c.mask("NAME");
+        assert_eq!(c.get("NAME"), None);
+        assert_eq!(root.get("NAME"), Some(name.clone()));
+        assert_eq!(*seen.borrow(), vec!["NAME"]);
+        // refused by the conversion, and a miss that is never interned
+        assert_eq!(c.get("VERSION"), None);
+        assert_eq!(c.get("NO_SUCH_FIELD"), None);
+        assert!(root.p.sym("NO_SUCH_FIELD").is_none());
+        assert_eq!(root.fork().child().get("NAME"), Some(name.clone()));
+
+        root.update("NAME", |n| n.push('!'));
+        assert_eq!(root.get("NAME"), Some(format!("{}!", name)));
+        root.remove_local("NAME");
+        assert_eq!(root.get("NAME"), Some(name.clone()));
+
+        root.remove_local("NAME");
+        root.on_set(|k, _| match k {
+            "NAME" => std::ops::ControlFlow::Break(()),
+            _ => std::ops::ControlFlow::Continue(()),
+        });
+        assert_eq!(c.child().get("NAME"), None);
+        assert!(!root.contains_local("NAME"));
+
+        root.clear_env_fallback();
+        assert_eq!(c.child().get("NAME"), None);
+    }
+}
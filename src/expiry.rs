@@ -0,0 +1,152 @@
+use crate::{PScope, Scope, Sym};
+use std::time::{Duration, Instant};
+
+impl<T> Scope<T> {
+    /// Whether k has outlived the time it was given here
+    fn expired(&self, k: Sym, now: Instant) -> bool {
+        matches!(self.attrs.borrow().expires.get(&k), Some(t) if *t <= now)
+    }
+
+    /// Removes k from each frame on the chain where it has expired, unless
+    /// that frame is borrowed or frozen, which keep it until next time
+    pub(crate) fn purge_key(&self, k: Sym) {
+        if !self.tree().expiring.get() {
+            return;
+        }
+        let now = Instant::now();
+        for f in self.frames() {
+            f.purge_at(k, now);
+        }
+    }
+
+    /// Like purge_key, for this frame alone
+    pub(crate) fn purge_here(&self, k: Sym) {
+        if self.tree().expiring.get() {
+            self.purge_at(k, Instant::now());
+        }
+    }
+
+    fn purge_at(&self, k: Sym, now: Instant) -> bool {
+        self.expired(k, now) && self.data.try_borrow_mut().is_ok() && self.remove_local(k).is_some()
+    }
+
+    /// Like purge_key, for every key in this frame, giving the number removed
+    pub(crate) fn purge_local(&self) -> usize {
+        if !self.tree().expiring.get() {
+            return 0;
+        }
+        let now = Instant::now();
+        let gone: Vec<Sym> = (self.attrs.borrow().expires.iter())
+            .filter(|(_, t)| **t <= now)
+            .map(|(k, _)| *k)
+            .collect();
+        gone.into_iter().filter(|&k| self.purge_at(k, now)).count()
+    }
+
+    /// Like purge_local, for every frame on the chain
+    pub(crate) fn purge_chain(&self) -> usize {
+        self.frames().map(|f| f.purge_local()).sum()
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// use std::time::Duration;
+/// let root = PScope::new();
+/// root.set_local("user".to_string(), 1);
+/// let req = root.child();
+/// req.set_local_ttl("user".to_string(), 2, Duration::from_secs(60));
+/// req.set_local_ttl("token".to_string(), 3, Duration::ZERO);
+///
+/// assert_eq!(req.get("user"), Some(2));
+/// assert_eq!(req.get("token"), None);
+/// assert_eq!(req.purge_expired(), 0);
+/// ```
+impl<T> PScope<T> {
+    /// Sets id in this frame until ttl has passed, after which it acts as if
+    /// it were never set here, so any outer binding shows through again.
+    /// The expiry holds however the value changes, until id is removed or
+    /// given a new one by this. Clocks only move on lookups: an expired
+    /// binding is removed, as by `remove_local`, when it is next looked up,
+    /// by name, Sym, slot or global ref, when its frame's keys are listed,
+    /// or when `visible`, `flatten` or `purge_expired` are called.
+    pub fn set_local_ttl(&self, id: String, val: T, ttl: Duration) {
+        let k = self.p.intern(&id);
+        self.p.set_local(k, val);
+        if self.p.data().contains_key(k) {
+            self.p.tree().expiring.set(true);
+            let at = Instant::now() + ttl;
+            self.p.attrs.borrow_mut().expires.insert(k, at);
+        }
+    }
+
+    /// The time left before id expires in this frame,
+    /// None if it is not set here, never expires or has already expired
+    pub fn ttl(&self, id: &str) -> Option<Duration> {
+        let k = self.p.sym(id)?;
+        let at = *self.p.attrs.borrow().expires.get(&k)?;
+        at.checked_duration_since(Instant::now())
+            .filter(|d| !d.is_zero())
+    }
+
+    /// Removes every expired binding from this frame and its parents,
+    /// giving the number removed. Frames borrowed by a guard are skipped.
+    pub fn purge_expired(&self) -> usize {
+        self.p.purge_chain()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn expired_bindings_act_absent() {
+        let root = PScope::new();
+        root.set_local("a".to_string(), 1);
+        let c = root.child();
+        c.set_local_ttl("a".to_string(), 10, Duration::ZERO);
+        c.set_local_ttl("b".to_string(), 20, Duration::ZERO);
+        c.set_local_ttl("c".to_string(), 30, Duration::from_secs(3600));
+        assert!(c.ttl("c").unwrap() > Duration::from_secs(3000));
+        assert_eq!(root.ttl("a"), None);
+
+        // a lookup purges it, so the root shows through
+        assert_eq!(c.get("a"), Some(1));
+        assert_eq!(c.ttl("a"), None);
+        assert_eq!(c.ttl("b"), None);
+        assert_eq!(c.local_keys(), vec!["c"]);
+        assert_eq!(c.purge_expired(), 0);
+        assert_eq!(c.flatten().len(), 2);
+
+        // every way of reading by name or listing the frame sees it gone
+        let reads: [fn(&PScope<i32>) -> bool; 8] = [
+            |c| c.get_many(&["t"]) == vec![None],
+            |c| !c.contains_local("t"),
+            |c| c.local_keys() == vec!["c"],
+            |c| c.local_len() == 1,
+            |c| c.with_global("t", |_| ()).is_none(),
+            |c| c.with_fn_local("t", |_| ()).is_none(),
+            |c| c.try_get("t") == Ok(None),
+            |c| c.locate("t").is_none(),
+        ];
+        for read in reads.iter() {
+            root.set_local_ttl("t".to_string(), 5, Duration::ZERO);
+            c.set_local_ttl("t".to_string(), 5, Duration::ZERO);
+            assert!(read(&c));
+        }
+        let t = root.intern("t");
+        root.set_local_ttl("t".to_string(), 5, Duration::ZERO);
+        assert_eq!(c.get_at(1, t), None);
+        root.set_local_ttl("t".to_string(), 5, Duration::ZERO);
+        assert!(!c.global_ref("t").is_set());
+
+        c.set_local_ttl("d".to_string(), 40, Duration::ZERO);
+        c.update("c", |n| *n += 1);
+        assert_eq!(c.flatten().get("d"), None);
+        assert_eq!(c.get("c"), Some(31));
+        c.remove_local("c");
+        c.set_local("c".to_string(), 0);
+        assert_eq!(c.ttl("c"), None);
+    }
+}
@@ -0,0 +1,241 @@
+use crate::config::{file_pairs, ConfigError};
+use crate::PScope;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::{Duration, Instant};
+
+/// A frame holding a config file, kept up to date with it, see
+/// `PScope::watch_config`. Nothing reloads by itself: a scope can't leave
+/// its thread, so the watcher's thread only queues what it sees, and the
+/// frame changes only when the thread that owns it polls, by calling
+/// `reload_if_changed` from its event loop, or `wait_for_change` from one
+/// of its own. A reload sets only the keys whose values changed and removes
+/// those gone from the file, so subscribers hear of just those.
+/// Watching stops when this is dropped.
+pub struct WatchedFile {
+    scope: PScope<String>,
+    path: PathBuf,
+    rx: Receiver<notify::Result<notify::Event>>,
+    _watcher: RecommendedWatcher,
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// # #[cfg(feature = "toml")]
+/// # let (name, src, edit) = ("app.toml", "level = \"info\"", "level = \"debug\"");
+/// # #[cfg(all(feature = "json", not(feature = "toml")))]
+/// # let (name, src, edit) = ("app.json", r#"{"level": "info"}"#, r#"{"level": "debug"}"#);
+/// # #[cfg(not(any(feature = "toml", feature = "json")))]
+/// # let (name, src, edit) = ("app.env", "level=info", "level=debug");
+/// let dir = std::env::temp_dir().join(format!("scope_store_doc_{}", std::process::id()));
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let path = dir.join(name);
+/// std::fs::write(&path, src).unwrap();
+///
+/// let defaults = PScope::new();
+/// let file = defaults.watch_config(&path).unwrap();
+/// let cli = file.scope().child();
+/// assert_eq!(cli.get("level").as_deref(), Some("info"));
+///
+/// std::fs::write(&path, edit).unwrap();
+/// file.reload().unwrap();
+/// assert_eq!(cli.get("level").as_deref(), Some("debug"));
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+impl WatchedFile {
+    /// The frame holding the file, a child of the one it was watched from
+    pub fn scope(&self) -> &PScope<String> {
+        &self.scope
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the file again now, giving the number of keys set or removed.
+    /// Nothing changes if it can't be read or does not parse.
+    pub fn reload(&self) -> Result<usize, ConfigError> {
+        let src = std::fs::read_to_string(&self.path)?;
+        let pairs: BTreeMap<String, String> = file_pairs(&self.path, &src)?.into_iter().collect();
+        let s = &self.scope;
+        let gone: Vec<String> = (s.local_keys().into_iter())
+            .filter(|k| !pairs.contains_key(k))
+            .collect();
+        let mut n = gone.len();
+        for k in gone {
+            s.remove_local(&k);
+        }
+        for (k, v) in pairs {
+            let same = (s.p.sym(&k)).is_some_and(|k| s.p.data().get(k) == Some(&v));
+            if !same {
+                s.set_local(k, v);
+                n += 1;
+            }
+        }
+        Ok(n)
+    }
+
+    /// Whether an event is about the file, other than it being read
+    fn touches(&self, e: &notify::Event) -> bool {
+        !matches!(e.kind, notify::EventKind::Access(_))
+            && (e.paths.iter()).any(|p| p.file_name() == self.path.file_name())
+    }
+
+    /// Takes every queued event, then reloads once if any of them, or changed,
+    /// was about the file. A watch error, err or the first queued, is given
+    /// after that reload, so the events that came with it are not lost.
+    fn finish(
+        &self,
+        mut changed: bool,
+        mut err: Option<notify::Error>,
+    ) -> Result<Option<usize>, ConfigError> {
+        while let Ok(e) = self.rx.try_recv() {
+            match e {
+                Ok(e) => changed |= self.touches(&e),
+                Err(e) => err = err.or(Some(e)),
+            }
+        }
+        let n = match changed {
+            true => Some(self.reload()?),
+            false => None,
+        };
+        match err {
+            Some(e) => Err(ConfigError::Watch(e)),
+            None => Ok(n),
+        }
+    }
+
+    /// Reloads if the file has changed since last asked, without waiting.
+    /// None if it has not.
+    pub fn reload_if_changed(&self) -> Result<Option<usize>, ConfigError> {
+        self.finish(false, None)
+    }
+
+    /// Waits up to timeout for the file to change, or the watcher to fail,
+    /// then reloads it once for that and any other changes already queued.
+    /// None if it did not change.
+    pub fn wait_for_change(&self, timeout: Duration) -> Result<Option<usize>, ConfigError> {
+        let until = Instant::now() + timeout;
+        loop {
+            let left = until.saturating_duration_since(Instant::now());
+            match self.rx.recv_timeout(left) {
+                Ok(Ok(e)) if self.touches(&e) => return self.finish(true, None),
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => return self.finish(false, Some(e)),
+                Err(_) => return Ok(None),
+            }
+        }
+    }
+}
+
+impl PScope<String> {
+    /// A child holding the config file at path, which can be reloaded whenever
+    /// the file changes. It is read by its extension, as `.toml` or `.json`,
+    /// or as a `.env` file if named like one, each with its own feature.
+    /// The file is watched before it is first read, so no change is missed.
+    pub fn watch_config<P: AsRef<Path>>(&self, path: P) -> Result<WatchedFile, ConfigError> {
+        let path = path.as_ref().to_path_buf();
+        let (tx, rx) = channel();
+        let mut watcher = notify::recommended_watcher(tx).map_err(ConfigError::Watch)?;
+        // the directory, so a file replaced by renaming over it is still seen
+        let dir = match path.parent() {
+            Some(d) if d != Path::new("") => d,
+            _ => Path::new("."),
+        };
+        (watcher.watch(dir, RecursiveMode::NonRecursive)).map_err(ConfigError::Watch)?;
+        let w = WatchedFile {
+            scope: self.child_named(&path.display().to_string()),
+            path,
+            rx,
+            _watcher: watcher,
+        };
+        w.reload()?;
+        Ok(w)
+    }
+}
+
+#[cfg(all(test, feature = "dotenvy"))]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    #[test]
+    fn changes_to_the_file_are_reloaded() {
+        let dir = std::env::temp_dir().join(format!("scope_store_watch_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(".env");
+        std::fs::write(&path, "A=1\nB=2\nC=3\n").unwrap();
+
+        let root = PScope::new();
+        let w = root.watch_config(&path).unwrap();
+        assert_eq!(w.scope().local_keys(), vec!["A", "B", "C"]);
+        assert_eq!(w.scope().name(), Some(&*path.display().to_string()));
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let s2 = seen.clone();
+        w.scope()
+            .subscribe(move |k, _| s2.borrow_mut().push(k.to_string()));
+
+        // drop whatever the first read queued
+        while w
+            .wait_for_change(Duration::from_millis(200))
+            .unwrap()
+            .is_some()
+        {}
+        seen.borrow_mut().clear();
+
+        // as editors save, so the watcher never sees a half written file
+        let tmp = dir.join("next");
+        std::fs::write(&tmp, "A=1\nB=20\nD=4\n").unwrap();
+        std::fs::rename(&tmp, &path).unwrap();
+        let mut n = None;
+        for _ in 0..50 {
+            n = w.wait_for_change(Duration::from_millis(100)).unwrap();
+            if w.scope().get("D").is_some() {
+                break;
+            }
+        }
+        assert!(n.is_some());
+        assert_eq!(w.scope().local_keys(), vec!["A", "B", "D"]);
+        assert_eq!(w.scope().get("B").as_deref(), Some("20"));
+        let mut seen = seen.borrow().clone();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen, vec!["B", "C", "D"]);
+
+        std::fs::write(&path, "BAD LINE").unwrap();
+        assert!(matches!(w.reload(), Err(ConfigError::Parse(_))));
+        assert_eq!(w.scope().get("A").as_deref(), Some("1"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn errors_wait_for_the_rest_of_the_queue() {
+        let dir = std::env::temp_dir().join(format!("scope_store_queue_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(".env");
+        std::fs::write(&path, "A=1\n").unwrap();
+
+        // fed by hand, so the order of events is known
+        let (tx, rx) = channel();
+        let w = WatchedFile {
+            scope: PScope::new(),
+            path: path.clone(),
+            rx,
+            _watcher: notify::recommended_watcher(|_| {}).unwrap(),
+        };
+        w.reload().unwrap();
+        std::fs::write(&path, "A=2\n").unwrap();
+        let kind = notify::EventKind::Modify(notify::event::ModifyKind::Any);
+        tx.send(Err(notify::Error::generic("lost"))).unwrap();
+        tx.send(Ok(notify::Event::new(kind).add_path(path.clone())))
+            .unwrap();
+
+        assert!(matches!(w.reload_if_changed(), Err(ConfigError::Watch(_))));
+        assert_eq!(w.scope().get("A").as_deref(), Some("2"));
+        assert_eq!(w.reload_if_changed().unwrap(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
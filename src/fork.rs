@@ -0,0 +1,288 @@
+use crate::store::Store;
+use crate::{BorrowError, PScope, Scope};
+use std::cell::{Ref, RefCell, RefMut};
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+/// A frame's bindings, which a fork shares with the frame it came from
+/// until either is written to
+pub(crate) enum Bindings<T> {
+    Own(Store<T>),
+    /// With Store::clone, kept here so frames without T: Clone can still copy it
+    Shared(Rc<Store<T>>, fn(&Store<T>) -> Store<T>),
+}
+
+impl<T> From<Store<T>> for Bindings<T> {
+    fn from(d: Store<T>) -> Self {
+        Bindings::Own(d)
+    }
+}
+
+impl<T> Deref for Bindings<T> {
+    type Target = Store<T>;
+    fn deref(&self) -> &Store<T> {
+        match self {
+            Bindings::Own(d) => d,
+            Bindings::Shared(d, _) => d,
+        }
+    }
+}
+
+/// Copies the bindings first if anything else still shares them
+impl<T> DerefMut for Bindings<T> {
+    fn deref_mut(&mut self) -> &mut Store<T> {
+        if let Bindings::Shared(d, copy) = self {
+            let own = match Rc::get_mut(d) {
+                Some(d) => std::mem::replace(d, Store::new(d.kind())),
+                None => copy(d),
+            };
+            *self = Bindings::Own(own);
+        }
+        match self {
+            Bindings::Own(d) => d,
+            Bindings::Shared(..) => unreachable!("just copied"),
+        }
+    }
+}
+
+impl<T: Clone> Bindings<T> {
+    /// Bindings for a fork, sharing these from now on
+    fn share(&mut self) -> Self {
+        if let Bindings::Own(d) = self {
+            let d = std::mem::replace(d, Store::new(d.kind()));
+            *self = Bindings::Shared(Rc::new(d), Store::clone);
+        }
+        match self {
+            Bindings::Shared(d, copy) => Bindings::Shared(d.clone(), *copy),
+            Bindings::Own(_) => unreachable!("just shared"),
+        }
+    }
+}
+
+impl<T> Scope<T> {
+    pub(crate) fn data(&self) -> Ref<'_, Store<T>> {
+        Ref::map(self.data.borrow(), |d| &**d)
+    }
+
+    /// The bindings of this frame, copying them first if they are still shared
+    pub(crate) fn data_mut(&self) -> RefMut<'_, Store<T>> {
+        let d = (self.data.try_borrow_mut())
+            .expect("a frame was changed while a ScopeRef or other borrow of it was held");
+        self.bump();
+        RefMut::map(d, |d| &mut **d)
+    }
+
+    /// Like data, failing instead of panicking if the bindings are mutably borrowed
+    pub(crate) fn try_data(&self) -> Result<Ref<'_, Store<T>>, BorrowError> {
+        let d = self.data.try_borrow().map_err(|_| BorrowError)?;
+        Ok(Ref::map(d, |d| &**d))
+    }
+
+    /// Like data_mut, failing instead of panicking if the bindings are borrowed
+    pub(crate) fn try_data_mut(&self) -> Result<RefMut<'_, Store<T>>, BorrowError> {
+        let d = self.data.try_borrow_mut().map_err(|_| BorrowError)?;
+        self.bump();
+        Ok(RefMut::map(d, |d| &mut **d))
+    }
+}
+
+/// The forks made so far by one call to fork, by the frame each came from,
+/// so frames reached more than once, through modules or imports, are forked once
+struct Forker<T> {
+    done: Vec<(*const Scope<T>, PScope<T>)>,
+    /// Forks whose modules and imports are still to be forked
+    todo: Vec<(PScope<T>, PScope<T>)>,
+}
+
+impl<T: Clone> Forker<T> {
+    fn done(&self, s: &PScope<T>) -> Option<PScope<T>> {
+        let p = Rc::as_ptr(&s.p);
+        (self.done.iter()).find_map(|(f, c)| (*f == p).then(|| c.clone()))
+    }
+
+    /// The fork of s and its parents, leaving their modules and imports for later
+    fn chain(&mut self, s: &PScope<T>) -> PScope<T> {
+        let mut chain = Vec::new();
+        let mut top = None;
+        let mut at = Some(s);
+        while let Some(f) = at {
+            if let Some(c) = self.done(f) {
+                top = Some(c);
+                break;
+            }
+            chain.push(f);
+            at = f.p.parent.as_ref();
+        }
+        for f in chain.into_iter().rev() {
+            let s = match &top {
+                Some(p) => p.child_frame(),
+                None => new_root(f),
+            };
+            let c = fork_frame(f, s);
+            self.done.push((Rc::as_ptr(&f.p), c.clone()));
+            self.todo.push((f.clone(), c.clone()));
+            top = Some(c);
+        }
+        top.expect("a chain has a root")
+    }
+
+    /// Points each fork's modules and imports at forks of their own
+    fn finish(&mut self) {
+        while let Some((f, c)) = self.todo.pop() {
+            let x = match f.p.extras() {
+                Some(x) => x,
+                None => continue,
+            };
+            let modules = x.modules.borrow().clone();
+            let modules = (modules.into_iter())
+                .map(|(n, m)| (n, self.chain(&m)))
+                .collect();
+            let im = x.imports.borrow().clone();
+            let im = im.map(|s| self.chain(s));
+            let cx = c.p.extras_init();
+            *cx.modules.borrow_mut() = modules;
+            *cx.imports.borrow_mut() = im;
+        }
+    }
+}
+
+/// An empty root for the fork of root, on the same names
+fn new_root<T>(root: &PScope<T>) -> Scope<T> {
+    let mut s = Scope::new(root.options());
+    let (t, rt) = (s.tree.as_mut().expect("a new root"), root.p.tree());
+    t.names = rt.names.clone();
+    t.generation = rt.generation.clone();
+    t.changes = rt.changes.clone();
+    t.imports_all.set(rt.imports_all.get());
+    t.imported.set(rt.imported.get());
+    t.expiring.set(rt.expiring.get());
+    t.validated.set(rt.validated.get());
+    s.hooks = root.p.hooks.clone();
+    s
+}
+
+/// Fills s in as a fork of from, but for its modules and imports
+fn fork_frame<T: Clone>(from: &PScope<T>, mut s: Scope<T>) -> PScope<T> {
+    let f = &from.p;
+    s.data = RefCell::new(match f.data.try_borrow_mut() {
+        Ok(mut d) => d.share(),
+        // held by a guard, so it can't start sharing, and is copied now
+        Err(_) => f.data().clone().into(),
+    });
+    s.attrs = RefCell::new(f.attrs.borrow().clone());
+    s.frozen.set(f.frozen.get());
+    s.boundary = f.boundary;
+    if let Some(x) = f.extras() {
+        let sx = s.extras_mut();
+        sx.name = x.name.clone();
+        sx.lru = x.lru.clone();
+        sx.positionals = RefCell::new(x.positionals.borrow().clone());
+        sx.valid = RefCell::new(x.valid.borrow().clone());
+    }
+    PScope { p: Rc::new(s) }
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// root.set_local("x".to_string(), 1);
+/// let c = root.child();
+/// c.set_local("y".to_string(), 2);
+///
+/// let f = c.fork();
+/// f.set("x".to_string(), 10);
+/// f.set_local("z".to_string(), 3);
+/// assert_eq!(f.get("x"), Some(10));
+/// assert_eq!(f.get("y"), Some(2));
+///
+/// assert_eq!(c.get("x"), Some(1));
+/// assert_eq!(c.get("z"), None);
+/// ```
+impl<T: Clone> PScope<T> {
+    /// A copy of this chain, from here to the root, whose changes never reach
+    /// the original, and which never sees the original's later changes.
+    /// Each frame shares its bindings with the one it came from until either
+    /// is written to, and only then are they copied, so frames that are only
+    /// read are never copied. Modules and imported scopes are forked with it,
+    /// each only once however often it is reached.
+    ///
+    /// Subscribers are left behind, and the dynamic callers of frames
+    /// made by child_dynamic are not followed.
+    pub fn fork(&self) -> Self {
+        let mut f = Forker {
+            done: Vec::new(),
+            todo: Vec::new(),
+        };
+        let res = f.chain(self);
+        f.finish();
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn forks_copy_on_write() {
+        let root = PScope::new();
+        root.set_local("g".to_string(), 0);
+        let a = root.child_named("a");
+        a.set_local("a".to_string(), 1);
+        let b = a.child();
+        b.set_const("k".to_string(), 5).unwrap();
+
+        let f = b.fork();
+        assert_eq!(f.depth(), 2);
+        assert_eq!(f.trace(), vec!["<root>", "a", "<scope>"]);
+        let copied = |s: &PScope<i32>| matches!(&*s.p.data.borrow(), Bindings::Own(_));
+        assert!(!copied(&f));
+
+        f.update("a", |n| *n += 10);
+        f.set_global("g".to_string(), 7);
+        assert!(f.set_checked("k".to_string(), 6).is_err());
+        assert_eq!(f.get("a"), Some(11));
+        assert_eq!(f.root().get("g"), Some(7));
+        assert!(!copied(&f));
+        assert!(copied(&f.parent().unwrap()));
+
+        assert_eq!(a.get("a"), Some(1));
+        assert_eq!(root.get("g"), Some(0));
+        // writing to the original copies it, leaving the fork as it was
+        b.set_local("late".to_string(), 2);
+        assert!(copied(&b) && !copied(&f));
+        assert_eq!(f.get("late"), None);
+        f.set_local("mine".to_string(), 3);
+        assert_eq!(b.get("mine"), None);
+        assert_eq!(b.get("late"), Some(2));
+
+        let g = root.fork().child();
+        assert_eq!(g.try_get("g"), Ok(Some(0)));
+        g.try_set("g".to_string(), 8).unwrap();
+        assert_eq!(g.try_get("g"), Ok(Some(8)));
+        assert_eq!(root.get("g"), Some(0));
+    }
+
+    #[test]
+    fn forks_take_their_modules_and_imports_with_them() {
+        let lib = PScope::new();
+        lib.set_local("x".to_string(), 1);
+        let root = PScope::new();
+        root.import(&lib, &[("x", "x")]);
+        root.import_all(&lib);
+        root.register_module("lib", lib.clone());
+
+        let f = root.fork();
+        f.set("x".to_string(), 2);
+        f.module("lib").unwrap().set_local("y".to_string(), 3);
+        assert_eq!(f.get("x"), Some(2));
+        assert_eq!(f.get("y"), Some(3));
+        assert_eq!(lib.get("x"), Some(1));
+        assert_eq!(lib.get("y"), None);
+        assert_eq!(root.get("y"), None);
+
+        lib.set_local("z".to_string(), 4);
+        assert_eq!(root.get("z"), Some(4));
+        assert_eq!(f.get("z"), None);
+    }
+}
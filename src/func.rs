@@ -0,0 +1,291 @@
+use crate::{PScope, Scope, Sym};
+use std::cell::RefMut;
+use std::rc::Rc;
+
+impl<T> Scope<T> {
+    /// This frame then each parent, up to and including the nearest function frame
+    fn function_frames(&self) -> impl Iterator<Item = &Scope<T>> {
+        std::iter::successors(Some(self), |s| match s.boundary {
+            true => None,
+            false => s.parent.as_ref().map(|p| &*p.p),
+        })
+    }
+
+    /// The nearest function frame, or the root
+    fn call_frame(&self) -> &Scope<T> {
+        self.frames()
+            .find(|f| f.boundary)
+            .unwrap_or_else(|| self.root_frame())
+    }
+
+    fn positionals_mut(&self) -> RefMut<'_, Vec<Option<T>>> {
+        self.bump();
+        self.extras_init().positionals.borrow_mut()
+    }
+
+    fn function_owner(&self, k: Sym) -> Option<&Scope<T>> {
+        self.function_frames().find(|f| f.defines(k))
+    }
+
+    /// This frame then each caller, falling back to the lexical parent
+    /// for frames that were not made by child_dynamic
+    fn dynamic_frames(&self) -> impl Iterator<Item = &Scope<T>> {
+        std::iter::successors(Some(self), |s| {
+            let caller = s.extras().and_then(|x| x.caller.as_ref());
+            caller.or(s.parent.as_ref()).map(|p| &*p.p)
+        })
+    }
+}
+
+/// Function frames mark a call boundary. The `fn_local` methods only see
+/// bindings up to the nearest one, and the `global` methods only the root,
+/// which between them give Python or Lua style function scoping.
+/// The plain methods ignore boundaries.
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// root.set_local("g".to_string(), 1);
+/// let outer = root.child_function();
+/// outer.set_local("x".to_string(), 2);
+///
+/// let f = outer.child_function();
+/// let block = f.child();
+/// assert_eq!(block.get_fn_local("x"), None);
+/// assert_eq!(block.get("x"), Some(2));
+/// assert_eq!(block.get_global("g"), Some(1));
+///
+/// block.set_fn_local("x".to_string(), 3);
+/// assert_eq!(block.get_fn_local("x"), Some(3));
+/// assert_eq!(outer.get("x"), Some(2));
+/// ```
+impl<T> PScope<T> {
+    pub fn child_function(&self) -> Self {
+        let mut s = self.child_frame();
+        s.boundary = true;
+        PScope { p: Rc::new(s) }
+    }
+
+    /// Whether this frame was made by child_function
+    pub fn is_function(&self) -> bool {
+        self.p.boundary
+    }
+
+    /// The nearest function frame, or the root if there is none
+    pub fn function_frame(&self) -> PScope<T> {
+        self.ancestors()
+            .find(|s| s.is_function())
+            .unwrap_or_else(|| self.root())
+    }
+
+    pub fn contains_fn_local(&self, id: &str) -> bool {
+        match self.p.sym(id) {
+            Some(s) => self.p.function_owner(s).is_some(),
+            None => false,
+        }
+    }
+
+    pub fn with_fn_local<R, F: FnOnce(&T) -> R>(&self, id: &str, f: F) -> Option<R> {
+        let s = self.p.sym(id).filter(|&s| self.p.allow_get(s))?;
+        self.p.function_owner(s)?.data().get(s).map(f)
+    }
+
+    /// Replaces id where it is defined within this function, or creates it here
+    pub fn set_fn_local(&self, id: String, val: T) {
+        let s = self.p.intern(&id);
+        match self.p.function_owner(s) {
+            Some(o) => o.set_local(s, val),
+            None => self.p.set_local(s, val),
+        }
+    }
+
+    pub fn with_global<R, F: FnOnce(&T) -> R>(&self, id: &str, f: F) -> Option<R> {
+        let s = self.p.sym(id).filter(|&s| self.p.allow_get(s))?;
+        self.p.root_frame().data().get(s).map(f)
+    }
+}
+
+/// Dynamic frames also link to the scope that called them.
+/// The `dynamic` lookups follow those links, while every other method
+/// keeps to the lexical parent chain. `ScopeTree` does not keep caller links.
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// root.set_local("x".to_string(), 1);
+/// let caller = root.child();
+/// caller.set_local("x".to_string(), 2);
+///
+/// let f = root.child_dynamic(&caller);
+/// assert_eq!(f.get("x"), Some(1));
+/// assert_eq!(f.get_dynamic("x"), Some(2));
+/// ```
+impl<T> PScope<T> {
+    /// A child of this scope that was called from caller.
+    ///
+    /// # Panics
+    ///
+    /// If caller belongs to another tree
+    pub fn child_dynamic(&self, caller: &PScope<T>) -> Self {
+        assert!(
+            Rc::ptr_eq(&self.root().p, &caller.root().p),
+            "the caller must be in the same tree"
+        );
+        let mut s = self.child_frame();
+        s.extras_mut().caller = Some(caller.clone());
+        PScope { p: Rc::new(s) }
+    }
+
+    /// The scope this frame was called from, if it was made by child_dynamic
+    pub fn caller(&self) -> Option<PScope<T>> {
+        self.p.extras()?.caller.clone()
+    }
+
+    pub fn with_dynamic<R, F: FnOnce(&T) -> R>(&self, id: &str, f: F) -> Option<R> {
+        let s = self.p.sym(id).filter(|&s| self.p.allow_get(s))?;
+        let o = self.p.dynamic_frames().find(|f| {
+            f.purge_here(s);
+            f.defines(s)
+        })?;
+        o.data().get(s).map(f)
+    }
+}
+
+/// Positional parameters belong to the nearest function frame, or the root,
+/// so every block inside a call sees the same ones. Index 0 is the first, `$1` in a shell.
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// let f = root.child_function();
+/// f.set_positionals(vec!["a", "b"]);
+/// let block = f.child();
+///
+/// assert_eq!(block.get_positional(1), Some("b"));
+/// assert_eq!(block.shift_positionals(), Some("a"));
+/// assert_eq!(block.get_positional(0), Some("b"));
+/// assert_eq!(root.positional_len(), 0);
+/// ```
+impl<T> PScope<T> {
+    /// Any gap before idx is left unset
+    pub fn set_positional(&self, idx: usize, val: T) {
+        let mut pos = self.p.call_frame().positionals_mut();
+        while pos.len() <= idx {
+            pos.push(None);
+        }
+        pos[idx] = Some(val);
+    }
+
+    /// Replaces all of them
+    pub fn set_positionals(&self, vals: Vec<T>) {
+        *self.p.call_frame().positionals_mut() = vals.into_iter().map(Some).collect();
+    }
+
+    pub fn with_positional<R, F: FnOnce(&T) -> R>(&self, idx: usize, f: F) -> Option<R> {
+        let pos = self.p.call_frame().extras()?.positionals.borrow();
+        pos.get(idx)?.as_ref().map(f)
+    }
+
+    /// Removes the first, moving the rest down one
+    pub fn shift_positionals(&self) -> Option<T> {
+        let mut pos = self.p.call_frame().positionals_mut();
+        match pos.is_empty() {
+            true => None,
+            false => pos.remove(0),
+        }
+    }
+
+    /// One past the highest index set, like `$#` in a shell
+    pub fn positional_len(&self) -> usize {
+        (self.p.call_frame().extras()).map_or(0, |x| x.positionals.borrow().len())
+    }
+}
+
+impl<T: Clone> PScope<T> {
+    pub fn get_positional(&self, idx: usize) -> Option<T> {
+        self.with_positional(idx, T::clone)
+    }
+
+    pub fn get_dynamic(&self, id: &str) -> Option<T> {
+        self.with_dynamic(id, T::clone)
+    }
+
+    pub fn get_fn_local(&self, id: &str) -> Option<T> {
+        self.with_fn_local(id, T::clone)
+    }
+
+    /// Reads only the root frame
+    pub fn get_global(&self, id: &str) -> Option<T> {
+        self.with_global(id, T::clone)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn boundaries_stop_local_lookups() {
+        let root = PScope::new();
+        root.set_local("x".to_string(), 1);
+        let b = root.child();
+        assert!(b.contains_fn_local("x"));
+        assert!(PScope::ptr_eq(&b.function_frame(), &root));
+
+        let f = b.child_function();
+        let inner = f.child();
+        assert!(f.is_function());
+        assert!(!inner.is_function());
+        assert!(PScope::ptr_eq(&inner.function_frame(), &f));
+        assert!(!inner.contains_fn_local("x"));
+
+        f.set_local("y".to_string(), 2);
+        inner.set_fn_local("y".to_string(), 3);
+        assert_eq!(f.get("y"), Some(3));
+        assert!(!inner.contains_local("y"));
+
+        inner.set_fn_local("x".to_string(), 4);
+        assert_eq!(inner.get_fn_local("x"), Some(4));
+        assert!(inner.contains_local("x"));
+        assert_eq!(root.get("x"), Some(1));
+        assert_eq!(inner.get_global("x"), Some(1));
+        assert_eq!(inner.get_global("y"), None);
+    }
+
+    #[test]
+    fn positionals_per_call() {
+        let root = PScope::new();
+        root.set_positional(0, 1);
+        let f = root.child().child_function();
+        f.set_positional(2, 3);
+        assert_eq!(f.positional_len(), 3);
+        assert_eq!(f.get_positional(0), None);
+        assert_eq!(f.shift_positionals(), None);
+        assert_eq!(f.get_positional(1), Some(3));
+        assert_eq!(f.child().with_positional(1, |n| n * 2), Some(6));
+        assert_eq!(root.child().get_positional(0), Some(1));
+        assert_eq!(f.get_positional(5), None);
+    }
+
+    #[test]
+    fn dynamic_follows_callers() {
+        let root = PScope::new();
+        root.set_local("x".to_string(), 0);
+        let a = root.child();
+        a.set_local("x".to_string(), 1);
+        let f = root.child_dynamic(&a);
+        let g = root.child_dynamic(&f);
+        let block = g.child();
+
+        assert_eq!(block.get_dynamic("x"), Some(1));
+        assert_eq!(block.get("x"), Some(0));
+        assert!(PScope::ptr_eq(&g.caller().unwrap(), &f));
+        assert!(block.caller().is_none());
+
+        // recursion builds deep caller chains
+        let mut cur = f;
+        for _ in 0..100_000 {
+            cur = root.child_dynamic(&cur);
+        }
+        assert_eq!(cur.get_dynamic("x"), Some(1));
+    }
+}
@@ -0,0 +1,101 @@
+use crate::{PScope, Sym};
+
+/// A binding in the root, see `PScope::global_ref`. Every access goes straight to
+/// the root's map by Sym, so no frames are walked and no names are compared,
+/// and a binding of the same name in a child frame never shadows it.
+pub struct GlobalRef<T> {
+    root: PScope<T>,
+    sym: Sym,
+}
+
+impl<T> Clone for GlobalRef<T> {
+    fn clone(&self) -> Self {
+        GlobalRef {
+            root: self.root.clone(),
+            sym: self.sym,
+        }
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// let c = root.child().child();
+/// let print = c.global_ref("print");
+/// assert_eq!(print.get(), None);
+///
+/// print.set(1);
+/// c.set_local("print".to_string(), 5);
+/// assert_eq!(print.update(|n| *n += 1), Some(()));
+/// assert_eq!(root.get("print"), Some(2));
+/// assert_eq!(print.get(), Some(2));
+/// ```
+impl<T> GlobalRef<T> {
+    pub fn sym(&self) -> Sym {
+        self.sym
+    }
+
+    /// Sets the binding in the root, making it if it is not there
+    pub fn set(&self, val: T) {
+        self.root.p.set_local(self.sym, val);
+    }
+
+    pub fn update<F: FnOnce(&mut T) -> A, A>(&self, f: F) -> Option<A> {
+        self.root.p.purge_here(self.sym);
+        self.root.p.update_local(&self.root.p, self.sym, f)
+    }
+
+    pub fn with<R, F: FnOnce(&T) -> R>(&self, f: F) -> Option<R> {
+        if !self.root.p.allow_get(self.sym) {
+            return None;
+        }
+        self.root.p.purge_here(self.sym);
+        self.root.p.data().get(self.sym).map(f)
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.root.p.purge_here(self.sym);
+        self.root.p.data().contains_key(self.sym)
+    }
+}
+
+impl<T: Clone> GlobalRef<T> {
+    pub fn get(&self) -> Option<T> {
+        self.with(T::clone)
+    }
+}
+
+impl<T> PScope<T> {
+    /// A handle on the root binding for id, whether or not it is set yet,
+    /// for builtins and other names that are looked up constantly and never move
+    pub fn global_ref(&self, id: &str) -> GlobalRef<T> {
+        GlobalRef {
+            root: self.root(),
+            sym: self.p.intern(id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn global_refs_skip_the_chain() {
+        let root = PScope::new();
+        root.set_local("g".to_string(), 1);
+        let c = root.child();
+        c.set_local("g".to_string(), 10);
+
+        let g = c.global_ref("g");
+        assert_eq!(g.sym(), c.intern("g"));
+        assert_eq!(g.get(), Some(1));
+        assert_eq!(g.clone().with(|n| n + 1), Some(2));
+        root.remove_local("g");
+        assert!(!g.is_set());
+        assert_eq!(g.update(|n| *n += 1), None);
+        g.set(3);
+        assert_eq!(c.get("g"), Some(10));
+        assert_eq!(root.get("g"), Some(3));
+    }
+}
@@ -0,0 +1,88 @@
+use crate::PScope;
+use std::cell::Ref;
+use std::fmt;
+use std::ops::Deref;
+
+/// A value borrowed from its frame by `PScope::get_ref`, read without cloning.
+/// While any guard on a frame is held, that frame can still be read but not
+/// changed: the plain setters panic, and the `try_` methods return `BorrowError`.
+/// Every other frame can be changed as usual.
+pub struct ScopeRef<'a, T>(Ref<'a, T>);
+
+impl<'a, T> ScopeRef<'a, T> {
+    /// Narrows the guard to part of the value, like `Ref::map`
+    pub fn map<U, F: FnOnce(&T) -> &U>(r: Self, f: F) -> ScopeRef<'a, U> {
+        ScopeRef(Ref::map(r.0, f))
+    }
+}
+
+impl<'a, T> Deref for ScopeRef<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for ScopeRef<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::{BorrowError, PScope, ScopeRef};
+/// let root = PScope::new();
+/// root.set_local("v".to_string(), vec![1, 2, 3]);
+/// let c = root.child();
+/// {
+///     let v = c.get_ref("v").unwrap();
+///     assert_eq!(v.len(), 3);
+///     assert_eq!(c.try_set("v".to_string(), vec![]), Err(BorrowError));
+///     c.set_local("w".to_string(), vec![]);
+///     let first = ScopeRef::map(v, |v| &v[0]);
+///     assert_eq!(*first, 1);
+/// }
+/// root.set("v".to_string(), vec![4]);
+/// ```
+impl<T> PScope<T> {
+    /// Borrows the visible value from the frame that owns it.
+    /// Imported bindings are not seen, as they belong to another scope.
+    pub fn get_ref(&self, id: &str) -> Option<ScopeRef<'_, T>> {
+        let s = self.p.sym(id).filter(|&s| self.p.allow_get(s))?;
+        let owner = self.p.owner(s)?;
+        Ref::filter_map(owner.data(), |d| d.get(s))
+            .ok()
+            .map(ScopeRef)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    #[test]
+    fn guards_block_changes_to_their_frame() {
+        let root = PScope::new();
+        root.set_local("a".to_string(), String::from("abc"));
+        let c = root.child();
+        let held = c.get_ref("a").unwrap();
+        assert_eq!(format!("{:?}", held), "\"abc\"");
+
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            root.set_local("b".to_string(), String::new())
+        }));
+        let msg = res.unwrap_err();
+        assert!(msg.downcast_ref::<String>().unwrap().contains("ScopeRef"));
+        assert!(c.try_update("a", |s| s.push('d')).is_err());
+        c.set_local("a".to_string(), String::from("shadow"));
+        assert_eq!(*held, "abc");
+        drop(held);
+
+        root.update("a", |s| s.push('d'));
+        assert_eq!(
+            root.get_ref("a").as_deref().map(String::as_str),
+            Some("abcd")
+        );
+    }
+}
@@ -0,0 +1,275 @@
+use crate::{PScope, ScopeOptions};
+use std::collections::hash_map::RandomState;
+use std::convert::TryInto;
+use std::hash::{BuildHasher, BuildHasherDefault, Hasher};
+use std::rc::Rc;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// How a tree hashes names when it turns them into Syms
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum NameHasher {
+    /// The std hasher, which resists inputs chosen to collide, the default
+    #[default]
+    Sip,
+    /// The rustc hasher, much faster on short names but easy to attack,
+    /// so only for names that can be trusted
+    Fx,
+    /// A BuildHasher given to `PScope::with_hasher`. There is none to copy,
+    /// so a new tree asked for this, as from saved options, uses Sip.
+    Custom,
+}
+
+const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+/// The hasher rustc uses, a multiply and rotate per word
+#[derive(Clone, Copy, Default)]
+pub(crate) struct FxHasher(u64);
+
+impl FxHasher {
+    fn add(&mut self, word: u64) {
+        self.0 = (self.0.rotate_left(5) ^ word).wrapping_mul(SEED);
+    }
+}
+
+impl Hasher for FxHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        let mut chunks = bytes.chunks_exact(8);
+        for c in &mut chunks {
+            self.add(u64::from_le_bytes(c.try_into().expect("chunks of 8")));
+        }
+        for b in chunks.remainder() {
+            self.add(*b as u64);
+        }
+    }
+
+    fn write_u8(&mut self, n: u8) {
+        self.add(n as u64);
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.add(n as u64);
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.add(n);
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.add(n as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// For maps keyed by Sym, which are small numbers that need no protecting
+pub(crate) type FxBuild = BuildHasherDefault<FxHasher>;
+
+/// Passes on a hash made earlier, for maps keyed by one
+#[derive(Clone, Copy, Default)]
+pub(crate) struct PassHasher(u64);
+
+impl Hasher for PassHasher {
+    fn write(&mut self, _: &[u8]) {
+        unreachable!("only hashes are hashed with this")
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = n;
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+pub(crate) type PassBuild = BuildHasherDefault<PassHasher>;
+
+/// How a tree hashes its names, picked at runtime by NameHasher
+#[derive(Clone)]
+pub(crate) enum NameBuild {
+    Sip(RandomState),
+    Fx,
+    Custom(Rc<dyn Fn(&str) -> u64>),
+}
+
+impl NameBuild {
+    pub fn new(h: NameHasher) -> Self {
+        match h {
+            NameHasher::Fx => NameBuild::Fx,
+            NameHasher::Sip | NameHasher::Custom => NameBuild::Sip(RandomState::new()),
+        }
+    }
+
+    pub fn custom<S: BuildHasher + 'static>(s: S) -> Self {
+        NameBuild::Custom(Rc::new(move |name| s.hash_one(name)))
+    }
+
+    pub fn kind(&self) -> NameHasher {
+        match self {
+            NameBuild::Sip(_) => NameHasher::Sip,
+            NameBuild::Fx => NameHasher::Fx,
+            NameBuild::Custom(_) => NameHasher::Custom,
+        }
+    }
+
+    pub fn hash(&self, name: &str) -> u64 {
+        match self {
+            NameBuild::Sip(s) => s.hash_one(name),
+            NameBuild::Fx => FxBuild::default().hash_one(name),
+            NameBuild::Custom(f) => f(name),
+        }
+    }
+}
+
+impl Default for NameBuild {
+    fn default() -> Self {
+        Self::new(NameHasher::default())
+    }
+}
+
+/// A name hashed ahead of time by a tree's hasher, see `PScope::hashed`,
+/// so lookups by it in that tree or its forks skip hashing it again.
+/// In any other tree it is hashed again, so it still finds what the name would.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hashed<K> {
+    key: K,
+    pub(crate) hash: u64,
+    /// The interner the hash was made for
+    pub(crate) by: u64,
+}
+
+impl<K: AsRef<str>> Hashed<K> {
+    pub(crate) fn new(key: K, hash: u64, by: u64) -> Self {
+        Hashed { key, hash, by }
+    }
+
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn name(&self) -> &str {
+        self.key.as_ref()
+    }
+}
+
+/// Any BuildHasher can hash a tree's names, and names used often can be
+/// hashed once, up front.
+///
+/// ```rust
+/// use scope_store::{PScope, ScopeOptions, NameHasher};
+/// use std::collections::hash_map::RandomState;
+/// let root = PScope::with_hasher(ScopeOptions::default(), RandomState::new());
+/// assert_eq!(root.options().hasher, NameHasher::Custom);
+/// let c = root.child();
+/// c.set_local("x".to_string(), 1);
+///
+/// let x = c.hashed("x");
+/// assert_eq!(c.get_hashed(&x), Some(1));
+/// assert_eq!(root.get_hashed(&x), None);
+/// // another tree hashes it again itself
+/// assert_eq!(PScope::<i32>::new().with_hashed(&x, |n| *n), None);
+/// ```
+impl<T> PScope<T> {
+    /// A new root whose names are hashed by s, like `with_options`,
+    /// whose hasher setting is ignored
+    pub fn with_hasher<S: BuildHasher + 'static>(opts: ScopeOptions, s: S) -> Self {
+        let root = Self::with_options(opts);
+        *root.p.tree().names.borrow_mut() = crate::sym::Interner::with_build(NameBuild::custom(s));
+        root
+    }
+
+    /// key hashed by this tree's hasher, for use with the `_hashed` lookups
+    pub fn hashed<K: AsRef<str>>(&self, key: K) -> Hashed<K> {
+        self.p.tree().names.borrow().hashed(key)
+    }
+
+    pub fn with_hashed<K: AsRef<str>, R, F: FnOnce(&T) -> R>(
+        &self,
+        id: &Hashed<K>,
+        f: F,
+    ) -> Option<R> {
+        self.p.with(self.p.sym_hashed(id)?, f)
+    }
+
+    pub fn contains_hashed<K: AsRef<str>>(&self, id: &Hashed<K>) -> bool {
+        self.with_hashed(id, |_| ()).is_some()
+    }
+}
+
+impl<T: Clone> PScope<T> {
+    pub fn get_hashed<K: AsRef<str>>(&self, id: &Hashed<K>) -> Option<T> {
+        self.p.get(self.p.sym_hashed(id)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn fx_spreads_short_names() {
+        let fx = FxBuild::default();
+        let hashes: std::collections::BTreeSet<u64> =
+            (0..1000).map(|n| fx.hash_one(format!("v{}", n))).collect();
+        assert_eq!(hashes.len(), 1000);
+        assert_eq!(fx.hash_one("abc"), fx.hash_one("abc"));
+
+        let names = NameBuild::new(NameHasher::Fx);
+        assert_eq!(names.kind(), NameHasher::Fx);
+        assert_eq!(names.hash("abc"), fx.hash_one("abc"));
+        let sip = NameBuild::default();
+        assert_eq!(sip.hash("abc"), sip.hash("abc"));
+        assert_eq!(NameBuild::new(NameHasher::Custom).kind(), NameHasher::Sip);
+
+        let root = crate::PScope::with_options(crate::ScopeOptions {
+            hasher: NameHasher::Fx,
+            storage: crate::Storage::Hash,
+            ..Default::default()
+        });
+        for n in 0..20 {
+            root.set_local(format!("v{}", n), n);
+        }
+        assert_eq!(root.get("v13"), Some(13));
+        assert_eq!(root.child().options().hasher, NameHasher::Fx);
+    }
+
+    /// Gives every name the same hash, so each lookup has to tell them apart
+    #[derive(Default)]
+    struct Clash;
+
+    impl Hasher for Clash {
+        fn write(&mut self, _: &[u8]) {}
+        fn finish(&self) -> u64 {
+            0
+        }
+    }
+
+    #[test]
+    fn hashed_names_find_what_names_do() {
+        let root = PScope::with_hasher(
+            ScopeOptions::default(),
+            BuildHasherDefault::<Clash>::default(),
+        );
+        for n in 0..20 {
+            root.set_local(format!("v{}", n), n);
+        }
+        let c = root.child();
+        assert_eq!(c.get("v13"), Some(13));
+        assert_eq!(c.get_hashed(&c.hashed("v7")), Some(7));
+        assert_eq!(c.get_hashed(&c.hashed("v20".to_string())), None);
+        assert!((0..20).all(|n| c.get_hashed(&c.hashed(format!("v{}", n))) == Some(n)));
+
+        let f = c.fork();
+        let v3 = c.hashed("v3");
+        assert_eq!(f.get_hashed(&v3), Some(3));
+        let other = PScope::new();
+        other.set_local("v3".to_string(), 30);
+        assert_eq!(other.get_hashed(&v3), Some(30));
+        assert!(other.contains_hashed(&v3));
+        assert_eq!(v3.name(), "v3");
+    }
+}
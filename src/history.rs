@@ -0,0 +1,162 @@
+use crate::snapshot::WeakSnapshot;
+use crate::PScope;
+use std::collections::VecDeque;
+
+/// The last checkpoints taken by `tick`, oldest first. They hold their
+/// frames weakly, as the root holds them, and a frame would keep it alive.
+pub(crate) struct History<T> {
+    cap: usize,
+    snaps: VecDeque<WeakSnapshot<T>>,
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// root.enable_history(10);
+/// for n in 0..5 {
+///     root.set_local("n".to_string(), n);
+///     root.tick();
+/// }
+/// let c = root.child();
+/// c.set("n".to_string(), 100);
+///
+/// let back = c.rewind(2).unwrap();
+/// assert!(PScope::ptr_eq(&back, &root));
+/// assert_eq!(root.get("n"), Some(3));
+/// assert_eq!(root.history_len(), 4);
+/// ```
+impl<T: Clone> PScope<T> {
+    /// Keeps the last capacity checkpoints taken by `tick` anywhere in the tree.
+    /// If the history is already on, only its capacity changes.
+    pub fn enable_history(&self, capacity: usize) {
+        let mut h = self.p.hooks().history.borrow_mut();
+        let h = h.get_or_insert_with(|| History {
+            cap: capacity,
+            snaps: VecDeque::new(),
+        });
+        h.cap = capacity;
+        while h.snaps.len() > capacity {
+            h.snaps.pop_front();
+        }
+    }
+
+    /// Takes a checkpoint of every frame from here to the root, like `snapshot`,
+    /// dropping the oldest one if the history is full. Does nothing if it is off.
+    pub fn tick(&self) {
+        let mut h = self.p.hooks().history.borrow_mut();
+        if let Some(h) = h.as_mut().filter(|h| h.cap > 0) {
+            if h.snaps.len() == h.cap {
+                h.snaps.pop_front();
+            }
+            h.snaps.push_back(self.snapshot().downgrade());
+        }
+    }
+
+    /// Restores the chain to how it was at the nth last tick, where 1 is the
+    /// latest, and gives back the frame that tick was called on, to carry on
+    /// from. The checkpoints after it are dropped, so it becomes the latest.
+    /// If that frame has since been dropped, the nearest parent still alive
+    /// is restored and given back instead. None, keeping every checkpoint,
+    /// if there are fewer than n, n is 0, or none of that one's frames are alive.
+    pub fn rewind(&self, n: usize) -> Option<PScope<T>> {
+        let (snap, later) = {
+            let mut h = self.p.hooks().history.borrow_mut();
+            let h = h.as_mut()?;
+            if n == 0 || n > h.snaps.len() {
+                return None;
+            }
+            let at = h.snaps.len() - n;
+            let snap = h.snaps[at].upgrade()?;
+            (snap, h.snaps.split_off(at + 1))
+        };
+        // outside the borrow, as the values dropped or cloned may use the history
+        drop(later);
+        let frame = snap.frame().clone();
+        frame.restore(&snap);
+        Some(frame)
+    }
+}
+
+impl<T> PScope<T> {
+    /// Drops every checkpoint and stops taking them
+    pub fn disable_history(&self) {
+        *self.p.hooks().history.borrow_mut() = None;
+    }
+
+    /// The number of checkpoints kept
+    pub fn history_len(&self) -> usize {
+        (self.p.hooks().history.borrow().as_ref()).map_or(0, |h| h.snaps.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    #[test]
+    fn history_steps_back_into_frames() {
+        let root = PScope::new();
+        root.tick();
+        assert_eq!(root.history_len(), 0);
+        root.enable_history(3);
+        root.set_local("g".to_string(), 0);
+        let f = root.child();
+        for n in 1..=4 {
+            f.set_local("x".to_string(), n);
+            root.set_local("g".to_string(), n);
+            f.tick();
+        }
+        assert_eq!(root.history_len(), 3);
+        let back = root.rewind(2).unwrap();
+        assert!(PScope::ptr_eq(&back, &f));
+        assert_eq!((f.get("x"), root.get("g")), (Some(3), Some(3)));
+
+        drop((f, back));
+        let back = root.rewind(2).unwrap();
+        assert!(PScope::ptr_eq(&back, &root));
+        assert_eq!(root.get("g"), Some(2));
+        assert_eq!(root.history_len(), 1);
+        assert!(root.rewind(2).is_none());
+        assert!(root.rewind(0).is_none());
+
+        root.enable_history(0);
+        assert_eq!(root.history_len(), 0);
+        root.disable_history();
+        assert!(root.rewind(1).is_none());
+    }
+
+    /// Uses the history of the tree it is in when dropped
+    struct Probe(Rc<RefCell<Option<PScope<Probe>>>>);
+
+    impl Clone for Probe {
+        fn clone(&self) -> Self {
+            Probe(self.0.clone())
+        }
+    }
+
+    impl Drop for Probe {
+        fn drop(&mut self) {
+            if let Some(r) = &*self.0.borrow() {
+                r.history_len();
+            }
+        }
+    }
+
+    #[test]
+    fn values_may_use_the_history_while_rewinding() {
+        let root = PScope::new();
+        let slot = Rc::new(RefCell::new(None));
+        root.enable_history(5);
+        for n in 0..4 {
+            root.set_local(format!("v{}", n), Probe(slot.clone()));
+            root.tick();
+        }
+        *slot.borrow_mut() = Some(root.clone());
+        assert!(root.rewind(3).is_some());
+        assert_eq!(root.history_len(), 2);
+        assert_eq!(root.local_len(), 2);
+        *slot.borrow_mut() = None;
+    }
+}
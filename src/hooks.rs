@@ -0,0 +1,347 @@
+use crate::env::EnvFallback;
+use crate::history::History;
+use crate::journal::Journal;
+use crate::undo::Undo;
+use crate::versions::Versions;
+use crate::{PScope, Scope, Sym};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::ops::ControlFlow;
+use std::rc::Rc;
+
+type GetHook = Rc<dyn Fn(&str) -> ControlFlow<()>>;
+type SetHook<T> = Rc<dyn Fn(&str, &mut T) -> ControlFlow<()>>;
+pub(crate) type Validator<T> = Rc<dyn Fn(&T) -> Result<(), String>>;
+type CopyFn<T> = Cell<Option<fn(&T) -> T>>;
+
+/// Middleware installed on a root, see `PScope::on_get` and `PScope::on_set`,
+/// the environment fallback, and the journal, history, undo and kept versions,
+/// which also watch the whole tree
+pub(crate) struct Hooks<T> {
+    get: RefCell<Vec<GetHook>>,
+    set: RefCell<Vec<SetHook<T>>>,
+    /// Set with the first hook or validator, to keep what a change in place replaced
+    copy: CopyFn<T>,
+    pub versions: RefCell<BTreeMap<Sym, Versions<T>>>,
+    pub env: RefCell<Option<EnvFallback<T>>>,
+    pub journal: RefCell<Option<Journal<T>>>,
+    pub history: RefCell<Option<History<T>>>,
+    pub undo: RefCell<Option<Undo<T>>>,
+}
+
+/// For forks, which keep the middleware but start without a journal, history or undo
+impl<T> Clone for Hooks<T> {
+    fn clone(&self) -> Self {
+        Hooks {
+            get: self.get.clone(),
+            set: self.set.clone(),
+            copy: self.copy.clone(),
+            versions: self.versions.clone(),
+            env: self.env.clone(),
+            journal: RefCell::new(None),
+            history: RefCell::new(None),
+            undo: RefCell::new(None),
+        }
+    }
+}
+
+impl<T> Default for Hooks<T> {
+    fn default() -> Self {
+        Hooks {
+            get: RefCell::new(Vec::new()),
+            set: RefCell::new(Vec::new()),
+            copy: Cell::new(None),
+            versions: RefCell::new(BTreeMap::new()),
+            env: RefCell::new(None),
+            journal: RefCell::new(None),
+            history: RefCell::new(None),
+            undo: RefCell::new(None),
+        }
+    }
+}
+
+impl<T> Scope<T> {
+    pub(crate) fn hooks(&self) -> &Hooks<T> {
+        (self.root_frame().hooks.as_deref()).expect("every root has hooks")
+    }
+
+    /// Runs the tree's on_get hooks for k, false if one refuses the read
+    pub(crate) fn allow_get(&self, k: Sym) -> bool {
+        // Cloned out so hooks are free to use the scope
+        let hs = {
+            let hs = self.hooks().get.borrow();
+            if hs.is_empty() {
+                return true;
+            }
+            hs.clone()
+        };
+        let name = self.name(k);
+        hs.iter().all(|h| h(&name).is_continue())
+    }
+
+    /// Runs the tree's on_set hooks for k, which may change val, then its
+    /// validator, false if any refuses the write
+    pub(crate) fn allow_set(&self, k: Sym, val: &mut T) -> bool {
+        self.check_set(k, val).is_ok()
+    }
+
+    /// Like allow_set, saying which refused
+    pub(crate) fn check_set(&self, k: Sym, val: &mut T) -> Result<(), Refused> {
+        let hs = self.hooks().set.borrow().clone();
+        if !hs.is_empty() {
+            let name = self.name(k);
+            if !hs.iter().all(|h| h(&name, val).is_continue()) {
+                return Err(Refused::Hook);
+            }
+        }
+        self.validate(k, val).map_err(Refused::Invalid)
+    }
+
+    /// A copy of k's value here, to put back if a change in place made from
+    /// frame by is refused. None if no hook or validator would look at it.
+    pub(crate) fn keep_for_check(&self, by: &Scope<T>, k: Sym) -> Option<T> {
+        let h = self.hooks();
+        let copy = h.copy.get()?;
+        if h.set.borrow().is_empty() && by.validators(k).next().is_none() {
+            return None;
+        }
+        self.data().get(k).map(copy)
+    }
+
+    /// Runs the hooks for frame by on k's value here once it has been changed
+    /// in place, keeping what they make of it, or putting back old if one refuses it
+    pub(crate) fn check_change(
+        &self,
+        by: &Scope<T>,
+        k: Sym,
+        old: Option<T>,
+    ) -> Result<(), Refused> {
+        let (old, copy) = match (old, self.hooks().copy.get()) {
+            (Some(o), Some(c)) => (o, c),
+            _ => return Ok(()),
+        };
+        let mut new = match self.data().get(k) {
+            Some(v) => copy(v),
+            None => return Ok(()),
+        };
+        let res = by.check_set(k, &mut new);
+        let keep = match res {
+            Ok(()) => new,
+            Err(_) => old,
+        };
+        if let Some(v) = self.data_mut().get_mut(k) {
+            *v = keep;
+        }
+        res
+    }
+
+    /// The validators for k on this frame and its parents, innermost first
+    fn validators(&self, k: Sym) -> impl Iterator<Item = Validator<T>> + '_ {
+        let any = self.tree().validated.get();
+        (self.frames().take_while(move |_| any))
+            .filter_map(move |f| f.extras()?.valid.borrow().get(&k).cloned())
+    }
+
+    /// Runs each validator for k seen from here
+    pub(crate) fn validate(&self, k: Sym, val: &T) -> Result<(), String> {
+        self.validators(k).try_for_each(|v| v(val))
+    }
+}
+
+/// Why a write was dropped
+pub(crate) enum Refused {
+    /// An on_set hook broke
+    Hook,
+    /// The key's validator failed, with its reason
+    Invalid(String),
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// use std::ops::ControlFlow;
+/// let root = PScope::new();
+/// root.on_set(|k, v: &mut i32| {
+///     if k.starts_with("__") {
+///         return ControlFlow::Break(());
+///     }
+///     *v = (*v).min(100);
+///     ControlFlow::Continue(())
+/// });
+/// root.set_validator("big", |v| match *v > 0 {
+///     true => Ok(()),
+///     false => Err("must be positive".to_string()),
+/// });
+/// root.on_get(|k| match k {
+///     "secret" => ControlFlow::Break(()),
+///     _ => ControlFlow::Continue(()),
+/// });
+///
+/// let c = root.child();
+/// c.set_local("__proto".to_string(), 1);
+/// c.set("big".to_string(), 1000);
+/// c.update("big", |v| *v = -5);
+/// root.set_local("secret".to_string(), 7);
+/// assert!(!c.contains("__proto"));
+/// assert_eq!(c.get("big"), Some(100));
+/// assert_eq!(c.get("secret"), None);
+/// ```
+impl<T> PScope<T> {
+    /// Adds f to the hooks run on the tree's root before each value is read,
+    /// by name, through a slot, global ref or entry, or all at once by
+    /// `visible` and the like. Break hides the binding, as if it were undefined.
+    /// Listing the names in a frame, by `local_keys`, doesn't run it.
+    pub fn on_get<F: Fn(&str) -> ControlFlow<()> + 'static>(&self, f: F) {
+        self.p.hooks().get.borrow_mut().push(Rc::new(f));
+    }
+}
+
+impl<T: Clone> PScope<T> {
+    /// Adds f to the hooks run on the tree's root before each new value is
+    /// stored, which may change the value. Break drops the write instead, as
+    /// a frozen frame would. A change made in place, by `update` and the like,
+    /// is given to f once it is made, and undone if f breaks, so a value of T
+    /// is copied before each such change while there are hooks. Restoring a
+    /// snapshot or tree, or undoing a change, doesn't run it. Hooks run in
+    /// the order they were added, and forks keep them.
+    pub fn on_set<F: Fn(&str, &mut T) -> ControlFlow<()> + 'static>(&self, f: F) {
+        let h = self.p.hooks();
+        h.copy.set(Some(T::clone));
+        h.set.borrow_mut().push(Rc::new(f));
+    }
+
+    /// Checks each new value of id set from this frame, or any frame below
+    /// it, with f, replacing any validator id had on this frame. A validator
+    /// on the root checks the whole tree. Changes made in place are checked
+    /// once they are made, and undone if f fails. The checked setters fail with
+    /// `ScopeError::Invalid` when f does, and the rest drop the write, as for
+    /// `on_set`, which runs first. Validators on inner frames run first.
+    pub fn set_validator<F: Fn(&T) -> Result<(), String> + 'static>(&self, id: &str, f: F) {
+        let k = self.p.intern(id);
+        self.p.hooks().copy.set(Some(T::clone));
+        self.p.tree().validated.set(true);
+        (self.p.extras_init().valid.borrow_mut()).insert(k, Rc::new(f));
+    }
+}
+
+impl<T> PScope<T> {
+    /// Returns false if id had no validator on this frame
+    pub fn remove_validator(&self, id: &str) -> bool {
+        match self.p.sym(id) {
+            Some(k) => (self.p.extras()).is_some_and(|x| x.valid.borrow_mut().remove(&k).is_some()),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{NumericScope, ScopeError, ScopeOp};
+    #[test]
+    fn hooks_see_every_kind_of_write() {
+        let root = PScope::new();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let l2 = log.clone();
+        root.on_set(move |k, v: &mut i32| {
+            l2.borrow_mut().push(k.to_string());
+            match *v < 0 {
+                true => ControlFlow::Break(()),
+                false => ControlFlow::Continue(()),
+            }
+        });
+        let c = root.child();
+        c.set("a".to_string(), 1);
+        c.set_global("b".to_string(), 2);
+        assert_eq!(c.try_replace("a", -1), Some(-1));
+        c.try_set("d".to_string(), -1).unwrap();
+        c.apply(vec![ScopeOp::SetLocal("e".to_string(), -5)])
+            .unwrap();
+        c.global_ref("f").set(3);
+        assert_eq!(*log.borrow(), vec!["a", "b", "a", "d", "e", "f"]);
+        assert_eq!(c.flatten().len(), 3);
+
+        // changes in place are checked once made, and undone if refused
+        log.borrow_mut().clear();
+        assert_eq!(c.update("a", |n| *n += 1), Some(()));
+        assert_eq!(c.update("a", |n| *n = -1), None);
+        let (up, b) = c.locate("b").unwrap();
+        assert_eq!(c.update_at(up, b, |n| *n -= 10), None);
+        assert_eq!(
+            c.update_slot(&c.resolve("b").unwrap(), |n| *n += 1),
+            Some(())
+        );
+        assert_eq!(c.global_ref("f").update(|n| *n = -3), None);
+        c.entry("f").and_modify(|n| *n = -4);
+        assert_eq!(c.incr("f"), Some(4));
+        assert_eq!(c.try_update("a", |n| *n -= 100), Ok(None));
+        assert_eq!(log.borrow().len(), 8);
+        assert_eq!(
+            c.get_many(&["a", "b", "f"]),
+            vec![Some(2), Some(3), Some(4)]
+        );
+
+        let reads = Rc::new(RefCell::new(0));
+        let r2 = reads.clone();
+        c.on_get(move |_| {
+            *r2.borrow_mut() += 1;
+            ControlFlow::Continue(())
+        });
+        c.get("a");
+        c.fork().get("b");
+        c.get_ref("f");
+        c.get_many(&["a", "b"]);
+        c.with_slot(&c.resolve("a").unwrap(), |_| ());
+        assert_eq!(*reads.borrow(), 6);
+
+        root.on_get(|k| match k {
+            "f" => ControlFlow::Break(()),
+            _ => ControlFlow::Continue(()),
+        });
+        let (up, f) = c.locate("f").unwrap();
+        assert_eq!(c.with_global("f", |_| ()), None);
+        assert_eq!(c.with_fn_local("f", |_| ()), None);
+        assert_eq!(c.child_dynamic(&c).with_dynamic("f", |_| ()), None);
+        assert_eq!(c.get_at(up, f), None);
+        assert_eq!(c.try_get("f"), Ok(None));
+        assert!(!c.entry("f").is_occupied());
+        assert!(!c.flatten().contains_key("f"));
+        assert_eq!(c.try_get("a"), Ok(Some(2)));
+    }
+
+    #[test]
+    fn validators_refuse_bad_values() {
+        let root = PScope::new();
+        let c = root.child();
+        c.set_validator("port", |v: &i32| match *v > 0 {
+            true => Ok(()),
+            false => Err(format!("{} is not a port", v)),
+        });
+        let bad = |v: i32| {
+            Err(ScopeError::Invalid(
+                "port".to_string(),
+                format!("{} is not a port", v),
+            ))
+        };
+        // only what is set from c and below is checked
+        assert_eq!(root.set_local_checked("port".to_string(), -1), Ok(()));
+        assert_eq!(c.declare("port", 0), bad(0));
+        assert_eq!(root.set_local_checked("port".to_string(), 80), Ok(()));
+        assert_eq!(c.set_checked("port".to_string(), -80), bad(-80));
+        c.child().set("port".to_string(), -2);
+        assert_eq!(root.get("port"), Some(80));
+
+        // and changes in place, which are undone
+        assert_eq!(c.update("port", |v| *v = -5), None);
+        assert_eq!(c.update_checked("port", |v| *v = -9), bad(-9));
+        assert_eq!(c.decr("port"), Some(79));
+        assert_eq!(root.get("port"), Some(79));
+        assert_eq!(root.update("port", |v| *v = -1), Some(()));
+
+        assert!(!root.remove_validator("port"));
+        assert!(c.remove_validator("port"));
+        assert!(!c.remove_validator("port"));
+        c.set_local("port".to_string(), -3);
+        assert_eq!(c.get("port"), Some(-3));
+    }
+}
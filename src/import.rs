@@ -0,0 +1,197 @@
+use crate::{BorrowError, PScope, Scope, Sym};
+use std::rc::Rc;
+
+/// The scope an import reads a key from, and the key's name there
+type Source<T> = (PScope<T>, Rc<str>);
+
+/// The bindings a frame sees from other scopes
+pub(crate) struct Imports<T> {
+    /// Local name to the scope and name it reads
+    named: Vec<(Sym, PScope<T>, Rc<str>)>,
+    all: Vec<PScope<T>>,
+}
+
+impl<T> Default for Imports<T> {
+    fn default() -> Self {
+        Imports {
+            named: Vec::new(),
+            all: Vec::new(),
+        }
+    }
+}
+
+impl<T> Clone for Imports<T> {
+    fn clone(&self) -> Self {
+        Imports {
+            named: self.named.clone(),
+            all: self.all.clone(),
+        }
+    }
+}
+
+impl<T> Imports<T> {
+    pub fn is_empty(&self) -> bool {
+        self.named.is_empty() && self.all.is_empty()
+    }
+
+    /// The same imports, each read from f of the scope it was from
+    pub fn map<F: FnMut(&PScope<T>) -> PScope<T>>(self, mut f: F) -> Self {
+        Imports {
+            named: (self.named.into_iter())
+                .map(|(l, s, n)| (l, f(&s), n))
+                .collect(),
+            all: self.all.iter().map(f).collect(),
+        }
+    }
+}
+
+impl<T> Scope<T> {
+    /// Where an import in this frame finds k, if one does right now
+    pub(crate) fn import_of(&self, k: Sym) -> Option<Source<T>> {
+        let im = self.extras()?.imports.borrow();
+        if im.is_empty() {
+            return None;
+        }
+        if let Some((_, s, n)) = im.named.iter().find(|(l, s, n)| *l == k && s.contains(n)) {
+            return Some((s.clone(), n.clone()));
+        }
+        let name = self.name(k);
+        let s = im.all.iter().find(|s| s.contains(&name))?;
+        Some((s.clone(), name))
+    }
+
+    /// Like import_of, failing instead of panicking on a borrowed frame
+    pub(crate) fn try_import_of(&self, k: Sym) -> Result<Option<Source<T>>, BorrowError> {
+        let im = match self.extras() {
+            Some(x) => x.imports.borrow(),
+            None => return Ok(None),
+        };
+        for (l, s, n) in &im.named {
+            if *l == k && s.p.try_has(n)? {
+                return Ok(Some((s.clone(), n.clone())));
+            }
+        }
+        let name = self.name(k);
+        for s in &im.all {
+            if s.p.try_has(&name)? {
+                return Ok(Some((s.clone(), name)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The local names of everything imported into this frame that has a value
+    pub(crate) fn imported_keys(&self) -> Vec<Sym> {
+        let im = match self.extras() {
+            Some(x) => x.imports.borrow(),
+            None => return Vec::new(),
+        };
+        let mut res: Vec<Sym> = (im.named.iter())
+            .filter(|(_, s, n)| s.contains(n))
+            .map(|(l, _, _)| *l)
+            .collect();
+        for s in &im.all {
+            res.extend(
+                s.p.visible_keys()
+                    .into_iter()
+                    .map(|k| self.intern(&s.p.name(k))),
+            );
+        }
+        res
+    }
+}
+
+impl<T: Clone> Scope<T> {
+    /// Like imported_keys, with the values
+    pub(crate) fn imported_values(&self) -> Vec<(Sym, T)> {
+        let im = match self.extras() {
+            Some(x) => x.imports.borrow(),
+            None => return Vec::new(),
+        };
+        let mut res: Vec<(Sym, T)> = (im.named.iter())
+            .filter_map(|(l, s, n)| Some((*l, s.get(n)?)))
+            .collect();
+        for s in &im.all {
+            res.extend(s.flatten().into_iter().map(|(k, v)| (self.intern(&k), v)));
+        }
+        res
+    }
+}
+
+/// Imports make bindings of another scope visible in this frame by reference,
+/// so reads see later changes, and `set` and `update` write through to the source.
+/// They come after the frame's own bindings, named imports before `import_all`.
+/// `get_ref`, `entry` and the checked methods do not see them, `ScopeTree` does not keep them,
+/// and two scopes importing a name from each other will loop forever.
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let lib = PScope::new();
+/// lib.set_local("a".to_string(), 1);
+/// lib.set_local("c".to_string(), 3);
+///
+/// let root = PScope::new();
+/// root.import(&lib, &[("a", "b")]);
+/// let c = root.child();
+/// assert_eq!(c.get("b"), Some(1));
+/// assert_eq!(c.get("a"), None);
+///
+/// c.set("b".to_string(), 2);
+/// assert_eq!(lib.get("a"), Some(2));
+///
+/// root.import_all(&lib);
+/// assert_eq!(c.get("c"), Some(3));
+/// ```
+impl<T> PScope<T> {
+    /// Each pair is the name in from, then the name to see it as here
+    pub fn import(&self, from: &PScope<T>, names: &[(&str, &str)]) {
+        let mut im = self.p.extras_init().imports.borrow_mut();
+        for (src, local) in names {
+            let l = self.p.intern(local);
+            im.named.retain(|(k, _, _)| *k != l);
+            im.named.push((l, from.clone(), Rc::from(*src)));
+        }
+        self.p.tree().imported.set(true);
+        self.p.touch();
+    }
+
+    /// Every binding visible from from, under its own name
+    pub fn import_all(&self, from: &PScope<T>) {
+        (self.p.extras_init().imports.borrow_mut())
+            .all
+            .push(from.clone());
+        let t = self.p.tree();
+        t.imports_all.set(true);
+        t.imported.set(true);
+        self.p.touch();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn imports_are_live() {
+        let lib = PScope::new();
+        lib.set_local("x".to_string(), 1);
+        let glob = PScope::new();
+        glob.set_local("x".to_string(), 10);
+        glob.set_local("y".to_string(), 20);
+
+        let root = PScope::new();
+        root.import_all(&glob);
+        root.import(&lib, &[("x", "x")]);
+        let c = root.child();
+        assert_eq!(c.get("x"), Some(1));
+        assert_eq!(c.get("y"), Some(20));
+        assert_eq!(c.update("y", |n| *n += 1), Some(()));
+        assert_eq!(glob.get("y"), Some(21));
+
+        lib.remove("x");
+        assert_eq!(c.get("x"), Some(10));
+        root.set_local("x".to_string(), 0);
+        assert_eq!(c.get("x"), Some(0));
+        assert_eq!(c.flatten().len(), 2);
+        assert_eq!(c.suggest("yy", 1), vec!["y"]);
+    }
+}
@@ -0,0 +1,356 @@
+use crate::{PScope, Scope, Sym, UnknownFrame};
+use std::collections::{BTreeMap, HashMap};
+
+/// What a `ScopeEvent` did
+#[derive(Clone, Debug, PartialEq)]
+pub enum EventKind<T> {
+    /// The frame was first seen, as a child of parent, or as the root.
+    /// It comes before any other event on the frame.
+    Frame {
+        parent: Option<u64>,
+    },
+    /// key was given value in the frame
+    Set {
+        key: String,
+        value: T,
+    },
+    /// key was changed in place, and now holds value
+    Update {
+        key: String,
+        value: T,
+    },
+    Remove {
+        key: String,
+    },
+    /// Everything set on the frame was dropped, as by `reset_local`
+    Clear,
+}
+
+/// One change recorded by the journal, see `PScope::enable_journal`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScopeEvent<T> {
+    /// Counts up from 0 across the whole tree, and is never reused
+    pub seq: u64,
+    /// Numbers each frame in the order the journal first saw it, see `journal_id`
+    pub frame: u64,
+    pub kind: EventKind<T>,
+}
+
+pub(crate) struct Journal<T> {
+    copy: fn(&T) -> T,
+    events: Vec<ScopeEvent<T>>,
+    next_seq: u64,
+    next_frame: u64,
+    /// By address, removed when the frame is dropped, so never reused
+    ids: HashMap<*const Scope<T>, u64>,
+}
+
+impl<T> Journal<T> {
+    fn push(&mut self, frame: u64, kind: EventKind<T>) {
+        self.events.push(ScopeEvent {
+            seq: self.next_seq,
+            frame,
+            kind,
+        });
+        self.next_seq += 1;
+    }
+}
+
+impl<T> Scope<T> {
+    /// The id of this frame in j, first recording it and any parents it needs,
+    /// outermost first. A loop, not recursion, so deep chains can't overflow the stack.
+    fn frame_id(&self, j: &mut Journal<T>) -> u64 {
+        let mut parent = None;
+        let mut unseen = Vec::new();
+        for f in self.frames() {
+            if let Some(id) = j.ids.get(&(f as *const _)) {
+                parent = Some(*id);
+                break;
+            }
+            unseen.push(f);
+        }
+        for f in unseen.into_iter().rev() {
+            let id = j.next_frame;
+            j.next_frame += 1;
+            j.ids.insert(f, id);
+            j.push(id, EventKind::Frame { parent });
+            parent = Some(id);
+        }
+        parent.expect("this frame was just recorded, if it was not already")
+    }
+
+    fn journal<F: FnOnce(&mut Journal<T>, u64)>(&self, f: F) {
+        if let Some(j) = self.hooks().journal.borrow_mut().as_mut() {
+            let id = self.frame_id(j);
+            f(j, id);
+        }
+    }
+
+    /// Records the current value of k here, as a Set or an Update
+    pub(crate) fn journal_value(&self, k: Sym, update: bool) {
+        self.journal(|j, id| {
+            let value = match self.data().get(k) {
+                Some(v) => (j.copy)(v),
+                None => return,
+            };
+            let key = self.name(k).to_string();
+            let kind = match update {
+                true => EventKind::Update { key, value },
+                false => EventKind::Set { key, value },
+            };
+            j.push(id, kind);
+        });
+    }
+
+    pub(crate) fn journal_removed(&self, k: Sym) {
+        let key = self.name(k).to_string();
+        self.journal(|j, id| j.push(id, EventKind::Remove { key }));
+    }
+
+    pub(crate) fn journal_cleared(&self) {
+        self.journal(|j, id| j.push(id, EventKind::Clear));
+    }
+
+    /// Lets the address of a dropped frame be used again
+    pub(crate) fn journal_dropped(&self) {
+        if self.root.is_none() {
+            return;
+        }
+        if let Some(j) = self.hooks().journal.borrow_mut().as_mut() {
+            j.ids.remove(&(self as *const _));
+        }
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::{EventKind, PScope};
+/// let root = PScope::new();
+/// root.set_local("a".to_string(), 1);
+/// root.enable_journal();
+/// let c = root.child();
+/// c.set("a".to_string(), 2);
+/// c.set_local("b".to_string(), 3);
+/// c.update("b", |n| *n += 1);
+///
+/// let ev = root.journal();
+/// assert_eq!(ev.len(), 5);
+/// assert_eq!(ev[0].kind, EventKind::Frame { parent: None });
+/// assert_eq!(ev[1].kind, EventKind::Set { key: "a".to_string(), value: 2 });
+/// assert_eq!(ev[2].kind, EventKind::Frame { parent: Some(0) });
+/// assert_eq!(ev[4].kind, EventKind::Update { key: "b".to_string(), value: 4 });
+/// assert_eq!(Some(ev[4].frame), c.journal_id());
+/// ```
+impl<T: Clone> PScope<T> {
+    /// Starts recording every change anywhere in the tree, with copies of
+    /// the values set. Each frame's id and parent are recorded the first time
+    /// it is changed. Restoring a snapshot or a tree is not recorded,
+    /// and forks don't share the journal. Does nothing if already on.
+    pub fn enable_journal(&self) {
+        let mut j = self.p.hooks().journal.borrow_mut();
+        if j.is_none() {
+            *j = Some(Journal {
+                copy: T::clone,
+                events: Vec::new(),
+                next_seq: 0,
+                next_frame: 0,
+                ids: HashMap::new(),
+            });
+        }
+    }
+
+    /// Every event recorded and not yet drained
+    pub fn journal(&self) -> Vec<ScopeEvent<T>> {
+        match self.p.hooks().journal.borrow().as_ref() {
+            Some(j) => j.events.clone(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl<T> PScope<T> {
+    /// Stops recording and drops the events not yet drained
+    pub fn disable_journal(&self) {
+        *self.p.hooks().journal.borrow_mut() = None;
+    }
+
+    pub fn is_journaling(&self) -> bool {
+        self.p.hooks().journal.borrow().is_some()
+    }
+
+    /// Takes the events recorded since the last drain, leaving the journal on
+    pub fn drain_journal(&self) -> Vec<ScopeEvent<T>> {
+        match self.p.hooks().journal.borrow_mut().as_mut() {
+            Some(j) => std::mem::take(&mut j.events),
+            None => Vec::new(),
+        }
+    }
+
+    /// The id this frame's events carry, recording the frame now if the
+    /// journal has not seen it yet. None if the journal is off.
+    pub fn journal_id(&self) -> Option<u64> {
+        let mut j = self.p.hooks().journal.borrow_mut();
+        Some(self.p.frame_id(j.as_mut()?))
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// root.enable_journal();
+/// let c = root.child();
+/// c.set_local("x".to_string(), 1);
+/// root.set_local("y".to_string(), 2);
+///
+/// let frames = PScope::replay(root.journal()).unwrap();
+/// let c2 = &frames[&c.journal_id().unwrap()];
+/// assert_eq!(c2.flatten(), c.flatten());
+/// assert!(PScope::ptr_eq(&c2.root(), &frames[&0]));
+/// ```
+impl<T> PScope<T> {
+    /// Rebuilds the frames a journal saw, keyed by their ids, with default
+    /// options. Frames are made as plain children, so the kind of frame,
+    /// such as a function's, is not kept, nor is anything set before the
+    /// journal started. Events may come from several drains, if in order.
+    pub fn replay<I: IntoIterator<Item = ScopeEvent<T>>>(
+        events: I,
+    ) -> Result<BTreeMap<u64, PScope<T>>, UnknownFrame> {
+        let mut frames: BTreeMap<u64, PScope<T>> = BTreeMap::new();
+        for e in events {
+            let f = match e.kind {
+                EventKind::Frame { parent } => {
+                    let f = match parent {
+                        Some(p) => frames.get(&p).ok_or(UnknownFrame(p))?.child(),
+                        None => PScope::new(),
+                    };
+                    frames.insert(e.frame, f);
+                    continue;
+                }
+                _ => frames.get(&e.frame).ok_or(UnknownFrame(e.frame))?,
+            };
+            match e.kind {
+                EventKind::Set { key, value } | EventKind::Update { key, value } => {
+                    f.set_local(key, value)
+                }
+                EventKind::Remove { key } => drop(f.remove_local(&key)),
+                EventKind::Clear => f.reset_local(),
+                EventKind::Frame { .. } => {}
+            }
+        }
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn journal_records_each_change() {
+        let root = PScope::new();
+        assert!(root.journal_id().is_none());
+        root.enable_journal();
+        root.set_local("a".to_string(), 1);
+        {
+            let c = root.child();
+            c.set_local("t".to_string(), 0);
+            assert_eq!(c.journal_id(), Some(1));
+        }
+        let c = root.child();
+        c.set_local("x".to_string(), 5);
+        root.remove("a");
+        c.reset_local();
+        let kinds: Vec<_> = root
+            .drain_journal()
+            .into_iter()
+            .map(|e| (e.seq, e.frame, e.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (0, 0, EventKind::Frame { parent: None }),
+                (
+                    1,
+                    0,
+                    EventKind::Set {
+                        key: "a".to_string(),
+                        value: 1
+                    }
+                ),
+                (2, 1, EventKind::Frame { parent: Some(0) }),
+                (
+                    3,
+                    1,
+                    EventKind::Set {
+                        key: "t".to_string(),
+                        value: 0
+                    }
+                ),
+                (4, 2, EventKind::Frame { parent: Some(0) }),
+                (
+                    5,
+                    2,
+                    EventKind::Set {
+                        key: "x".to_string(),
+                        value: 5
+                    }
+                ),
+                (
+                    6,
+                    0,
+                    EventKind::Remove {
+                        key: "a".to_string()
+                    }
+                ),
+                (7, 2, EventKind::Clear),
+            ]
+        );
+        c.set_local("y".to_string(), 1);
+        assert_eq!(root.journal()[0].seq, 8);
+        root.disable_journal();
+        assert!(!root.is_journaling());
+        assert!(root.journal().is_empty());
+    }
+
+    #[test]
+    fn replay_rebuilds_every_frame() {
+        let root = PScope::new();
+        root.enable_journal();
+        let a = root.child();
+        let b = a.child();
+        let events: Vec<_> = (0..3)
+            .map(|n| {
+                a.set_local("n".to_string(), n);
+                b.set("n".to_string(), n * 10);
+                b.set_local(format!("b{}", n), n);
+                root.drain_journal()
+            })
+            .collect();
+        b.update("b1", |v| *v += 5);
+        b.remove_local("b0");
+        a.reset_local();
+        let tail = root.drain_journal();
+
+        let frames = PScope::replay(events.into_iter().flatten().chain(tail.clone())).unwrap();
+        assert_eq!(frames.len(), 3);
+        for f in [&root, &a, &b] {
+            assert_eq!(frames[&f.journal_id().unwrap()].flatten(), f.flatten());
+        }
+        assert_eq!(PScope::replay(tail).err(), Some(UnknownFrame(2)));
+    }
+
+    #[test]
+    fn deep_chains_get_ids() {
+        let root = PScope::new();
+        root.enable_journal();
+        let mut leaf = root.child();
+        for _ in 0..100_000 {
+            leaf = leaf.child();
+        }
+        leaf.set_local("a".to_string(), 1);
+        assert_eq!(leaf.journal_id(), Some(100_001));
+        let events = root.drain_journal();
+        assert_eq!(events.len(), 100_003);
+        assert_eq!(events[1].kind, EventKind::Frame { parent: Some(0) });
+    }
+}
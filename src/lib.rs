@@ -1,84 +1,703 @@
-use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::ops::{Deref, DerefMut};
 use std::rc::Rc;
+use std::time::Instant;
 
+pub mod arena;
+mod args;
+#[cfg(feature = "tokio")]
+pub mod async_scope;
+mod batch;
+mod cache;
+mod closure;
+mod computed;
+#[cfg(any(feature = "toml", feature = "json", feature = "dotenvy"))]
+mod config;
+mod dirty;
+mod entry;
+mod env;
+mod expiry;
+#[cfg(all(
+    feature = "watching",
+    any(feature = "toml", feature = "json", feature = "dotenvy")
+))]
+mod file_watch;
+mod fork;
+mod func;
+mod global;
+mod guard;
+mod hash;
+mod history;
+mod hooks;
+mod import;
+mod journal;
+mod lru;
+mod memory;
+mod meta;
+mod module;
+mod notify;
+mod numeric;
+mod once;
+mod options;
+pub mod owned;
+#[cfg(feature = "rpds")]
+pub mod persistent;
+mod pool;
+pub mod scope_actor;
+mod slot;
+mod snapshot;
+pub mod stack;
+mod stats;
+mod store;
+pub mod strict;
+mod suggest;
+mod sym;
+#[cfg(feature = "sync")]
+pub mod sync;
+mod thread_root;
+mod tree;
+mod txn;
+mod undo;
+#[cfg(feature = "value")]
+pub mod value;
+mod versions;
+mod watch;
+pub use batch::ScopeOp;
+use cache::KeyCache;
+pub use closure::Closure;
+#[cfg(any(feature = "toml", feature = "json", feature = "dotenvy"))]
+pub use config::ConfigError;
+pub use entry::ScopeEntry;
+#[cfg(all(
+    feature = "watching",
+    any(feature = "toml", feature = "json", feature = "dotenvy")
+))]
+pub use file_watch::WatchedFile;
+use fork::Bindings;
+pub use global::GlobalRef;
+pub use guard::ScopeRef;
+pub use hash::{Hashed, NameHasher};
+use hooks::{Hooks, Refused, Validator};
+use import::Imports;
+pub use journal::{EventKind, ScopeEvent};
+use lru::Lru;
+pub use meta::Meta;
+pub use notify::SubId;
+use notify::Subscriber;
+pub use numeric::{NumericScope, One};
+pub use options::{ScopeOptions, SetMode};
+pub use pool::ScopePool;
+pub use slot::Slot;
+pub use snapshot::ScopeSnapshot;
+use stats::Counters;
+pub use stats::ScopeStats;
+pub use store::Storage;
+use store::Store;
+pub use strict::{AlreadySet, BorrowError, ScopeError, UnknownFrame};
+use sym::Interner;
+pub use sym::Sym;
+pub use tree::{OwnedScopeTree, ScopeTree};
+pub use txn::ScopeTxn;
+pub use watch::{ScopeWatcher, Watchpoint};
+
+/// Only the bindings sit behind the RefCell, the links never change once a frame is made,
+/// which lets a borrow of any frame in the chain live as long as the handle it came from.
 struct Scope<T> {
-    data: BTreeMap<String, T>,
+    /// Read through data() and data_mut(), which know about forks
+    data: RefCell<Bindings<T>>,
     parent: Option<PScope<T>>,
     root: Option<PScope<T>>,
+    /// Only set on the root, boxed so child frames don't pay for it
+    tree: Option<Box<TreeData>>,
+    attrs: RefCell<Attrs>,
+    frozen: Cell<bool>,
+    /// Number of parents above this frame
+    depth: usize,
+    cache: RefCell<KeyCache>,
+    /// The generation and distance up to the nearest parent that defines anything
+    skip: Cell<Option<(u64, usize)>>,
+    /// Moves on whenever anything in this frame may have changed
+    version: Cell<u64>,
+    /// Set on frames made by child_function
+    boundary: bool,
+    /// Only set on the root, where dropped frames leave their maps
+    pool: Option<ScopePool<T>>,
+    /// Only set on the root
+    hooks: Option<Box<Hooks<T>>>,
+    /// Made the first time one of its parts is used, so most frames never pay for it
+    extras: OnceCell<Box<Extras<T>>>,
+}
+
+/// The parts of a frame that few frames use
+struct Extras<T> {
+    subs: RefCell<Vec<Subscriber<T>>>,
+    /// The dynamic parent of frames made by child_dynamic
+    caller: Option<PScope<T>>,
+    /// The label given to child_named
+    name: Option<String>,
+    modules: RefCell<BTreeMap<String, PScope<T>>>,
+    imports: RefCell<Imports<T>>,
+    /// Call parameters by position, used on function frames and the root
+    positionals: RefCell<Vec<Option<T>>>,
+    /// Only set on frames made by child_lru
+    lru: Option<Box<Lru>>,
+    /// Checks on values set from this frame, see set_validator
+    valid: RefCell<BTreeMap<Sym, Validator<T>>>,
+}
+
+impl<T> Default for Extras<T> {
+    fn default() -> Self {
+        Extras {
+            subs: RefCell::new(Vec::new()),
+            caller: None,
+            name: None,
+            modules: RefCell::new(BTreeMap::new()),
+            imports: RefCell::new(Imports::default()),
+            positionals: RefCell::new(Vec::new()),
+            lru: None,
+            valid: RefCell::new(BTreeMap::new()),
+        }
+    }
+}
+
+/// Settings and state shared by a whole tree, kept on its root
+#[derive(Default)]
+struct TreeData {
+    /// Counts bubbling subscribers anywhere in the tree
+    bubbling: Cell<usize>,
+    /// Shared with any forks, so a Sym means the same in each
+    names: Rc<RefCell<Interner>>,
+    set_mode: SetMode,
+    max_depth: Option<usize>,
+    /// Only when made with stats on
+    stats: Option<RefCell<Counters>>,
+    /// Only when made with track_changes, counts every change in the tree
+    changes: Option<Cell<u64>>,
+    /// Moves on whenever a frame gains or loses a key, in this tree or any fork of it
+    generation: Rc<Cell<u64>>,
+    /// Set once any frame has used import_all
+    imports_all: Cell<bool>,
+    /// Set once any frame has imported anything
+    imported: Cell<bool>,
+    /// Set once any binding has been given a ttl
+    expiring: Cell<bool>,
+    /// Set once any frame has been given a validator
+    validated: Cell<bool>,
+    /// Work put off by subscribers until the change that fired them is done
+    deferred: RefCell<VecDeque<Box<dyn FnOnce()>>>,
+}
+
+impl TreeData {
+    fn new(opts: ScopeOptions) -> Self {
+        TreeData {
+            names: Rc::new(RefCell::new(Interner::new(opts.hasher))),
+            set_mode: opts.set_mode,
+            max_depth: opts.max_depth,
+            stats: opts.stats.then(RefCell::default),
+            changes: opts.track_changes.then(Cell::default),
+            ..Default::default()
+        }
+    }
+}
+
+/// Settings for bindings in a frame, kept apart from the values
+/// and only allocated once one is used, as most frames never use them.
+#[derive(Clone, Default)]
+struct Attrs(Option<Box<AttrSets>>);
+
+#[derive(Clone, Default)]
+struct AttrSets {
+    consts: BTreeSet<Sym>,
+    /// Declared here without a value yet
+    uninit: BTreeSet<Sym>,
+    /// Tombstones hiding any outer binding
+    masked: BTreeSet<Sym>,
+    /// Set from this frame goes to the root
+    global: BTreeSet<Sym>,
+    /// Set from this frame goes to the parent chain
+    nonlocal: BTreeSet<Sym>,
+    meta: BTreeMap<Sym, Meta>,
+    /// Moved into the parent when the frame goes away
+    exports: BTreeSet<Sym>,
+    /// Passed on to child processes, see set_exported
+    env: BTreeSet<Sym>,
+    /// The formula each computed binding was last given, and its deps, see set_computed
+    computed: BTreeMap<Sym, (u64, Vec<Sym>)>,
+    /// When each key last changed here, with track_changes
+    stamps: BTreeMap<Sym, u64>,
+    /// When each binding set with a ttl stops being visible
+    expires: BTreeMap<Sym, Instant>,
+}
+
+static NO_ATTRS: AttrSets = AttrSets {
+    consts: BTreeSet::new(),
+    uninit: BTreeSet::new(),
+    masked: BTreeSet::new(),
+    global: BTreeSet::new(),
+    nonlocal: BTreeSet::new(),
+    meta: BTreeMap::new(),
+    exports: BTreeSet::new(),
+    env: BTreeSet::new(),
+    computed: BTreeMap::new(),
+    stamps: BTreeMap::new(),
+    expires: BTreeMap::new(),
+};
+
+impl Deref for Attrs {
+    type Target = AttrSets;
+    fn deref(&self) -> &AttrSets {
+        self.0.as_deref().unwrap_or(&NO_ATTRS)
+    }
+}
+
+impl DerefMut for Attrs {
+    fn deref_mut(&mut self) -> &mut AttrSets {
+        self.0.get_or_insert_with(Default::default)
+    }
+}
+
+impl Attrs {
+    /// The sets, only if they have been allocated, for removing from
+    fn existing(&mut self) -> Option<&mut AttrSets> {
+        self.0.as_deref_mut()
+    }
+}
+
+impl AttrSets {
+    /// Whether k is defined here without a value
+    fn is_hole(&self, k: Sym) -> bool {
+        self.uninit.contains(&k) || self.masked.contains(&k)
+    }
 }
 
 impl<T> Scope<T> {
-    fn new() -> Self {
+    /// A new root
+    fn new(opts: ScopeOptions) -> Self {
+        let mut s = Self::frame(opts.storage);
+        s.tree = Some(Box::new(TreeData::new(opts)));
+        s.hooks = Some(Box::default());
+        s
+    }
+
+    /// A frame with no links, and no tree data
+    fn frame(storage: Storage) -> Self {
         Self {
-            data: BTreeMap::new(),
+            data: RefCell::new(Store::new(storage).into()),
             parent: None,
             root: None,
+            tree: None,
+            attrs: RefCell::new(Attrs::default()),
+            frozen: Cell::new(false),
+            depth: 0,
+            cache: RefCell::new(KeyCache::default()),
+            skip: Cell::new(None),
+            version: Cell::new(0),
+            boundary: false,
+            pool: None,
+            hooks: None,
+            extras: OnceCell::new(),
         }
     }
-    pub fn set_local(&mut self, id: String, val: T) {
-        self.data.insert(id, val);
+
+    /// The rarely used parts of this frame, if any have been used
+    fn extras(&self) -> Option<&Extras<T>> {
+        self.extras.get().map(|x| &**x)
+    }
+
+    /// The rarely used parts of this frame, made now if they were not yet
+    fn extras_init(&self) -> &Extras<T> {
+        self.extras.get_or_init(Box::default)
     }
 
-    pub fn set_global(&mut self, id: String, val: T) {
+    /// Like extras_init, for a frame that is still being made
+    fn extras_mut(&mut self) -> &mut Extras<T> {
+        self.extras_init();
+        self.extras.get_mut().expect("just made")
+    }
+
+    fn root_frame(&self) -> &Scope<T> {
         match &self.root {
-            Some(v) => v.set(id, val),
-            None => drop(self.data.insert(id, val)),
+            Some(r) => &r.p,
+            None => self,
+        }
+    }
+
+    fn tree(&self) -> &TreeData {
+        (self.root_frame().tree.as_ref()).expect("every root has tree data")
+    }
+
+    pub fn set_local(&self, id: Sym, mut val: T) {
+        if self.frozen.get() || !self.allow_set(id, &mut val) {
+            return;
         }
+        self.store_local(id, val);
     }
 
-    pub fn set(&mut self, id: String, val: T) {
-        if let Some(_) = self.data.get(&id) {
-            self.data.insert(id, val);
+    /// set_local, once the hooks have passed val
+    fn store_local(&self, id: Sym, val: T) {
+        if self.frozen.get() {
             return;
         }
-        match &self.parent {
-            Some(p) => {
-                if let Some(v) = p.try_replace(&id, val) {
-                    self.data.insert(id, v);
+        let old = self.data_mut().insert(id, val);
+        if old.is_none() {
+            self.touch();
+        }
+        self.fill(id);
+        self.undo_value(id, old.as_ref());
+        self.changed(id);
+    }
+
+    /// Clears any mark that k has no value here, once it has one
+    fn fill(&self, k: Sym) {
+        if let Some(a) = self.attrs.borrow_mut().existing() {
+            a.uninit.remove(&k);
+            a.masked.remove(&k);
+        }
+    }
+
+    pub fn set_global(&self, id: Sym, val: T) {
+        self.root_frame().set(id, val);
+    }
+
+    pub fn set(&self, id: Sym, mut val: T) {
+        if self.allow_set(id, &mut val) {
+            self.assign(id, val);
+        }
+    }
+
+    /// set, once the hooks have passed val
+    fn assign(&self, id: Sym, val: T) {
+        let (global, nonlocal) = {
+            let a = self.attrs.borrow();
+            (a.global.contains(&id), a.nonlocal.contains(&id))
+        };
+        if global {
+            return self.root_frame().store_local(id, val);
+        }
+        if let (true, Some(p)) = (nonlocal, &self.parent) {
+            return p.p.assign(id, val);
+        }
+        if let (Some(v), Some(f)) = (self.try_replace(id, val), self.create_target()) {
+            f.store_local(id, v);
+        }
+    }
+
+    /// The frame that set would create a new binding in
+    fn create_target(&self) -> Option<&Scope<T>> {
+        match self.tree().set_mode {
+            SetMode::CreateLocal => Some(self),
+            SetMode::CreateInRoot => Some(self.root_frame()),
+            SetMode::Error => None,
+        }
+    }
+
+    /// Some<T> means k is not in this frame
+    fn replace_local(&self, k: Sym, val: T) -> Option<T> {
+        let mut d = self.data_mut();
+        let old = match d.get_mut(k) {
+            Some(v) => std::mem::replace(v, val),
+            None if self.attrs.borrow().is_hole(k) => {
+                d.insert(k, val);
+                drop(d);
+                self.fill(k);
+                self.undo_value(k, None);
+                self.changed(k);
+                return None;
+            }
+            None => return Some(val),
+        };
+        drop(d);
+        self.undo_value(k, Some(&old));
+        self.changed(k);
+        None
+    }
+
+    /// Some<T> means not set, use T, to insert in local scope.
+    /// A frozen frame defining id stops the search, so the caller shadows it.
+    fn try_replace(&self, id: Sym, mut val: T) -> Option<T> {
+        for f in self.frames() {
+            if !f.frozen.get() {
+                val = f.replace_local(id, val)?;
+                if let Some((s, n)) = f.import_of(id) {
+                    val = s.try_replace(&n, val)?;
+                    break;
                 }
+            } else if f.defines(id) {
+                break;
             }
-            None => drop(self.data.insert(id, val)),
         }
+        Some(val)
+    }
+
+    /// The value is taken out of its frame while f runs, so f may freely read and
+    /// write the scope, though k itself will appear unset until f returns.
+    /// None if a hook refuses the change, which is then undone.
+    pub fn update<F: FnOnce(&mut T) -> A, A>(&self, k: Sym, f: F) -> Option<A> {
+        let (res, ok) = self.modify(k, f)?;
+        ok.ok().map(|_| res)
+    }
+
+    /// Like update, giving f's result even if the change was undone, with why
+    fn modify<F: FnOnce(&mut T) -> A, A>(&self, k: Sym, f: F) -> Option<(A, Result<(), Refused>)> {
+        let o = self.owner(k)?;
+        if o.data().contains_key(k) {
+            return o.modify_local(self, k, f);
+        }
+        let (s, n) = o.import_of(k)?;
+        s.p.modify(s.p.sym(&n)?, f)
     }
 
-    /// Some<T> means not set, use T, to insert in local scope
-    fn try_replace(&mut self, id: &str, val: T) -> Option<T> {
-        if let Some(v) = self.data.get_mut(id) {
-            *v = val;
+    /// Changes k in this frame, with the hooks and validators seen from by
+    fn update_local<F: FnOnce(&mut T) -> A, A>(&self, by: &Scope<T>, k: Sym, f: F) -> Option<A> {
+        let (res, ok) = self.modify_local(by, k, f)?;
+        ok.ok().map(|_| res)
+    }
+
+    fn modify_local<F: FnOnce(&mut T) -> A, A>(
+        &self,
+        by: &Scope<T>,
+        k: Sym,
+        f: F,
+    ) -> Option<(A, Result<(), Refused>)> {
+        if self.frozen.get() {
             return None;
         }
-        match &self.parent {
-            Some(p) => p.p.borrow_mut().try_replace(id, val),
-            None => Some(val),
+        let old = self.keep_for_check(by, k);
+        let v = self.data_mut().remove(k)?;
+        self.touch();
+        self.undo_value(k, Some(&v));
+        let mut held = PutBack {
+            frame: self,
+            entry: Some((k, v)),
+        };
+        let res = held.entry.as_mut().map(|(_, v)| f(v))?;
+        drop(held);
+        let ok = self.check_change(by, k, old);
+        if ok.is_ok() {
+            self.updated(k);
+        }
+        Some((res, ok))
+    }
+
+    pub fn remove_local(&self, k: Sym) -> Option<T> {
+        if self.frozen.get() {
+            return None;
+        }
+        let res = self.data_mut().remove(k);
+        if let Some(v) = &res {
+            self.touch();
+            self.forget(k, v);
+        }
+        res
+    }
+
+    /// Drops the settings of k once its value v has been removed, and tells subscribers
+    fn forget(&self, k: Sym, v: &T) {
+        if let Some(a) = self.attrs.borrow_mut().existing() {
+            a.consts.remove(&k);
+            a.meta.remove(&k);
+            a.env.remove(&k);
+            a.computed.remove(&k);
+            a.expires.remove(&k);
+        }
+        self.stamp(k);
+        self.lru_forget(k);
+        self.undo_value(k, Some(v));
+        self.journal_removed(k);
+        self.notify(k, v);
+        self.run_deferred();
+    }
+
+    /// Removes from the nearest frame that defines k
+    pub fn remove(&self, k: Sym) -> Option<T> {
+        self.owner(k)?.remove_local(k)
+    }
+
+    /// This frame then each of its parents, walked without recursion
+    fn frames(&self) -> impl Iterator<Item = &Scope<T>> {
+        std::iter::successors(Some(self), |s| s.parent.as_ref().map(|p| &*p.p))
+    }
+
+    /// Whether k is defined in this frame, even if it has no value
+    fn defines(&self, k: Sym) -> bool {
+        self.data().contains_key(k) || self.attrs.borrow().is_hole(k) || self.import_of(k).is_some()
+    }
+
+    /// The nearest frame that defines k, which may hold it without a value
+    fn owner(&self, k: Sym) -> Option<&Scope<T>> {
+        self.purge_key(k);
+        let res = self.find_owner(k).or_else(|| self.copy_from_env(k));
+        if let Some(o) = res {
+            o.lru_use(k);
+        }
+        res
+    }
+
+    fn find_owner(&self, k: Sym) -> Option<&Scope<T>> {
+        if let Some(up) = self.cached(k) {
+            self.record(k, 0, up.is_some());
+            return self.frames().nth(up?);
+        }
+        let mut walked = 0;
+        let res = self.used_frames().find(|f| {
+            walked += 1;
+            f.defines(k)
+        });
+        self.record(k, walked, res.is_some());
+        self.remember(k, walked, res.map(|o| self.depth - o.depth));
+        res
+    }
+
+    /// The nearest frame that defines k, by name
+    fn owner_str(&self, k: &str) -> Option<&Scope<T>> {
+        self.owner(self.sym(k)?)
+    }
+
+    fn contains_local(&self, k: &str) -> bool {
+        match self.sym(k) {
+            Some(s) => self.data().contains_key(s),
+            None => false,
+        }
+    }
+
+    fn defines_local(&self, k: &str) -> bool {
+        match self.sym(k) {
+            Some(s) => self.defines(s),
+            None => false,
         }
     }
 
-    pub fn update<F: Fn(&mut T) -> A, A>(&mut self, k: &str, f: F) -> Option<A> {
-        if let Some(v) = self.data.get_mut(k) {
+    pub fn with<R, F: FnOnce(&T) -> R>(&self, k: Sym, f: F) -> Option<R> {
+        if !self.allow_get(k) {
+            return None;
+        }
+        let o = self.owner(k)?;
+        if let Some(v) = o.data().get(k) {
             return Some(f(v));
         }
-        match &self.parent {
-            Some(v) => v.update(k, f),
-            None => None,
+        let (s, n) = o.import_of(k)?;
+        s.with(&n, f)
+    }
+}
+
+/// Returns a binding taken out by update to its frame, even if the update panics
+struct PutBack<'a, T> {
+    frame: &'a Scope<T>,
+    entry: Option<(Sym, T)>,
+}
+
+impl<'a, T> Drop for PutBack<'a, T> {
+    fn drop(&mut self) {
+        if let (Some((k, v)), Ok(mut d)) = (self.entry.take(), self.frame.data.try_borrow_mut()) {
+            d.insert(k, v);
+            self.frame.touch();
         }
     }
 }
 
-impl<T: Clone> Scope<T> {
-    pub fn get(&self, k: &str) -> Option<T> {
-        if let Some(v) = self.data.get(k) {
-            return Some(v.clone());
+/// Unlinks the chain one frame at a time, so dropping a very deep chain can't overflow the stack
+impl<T> Drop for Scope<T> {
+    fn drop(&mut self) {
+        self.dropping();
+        self.recycle();
+        let mut stack: Vec<_> = self.parent.take().into_iter().collect();
+        stack.extend(self.extras.get_mut().and_then(|x| x.caller.take()));
+        while let Some(p) = stack.pop() {
+            // before it leaves its Rc, as the hooks know it by its address
+            // and its exports need the parent taken below
+            if Rc::strong_count(&p.p) == 1 {
+                p.p.dropping();
+            }
+            if let Ok(mut s) = Rc::try_unwrap(p.p) {
+                stack.extend(s.parent.take());
+                stack.extend(s.extras.get_mut().and_then(|x| x.caller.take()));
+            }
         }
-        match &self.parent {
-            Some(p) => p.get(k),
-            None => None,
+    }
+}
+
+impl<T> Scope<T> {
+    /// Empties the frame of everything set on it since it was made, except subscribers.
+    /// The old values are only dropped once nothing is borrowed.
+    fn clear_local(&self) {
+        self.undo_cleared();
+        let kind = self.data().kind();
+        let x = self.extras();
+        let old = (
+            std::mem::replace(&mut *self.data.borrow_mut(), Store::new(kind).into()),
+            x.map(|x| std::mem::take(&mut *x.modules.borrow_mut())),
+            x.map(|x| std::mem::take(&mut *x.imports.borrow_mut())),
+            x.map(|x| std::mem::take(&mut *x.positionals.borrow_mut())),
+            x.map(|x| std::mem::take(&mut *x.valid.borrow_mut())),
+        );
+        *self.attrs.borrow_mut() = Attrs::default();
+        for (k, _) in old.0.iter() {
+            self.stamp(*k);
         }
+        self.cache.borrow_mut().clear();
+        self.lru_clear();
+        self.touch();
+        self.journal_cleared();
+        drop(old);
+    }
+
+    /// What a frame does as it goes, other than giving back its maps
+    fn dropping(&self) {
+        self.run_exports();
+        self.journal_dropped();
+        self.undo_dropped();
+    }
+
+    /// Moves the bindings marked by export_on_drop into the parent
+    fn run_exports(&self) {
+        let parent = match &self.parent {
+            Some(p) => p,
+            None => return,
+        };
+        let keys = match self.attrs.borrow_mut().existing() {
+            Some(a) => std::mem::take(&mut a.exports),
+            None => return,
+        };
+        for k in keys {
+            let v = self.data_mut().remove(k);
+            if let Some(v) = v {
+                self.touch();
+                parent.p.set_local(k, v);
+            }
+        }
+    }
+}
+
+impl<T: Clone> Scope<T> {
+    pub fn get(&self, k: Sym) -> Option<T> {
+        self.with(k, T::clone)
+    }
+
+    /// Adds any binding not already shadowed by a key in res
+    fn collect_visible(&self, res: &mut BTreeMap<Sym, T>) {
+        self.purge_chain();
+        let mut hidden = BTreeSet::new();
+        for f in self.frames() {
+            for (k, v) in f.data().iter() {
+                if !hidden.contains(k) {
+                    res.entry(*k).or_insert_with(|| v.clone());
+                }
+            }
+            for (k, v) in f.imported_values() {
+                if !hidden.contains(&k) {
+                    res.entry(k).or_insert(v);
+                }
+            }
+            let a = f.attrs.borrow();
+            hidden.extend(a.uninit.iter().chain(&a.masked).copied());
+        }
+        res.retain(|&k, _| self.allow_get(k));
     }
 }
 
 pub struct PScope<T> {
-    p: Rc<RefCell<Scope<T>>>,
+    p: Rc<Scope<T>>,
 }
 
 impl<T> Clone for PScope<T> {
@@ -123,55 +742,512 @@ impl<T> Clone for PScope<T> {
 ///
 impl<T> PScope<T> {
     pub fn new() -> Self {
+        Self::with_storage(Storage::default())
+    }
+
+    /// A new root, whose frames and all child frames use the given map
+    pub fn with_storage(storage: Storage) -> Self {
+        Self::with_options(ScopeOptions {
+            storage,
+            ..Default::default()
+        })
+    }
+
+    /// A new root, with settings for the whole tree
+    pub fn with_options(opts: ScopeOptions) -> Self {
         PScope {
-            p: Rc::new(RefCell::new(Scope::new())),
+            p: Rc::new(Scope::new(opts)),
+        }
+    }
+
+    pub fn options(&self) -> ScopeOptions {
+        let t = self.p.tree();
+        ScopeOptions {
+            storage: self.p.data().kind(),
+            set_mode: t.set_mode,
+            max_depth: t.max_depth,
+            stats: t.stats.is_some(),
+            track_changes: t.changes.is_some(),
+            hasher: t.names.borrow().hasher(),
         }
     }
 
     pub fn set_local(&self, id: String, val: T) {
-        self.p.borrow_mut().set_local(id, val);
+        self.p.set_local(self.p.intern(&id), val);
     }
 
     pub fn set_global(&self, id: String, val: T) {
-        self.p.borrow_mut().set_global(id, val);
+        self.p.set_global(self.p.intern(&id), val);
     }
 
     pub fn set(&self, id: String, val: T) {
-        self.p.borrow_mut().set(id, val);
+        self.p.set(self.p.intern(&id), val);
+    }
+
+    pub fn try_replace(&self, id: &str, mut val: T) -> Option<T> {
+        match self.p.sym(id) {
+            Some(s) if self.p.allow_set(s, &mut val) => self.p.try_replace(s, val),
+            _ => Some(val),
+        }
+    }
+
+    pub fn update<F: FnOnce(&mut T) -> A, A>(&self, id: &str, f: F) -> Option<A> {
+        self.p.update(self.p.sym(id)?, f)
+    }
+
+    pub fn remove_local(&self, id: &str) -> Option<T> {
+        self.p.remove_local(self.p.sym(id)?)
+    }
+
+    pub fn remove(&self, id: &str) -> Option<T> {
+        self.p.remove(self.p.sym(id)?)
+    }
+
+    /// Hides id from this frame down, without touching any outer binding,
+    /// until it is set again here. Any local value is removed first.
+    pub fn mask(&self, id: &str) {
+        if self.is_frozen() {
+            return;
+        }
+        let s = self.p.intern(id);
+        self.p.remove_local(s);
+        self.p.attrs.borrow_mut().masked.insert(s);
+        self.p.touch();
+    }
+
+    /// Makes `set` on this frame write id into the root, like Python's `global`
+    pub fn mark_global(&self, id: &str) {
+        let s = self.p.intern(id);
+        let mut a = self.p.attrs.borrow_mut();
+        a.nonlocal.remove(&s);
+        a.global.insert(s);
+    }
+
+    /// Makes `set` on this frame skip it, and set id from the parent on,
+    /// like Python's `nonlocal`
+    pub fn mark_nonlocal(&self, id: &str) {
+        let s = self.p.intern(id);
+        let mut a = self.p.attrs.borrow_mut();
+        a.global.remove(&s);
+        a.nonlocal.insert(s);
+    }
+
+    /// Moves each of names into the parent frame when this frame goes away,
+    /// or when a `with_child` frame is cleared. Names unset by then are skipped.
+    pub fn export_on_drop(&self, names: &[&str]) {
+        for n in names {
+            let s = self.p.intern(n);
+            self.p.attrs.borrow_mut().exports.insert(s);
+        }
+    }
+
+    /// Moves id into the parent frame now, returning false if this frame doesn't hold it
+    pub fn export(&self, id: &str) -> bool {
+        let parent = match &self.p.parent {
+            Some(p) => p,
+            None => return false,
+        };
+        let s = match self.p.sym(id) {
+            Some(s) => s,
+            None => return false,
+        };
+        match self.p.remove_local(s) {
+            Some(v) => {
+                parent.p.set_local(s, v);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns false if id was not masked in this frame
+    pub fn unmask(&self, id: &str) -> bool {
+        match self.p.sym(id) {
+            Some(s)
+                if (self.p.attrs.borrow_mut().existing()).is_some_and(|a| a.masked.remove(&s)) =>
+            {
+                self.p.touch();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Reads the visible value in place, without needing to clone it
+    pub fn with<R, F: FnOnce(&T) -> R>(&self, id: &str, f: F) -> Option<R> {
+        self.p.with(self.p.sym(id)?, f)
+    }
+
+    pub fn parent(&self) -> Option<PScope<T>> {
+        self.p.parent.clone()
+    }
+
+    pub fn root(&self) -> PScope<T> {
+        match &self.p.root {
+            Some(r) => r.clone(),
+            None => self.clone(),
+        }
+    }
+
+    /// This frame, then each parent up to and including the root
+    pub fn ancestors(&self) -> impl Iterator<Item = PScope<T>> {
+        std::iter::successors(Some(self.clone()), |s| s.parent())
+    }
+
+    /// The distance from the root, which has depth 0
+    pub fn depth(&self) -> usize {
+        self.p.depth
+    }
+
+    /// A count that moves on whenever anything visible from here may have changed,
+    /// in this frame or any parent, for keying memos of what was worked out from it.
+    /// It can move without a visible change, but never repeats for one chain.
+    /// Changes in imported scopes are not counted. Walks the chain.
+    pub fn generation(&self) -> u64 {
+        self.p.frames().map(|f| f.version.get()).sum()
+    }
+
+    /// Like generation, counting only this frame
+    pub fn local_generation(&self) -> u64 {
+        self.p.version.get()
+    }
+
+    /// A handle on the frame that defines the visible binding for id
+    pub fn owner_of(&self, id: &str) -> Option<PScope<T>> {
+        let o = self.p.owner_str(id)?;
+        match o.data().contains_key(self.p.sym(id)?) {
+            true => Some(self.handle(o)),
+            false => None,
+        }
+    }
+
+    /// The handle for a frame on this chain
+    fn handle(&self, f: &Scope<T>) -> PScope<T> {
+        self.ancestors()
+            .find(|a| std::ptr::eq(&*a.p, f))
+            .expect("the frame is always on the chain")
+    }
+
+    /// True if both handles point to the same frame
+    pub fn ptr_eq(a: &Self, b: &Self) -> bool {
+        Rc::ptr_eq(&a.p, &b.p)
+    }
+
+    /// Whether id is visible from this frame, with a value
+    pub fn contains(&self, id: &str) -> bool {
+        self.with(id, |_| ()).is_some()
+    }
+
+    pub fn contains_local(&self, id: &str) -> bool {
+        self.p.contains_local(id)
     }
 
-    pub fn try_replace(&self, id: &str, val: T) -> Option<T> {
-        self.p.borrow_mut().try_replace(id, val)
+    /// The keys defined in this frame only, sorted,
+    /// or in the order they were set with Indexed storage
+    pub fn local_keys(&self) -> Vec<String> {
+        self.p.purge_local();
+        let data = self.p.data();
+        let mut res: Vec<String> = (data.iter())
+            .map(|(k, _)| self.p.name(*k).to_string())
+            .collect();
+        if !data.keeps_order() {
+            res.sort();
+        }
+        res
     }
 
-    pub fn update<F: Fn(&mut T) -> A, A>(&self, id: &str, f: F) -> Option<A> {
-        self.p.borrow_mut().update(id, f)
+    pub fn local_len(&self) -> usize {
+        self.p.purge_local();
+        self.p.data().len()
+    }
+
+    /// True if this frame defines nothing, though its parents may
+    pub fn is_empty(&self) -> bool {
+        self.local_len() == 0
+    }
+
+    /// Stops any further change to this frame's bindings.
+    /// The plain setters ignore writes to a frozen frame, so `set` shadows its
+    /// bindings locally instead, while the checked ones return `ScopeError::Frozen`.
+    pub fn freeze(&self) {
+        self.p.frozen.set(true);
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.p.frozen.get()
+    }
+
+    /// Runs f with a new child frame. The child's bindings are dropped when f returns,
+    /// or panics, so any clone of it kept past f only sees an empty frame.
+    pub fn with_child<R, F: FnOnce(&PScope<T>) -> R>(&self, f: F) -> R {
+        let c = ClearOnDrop(self.child());
+        f(&c.0)
+    }
+
+    /// Empties this frame in place, so it can be used again without a new allocation.
+    /// Subscribers stay, but are not told. Does nothing to a frozen frame.
+    pub fn reset_local(&self) {
+        if !self.is_frozen() {
+            self.p.clear_local();
+        }
+    }
+
+    /// An empty sibling of this frame, for the next loop iteration or tail call.
+    /// If this is the only handle on the frame it is reset and given back,
+    /// otherwise a new child of the parent is made.
+    pub fn reuse_as_child(&self) -> Self {
+        match (
+            &self.p.parent,
+            Rc::strong_count(&self.p) == 1 && !self.is_frozen(),
+        ) {
+            (Some(_), true) => {
+                self.reset_local();
+                self.clone()
+            }
+            (Some(p), false) => p.child(),
+            (None, _) => self.child(),
+        }
     }
 
     pub fn child(&self) -> Self {
-        let root = match &self.p.borrow().root {
+        PScope {
+            p: Rc::new(self.child_frame()),
+        }
+    }
+
+    /// A child with room for n bindings before it needs to reallocate
+    pub fn child_with_capacity(&self, n: usize) -> Self {
+        let c = self.child();
+        c.reserve_local(n);
+        c
+    }
+
+    /// Makes room in this frame for n more bindings
+    pub fn reserve_local(&self, n: usize) {
+        self.p.data_mut().reserve(n);
+    }
+
+    /// A child labelled for traces, such as "fn main" or "for loop"
+    pub fn child_named(&self, name: &str) -> Self {
+        let mut s = self.child_frame();
+        s.extras_mut().name = Some(name.to_string());
+        PScope { p: Rc::new(s) }
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.p.extras()?.name.as_deref()
+    }
+
+    /// The name of every frame from the root down to this one.
+    /// Frames without a name show as "<root>" or "<scope>".
+    pub fn trace(&self) -> Vec<String> {
+        let mut res: Vec<String> = (self.p.frames())
+            .map(
+                |f| match (f.extras().and_then(|x| x.name.as_ref()), &f.parent) {
+                    (Some(n), _) => n.clone(),
+                    (None, None) => "<root>".to_string(),
+                    (None, Some(_)) => "<scope>".to_string(),
+                },
+            )
+            .collect();
+        res.reverse();
+        res
+    }
+
+    /// Like child, but fails instead of panicking past the tree's max_depth
+    pub fn try_child(&self) -> Result<Self, ScopeError> {
+        self.check_depth()?;
+        Ok(self.child())
+    }
+
+    fn check_depth(&self) -> Result<(), ScopeError> {
+        match self.p.tree().max_depth {
+            Some(m) if self.p.depth >= m => Err(ScopeError::DepthExceeded(m)),
+            _ => Ok(()),
+        }
+    }
+
+    /// # Panics
+    ///
+    /// If the new frame would be deeper than the tree's max_depth
+    fn child_frame(&self) -> Scope<T> {
+        if let Err(e) = self.check_depth() {
+            panic!("{}", e);
+        }
+        let root = match &self.p.root {
             Some(r) => Some(r.clone()),
             None => Some(self.clone()),
         };
-        let parent = Some(self.clone());
-        PScope {
-            p: Rc::new(RefCell::new(Scope {
-                data: BTreeMap::new(),
-                root,
-                parent,
-            })),
+        let mut s = Scope::frame(self.p.data().kind());
+        self.p.fill_from_pool(&mut s);
+        s.root = root;
+        s.parent = Some(self.clone());
+        s.depth = self.p.depth + 1;
+        s
+    }
+}
+
+/// Empties a frame when it goes out of scope, even if handles to it escaped
+struct ClearOnDrop<T>(PScope<T>);
+
+impl<T> Drop for ClearOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.p.run_exports();
+        let subs = (self.0.p.extras()).map(|x| std::mem::take(&mut *x.subs.borrow_mut()));
+        self.0.p.clear_local();
+        drop(subs);
+    }
+}
+
+impl<T: Clone + Default> PScope<T> {
+    pub fn get_or_default(&self, id: &str) -> Option<T> {
+        self.get_or_insert_with(id, T::default)
+    }
+}
+
+impl<T: PartialEq> PScope<T> {
+    /// Replaces the visible value of id with new, only if it equals expected.
+    /// Gives new back if it doesn't, or id is undefined or frozen.
+    pub fn set_if(&self, id: &str, expected: &T, new: T) -> Result<(), T> {
+        if self.with(id, |v| v == expected) != Some(true) {
+            return Err(new);
+        }
+        match self.try_replace(id, new) {
+            None => Ok(()),
+            Some(v) => Err(v),
         }
     }
 }
+
+impl<T> Default for PScope<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T: Clone> PScope<T> {
     pub fn get(&self, id: &str) -> Option<T> {
-        self.p.borrow().get(id)
+        self.p.get(self.p.sym(id)?)
+    }
+
+    /// The visible value, or a new local one made by f.
+    /// None if it is missing and can't be stored, see `ScopeEntry::or_insert_with`.
+    pub fn get_or_insert_with<F: FnOnce() -> T>(&self, id: &str, f: F) -> Option<T> {
+        self.entry(id).or_insert_with(f).map(|v| v.clone())
+    }
+
+    /// The value of each of keys, found in a single walk up the chain
+    /// rather than one per key
+    pub fn get_many(&self, keys: &[&str]) -> Vec<Option<T>> {
+        let mut res = vec![None; keys.len()];
+        let mut left: Vec<(usize, Sym)> = (keys.iter().enumerate())
+            .filter_map(|(n, k)| Some((n, self.p.sym(k)?)))
+            .filter(|&(_, k)| self.p.allow_get(k))
+            .collect();
+        for f in self.p.used_frames() {
+            if left.is_empty() {
+                break;
+            }
+            let d = f.data();
+            left.retain(|&(n, k)| {
+                if let Some(v) = d.get(k) {
+                    res[n] = Some(v.clone());
+                } else if let Some((s, name)) = f.import_of(k) {
+                    res[n] = s.get(&name);
+                } else if !f.attrs.borrow().is_hole(k) {
+                    return true;
+                }
+                false
+            });
+        }
+        res
+    }
+
+    /// Like get_many, keyed by name and skipping anything undefined
+    pub fn get_many_map(&self, keys: &[&str]) -> HashMap<String, T> {
+        (keys.iter().zip(self.get_many(keys)))
+            .filter_map(|(k, v)| Some((k.to_string(), v?)))
+            .collect()
+    }
+
+    /// Every key visible from this scope, with its innermost value, sorted.
+    /// With Indexed storage they come in the order they were first set,
+    /// outermost frame first.
+    pub fn visible(&self) -> impl Iterator<Item = (String, T)> {
+        let mut syms = BTreeMap::new();
+        self.p.collect_visible(&mut syms);
+        let mut res = Vec::new();
+        if self.p.data().keeps_order() {
+            let frames: Vec<_> = self.p.frames().collect();
+            for f in frames.into_iter().rev() {
+                for (k, _) in f.data().iter() {
+                    if let Some(v) = syms.remove(k) {
+                        res.push((self.p.name(*k).to_string(), v));
+                    }
+                }
+            }
+        }
+        let rest: BTreeMap<String, T> = (syms.into_iter())
+            .map(|(k, v)| (self.p.name(k).to_string(), v))
+            .collect();
+        res.extend(rest);
+        res.into_iter()
+    }
+
+    /// One map of everything visible from this scope, with shadowing applied
+    pub fn flatten(&self) -> BTreeMap<String, T> {
+        let mut syms = BTreeMap::new();
+        self.p.collect_visible(&mut syms);
+        (syms.into_iter())
+            .map(|(k, v)| (self.p.name(k).to_string(), v))
+            .collect()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[test]
+    fn generations_follow_changes() {
+        let root = PScope::new();
+        root.set_local("a".to_string(), 1);
+        let c = root.child();
+        let g = c.generation();
+        assert_eq!(c.get("a"), Some(1));
+        assert_eq!(c.generation(), g);
+
+        c.update("a", |n| *n += 1);
+        assert!(c.generation() > g);
+        assert_eq!(c.local_generation(), 0);
+        let g = c.generation();
+        c.mask("a");
+        assert!(c.local_generation() > 0);
+        assert!(c.generation() > g);
+        let g = c.generation();
+        c.set_positionals(vec![1]);
+        assert!(c.generation() > g);
+    }
+
+    #[test]
+    fn get_many_walks_once() {
+        let root = PScope::new();
+        root.set_local("a".to_string(), 1);
+        root.set_local("b".to_string(), 2);
+        let c = root.child();
+        c.set_local("a".to_string(), 10);
+        c.mask("b");
+        let d = c.child();
+        d.import(&root, &[("b", "rb")]);
+
+        assert_eq!(
+            d.get_many(&["a", "b", "rb", "nope"]),
+            vec![Some(10), None, Some(2), None]
+        );
+        let m = d.get_many_map(&["a", "b", "a"]);
+        assert_eq!(m.len(), 1);
+        assert_eq!(m["a"], 10);
+    }
+
     #[test]
     fn it_works() {
         let root = PScope::new();
@@ -203,4 +1279,435 @@ mod tests {
             Some(10)
         );
     }
+
+    #[test]
+    fn remove_walks_chain() {
+        let root = PScope::new();
+        root.set_local("a".to_string(), 1);
+        let c = root.child();
+        c.set_local("a".to_string(), 2);
+
+        assert_eq!(c.remove_local("b"), None);
+        assert_eq!(c.remove("a"), Some(2));
+        assert_eq!(c.get("a"), Some(1));
+        assert_eq!(c.remove_local("a"), None);
+        assert_eq!(c.remove("a"), Some(1));
+        assert_eq!(root.get("a"), None);
+        assert_eq!(c.remove("a"), None);
+    }
+
+    #[test]
+    fn visible_resolves_shadowing() {
+        let root = PScope::new();
+        root.set_local("a".to_string(), 1);
+        root.set_local("b".to_string(), 2);
+        let c = root.child();
+        c.set_local("a".to_string(), 3);
+        c.set_local("c".to_string(), 4);
+
+        let v: Vec<_> = c.visible().collect();
+        assert_eq!(
+            v,
+            vec![
+                ("a".to_string(), 3),
+                ("b".to_string(), 2),
+                ("c".to_string(), 4)
+            ]
+        );
+        assert_eq!(root.visible().count(), 2);
+
+        let flat = c.flatten();
+        assert_eq!(flat.get("a"), Some(&3));
+        assert_eq!(flat.len(), 3);
+    }
+
+    #[test]
+    fn masks_hide_outer_bindings() {
+        let root = PScope::new();
+        root.set_local("a".to_string(), 1);
+        root.set_local("b".to_string(), 2);
+        let c = root.child();
+        c.set_local("a".to_string(), 3);
+        c.mask("a");
+        c.mask("b");
+        let d = c.child();
+
+        assert_eq!(d.get("a"), None);
+        assert!(!d.contains("b"));
+        assert!(d.owner_of("b").is_none());
+        assert_eq!(d.visible().count(), 0);
+        assert_eq!(d.update("b", |n| *n), None);
+        assert_eq!(root.get("a"), Some(1));
+
+        d.set("b".to_string(), 4);
+        assert_eq!(c.get("b"), Some(4));
+        assert_eq!(root.get("b"), Some(2));
+        assert!(c.unmask("a"));
+        assert!(!c.unmask("a"));
+        assert_eq!(d.get("a"), Some(1));
+    }
+
+    #[test]
+    fn global_and_nonlocal_marks() {
+        let root = PScope::new();
+        let outer = root.child();
+        outer.set_local("x".to_string(), 1);
+        let f = outer.child();
+        f.set_local("x".to_string(), 2);
+        f.set_local("y".to_string(), 2);
+        f.mark_global("x");
+        f.mark_nonlocal("y");
+
+        f.set("x".to_string(), 3);
+        assert_eq!(root.get("x"), Some(3));
+        assert_eq!(outer.get("x"), Some(1));
+        assert_eq!(f.get("x"), Some(2));
+
+        f.set("y".to_string(), 4);
+        assert_eq!(outer.get("y"), Some(4));
+        assert!(!root.contains("y"));
+
+        f.mark_nonlocal("x");
+        f.set("x".to_string(), 5);
+        assert_eq!(outer.get("x"), Some(5));
+        assert_eq!(root.get("x"), Some(3));
+    }
+
+    #[test]
+    fn exports_move_to_parent() {
+        let root = PScope::new();
+        root.with_child(|c| {
+            c.set_local("a".to_string(), 1);
+            c.set_local("b".to_string(), 2);
+            c.export_on_drop(&["a", "z"]);
+        });
+        assert_eq!(root.get("a"), Some(1));
+        assert!(!root.contains("b"));
+
+        let c = root.child();
+        c.set_local("x".to_string(), 3);
+        c.set_local("y".to_string(), 4);
+        c.export_on_drop(&["x"]);
+        assert!(c.export("y"));
+        assert!(!c.export("y"));
+        assert!(!root.export("a"));
+        assert_eq!(root.get("y"), Some(4));
+        assert!(!root.contains("x"));
+        drop(c);
+        assert_eq!(root.get("x"), Some(3));
+
+        // freed once its last child goes, rather than dropped directly
+        let c = root.child();
+        c.export_on_drop(&["w"]);
+        c.set_local("w".to_string(), 5);
+        let d = c.child().child();
+        drop(c);
+        assert!(!root.contains("w"));
+        drop(d);
+        assert_eq!(root.get("w"), Some(5));
+    }
+
+    #[test]
+    fn frames_are_reused() {
+        let root = PScope::new();
+        let mut body = root.child();
+        let first = Rc::as_ptr(&body.p);
+        for i in 0..3 {
+            assert!(body.is_empty());
+            body.set_local("i".to_string(), i);
+            body.set_const("k".to_string(), i).unwrap();
+            body = body.reuse_as_child();
+        }
+        assert_eq!(Rc::as_ptr(&body.p), first);
+        assert!(!body.is_const("k"));
+
+        let kept = body.clone();
+        body.set_local("i".to_string(), 9);
+        let next = body.reuse_as_child();
+        assert!(!PScope::ptr_eq(&next, &kept));
+        assert_eq!(kept.get("i"), Some(9));
+        assert!(PScope::ptr_eq(&next.parent().unwrap(), &root));
+    }
+
+    #[test]
+    fn children_start_empty() {
+        let root = PScope::new();
+        let c = root.child();
+        assert!(c.p.tree.is_none());
+        assert!(c.p.attrs.borrow().0.is_none());
+        assert!(matches!(&*c.p.data(), Store::Small(v, _) if v.capacity() == 0));
+        c.set_local("a".to_string(), 1);
+        assert_eq!(c.get("a"), Some(1));
+        c.remove_local("a");
+        assert!(c.p.extras().is_none() && root.p.extras().is_none());
+        assert!(root.child_named("f").p.extras().is_some());
+        drop(c);
+        assert!(root.p.attrs.borrow().0.is_none());
+        root.mask("a");
+        assert!(root.p.attrs.borrow().0.is_some());
+    }
+
+    #[cfg(feature = "indexmap")]
+    #[test]
+    fn indexed_storage() {
+        let root = PScope::with_storage(Storage::Indexed);
+        root.set_local("z".to_string(), 1);
+        root.set_local("a".to_string(), 2);
+        let c = root.child();
+        c.set_local("m".to_string(), 3);
+        c.set_local("z".to_string(), 4);
+        assert_eq!(c.local_keys(), vec!["m", "z"]);
+        let v: Vec<_> = c.visible().collect();
+        assert_eq!(
+            v,
+            vec![
+                ("z".to_string(), 4),
+                ("a".to_string(), 2),
+                ("m".to_string(), 3)
+            ]
+        );
+
+        let c2 = ScopeTree::capture(std::slice::from_ref(&c))
+            .restore()
+            .remove(0);
+        assert_eq!(c2.visible().collect::<Vec<_>>(), v);
+    }
+
+    #[test]
+    fn hash_storage() {
+        let root = PScope::with_storage(Storage::Hash);
+        root.set_local("a".to_string(), 1);
+        let c = root.child();
+        assert_eq!(c.p.data().kind(), Storage::Hash);
+        c.set_local("b".to_string(), 2);
+        c.set("a".to_string(), 3);
+        assert_eq!(root.get("a"), Some(3));
+        let v: Vec<_> = c.visible().collect();
+        assert_eq!(v, vec![("a".to_string(), 3), ("b".to_string(), 2)]);
+    }
+
+    #[test]
+    fn set_modes() {
+        let opts = |set_mode| ScopeOptions {
+            set_mode,
+            ..Default::default()
+        };
+        let root = PScope::with_options(opts(SetMode::CreateInRoot));
+        root.child().set("a".to_string(), 1);
+        assert_eq!(root.get("a"), Some(1));
+        assert_eq!(root.child().options().set_mode, SetMode::CreateInRoot);
+
+        let root = PScope::with_options(opts(SetMode::Error));
+        let c = root.child();
+        c.set("a".to_string(), 1);
+        assert_eq!(c.get("a"), None);
+        assert_eq!(
+            c.set_checked("a".to_string(), 1),
+            Err(ScopeError::Undefined("a".to_string()))
+        );
+        c.set_local("a".to_string(), 1);
+        c.set("a".to_string(), 2);
+        assert_eq!(c.get("a"), Some(2));
+    }
+
+    #[test]
+    fn traces() {
+        let root: PScope<i32> = PScope::new();
+        let f = root.child_named("fn main");
+        let b = f.child().child_named("if body");
+        assert_eq!(b.trace(), vec!["<root>", "fn main", "<scope>", "if body"]);
+        assert_eq!(f.name(), Some("fn main"));
+        assert_eq!(root.name(), None);
+    }
+
+    #[test]
+    fn depth_limit() {
+        let root: PScope<i32> = PScope::with_options(ScopeOptions {
+            max_depth: Some(2),
+            ..Default::default()
+        });
+        let c = root.try_child().unwrap().try_child().unwrap();
+        assert_eq!(c.depth(), 2);
+        assert_eq!(c.try_child().err(), Some(ScopeError::DepthExceeded(2)));
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| c.child_function()));
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn frozen_frames_are_shadowed() {
+        let root = PScope::new();
+        root.set_local("print".to_string(), 1);
+        root.freeze();
+        let c = root.child();
+
+        root.set_local("print".to_string(), 2);
+        assert_eq!(root.update("print", |n| *n += 1), None);
+        assert_eq!(c.remove("print"), None);
+        assert_eq!(root.get("print"), Some(1));
+
+        c.set("print".to_string(), 5);
+        assert_eq!(c.get("print"), Some(5));
+        assert_eq!(root.get("print"), Some(1));
+        c.set_global("other".to_string(), 1);
+        assert_eq!(root.get("other"), None);
+    }
+
+    #[test]
+    fn with_child_clears_frame() {
+        let root = PScope::new();
+        root.set_local("a".to_string(), 1);
+        let mut kept = None;
+        let n = root.with_child(|c| {
+            c.set_local("b".to_string(), 2);
+            c.set("a".to_string(), 3);
+            kept = Some(c.clone());
+            c.get("b")
+        });
+        assert_eq!(n, Some(2));
+        let kept = kept.unwrap();
+        assert_eq!(kept.get("b"), None);
+        assert_eq!(kept.get("a"), Some(3));
+    }
+
+    #[test]
+    fn introspection() {
+        let root = PScope::new();
+        root.set_local("b".to_string(), 1);
+        root.set_local("a".to_string(), 1);
+        let c = root.child();
+        assert!(c.is_empty());
+        assert!(c.contains("a"));
+        assert!(!c.contains_local("a"));
+        assert!(!c.contains("z"));
+        c.set_local("z".to_string(), 2);
+        assert!(c.contains_local("z"));
+        assert_eq!(c.local_len(), 1);
+        assert_eq!(root.local_keys(), vec!["a".to_string(), "b".to_string()]);
+    }
+
+    #[test]
+    fn get_or_insert() {
+        let root = PScope::new();
+        root.set_local("a".to_string(), 1);
+        let c = root.child();
+        assert_eq!(c.get_or_insert_with("a", || 5), Some(1));
+        assert_eq!(c.get_or_insert_with("b", || 5), Some(5));
+        assert_eq!(c.get_or_default("c"), Some(0));
+        assert!(c.contains_local("b"));
+        assert!(c.contains_local("c"));
+        assert!(!root.contains("b"));
+
+        // a frozen frame still gives what it has, but can't make anything new
+        c.freeze();
+        assert_eq!(c.get_or_insert_with("a", || 5), Some(1));
+        assert_eq!(c.get_or_insert_with("b", || 6), Some(5));
+        assert_eq!(c.get_or_insert_with("d", || 5), None);
+        assert_eq!(c.get_or_default("e"), None);
+        assert!(!c.contains("d"));
+    }
+
+    #[test]
+    fn tree_accessors() {
+        let root = PScope::new();
+        let a = root.child();
+        let b = a.child();
+        b.set_local("x".to_string(), 1);
+
+        assert!(root.parent().is_none());
+        assert!(Rc::ptr_eq(&b.parent().unwrap().p, &a.p));
+        assert!(Rc::ptr_eq(&b.root().p, &root.p));
+        assert!(Rc::ptr_eq(&root.root().p, &root.p));
+        let chain: Vec<_> = b.ancestors().map(|s| s.local_len()).collect();
+        assert_eq!(chain, vec![1, 0, 0]);
+        assert!(Rc::ptr_eq(&b.ancestors().last().unwrap().p, &root.p));
+    }
+
+    #[test]
+    fn provenance() {
+        let root = PScope::new();
+        root.set_local("x".to_string(), 1);
+        let a = root.child();
+        let b = a.child();
+        a.set_local("y".to_string(), 1);
+
+        assert_eq!(root.depth(), 0);
+        assert_eq!(b.depth(), 2);
+        assert!(PScope::ptr_eq(&b.owner_of("x").unwrap(), &root));
+        assert!(PScope::ptr_eq(&b.owner_of("y").unwrap(), &a));
+        assert!(b.owner_of("z").is_none());
+    }
+
+    #[test]
+    fn deep_chains() {
+        let root = PScope::new();
+        root.set_local("a".to_string(), 0);
+        let mut leaf = root.child();
+        for _ in 0..100_000 {
+            leaf = leaf.child();
+        }
+        leaf.set("a".to_string(), 1);
+        assert_eq!(leaf.update("a", |n| *n + 1), Some(2));
+        assert_eq!(leaf.get("a"), Some(1));
+        assert_eq!(leaf.remove("a"), Some(1));
+        assert_eq!(leaf.visible().count(), 0);
+        drop(leaf);
+        assert!(root.is_empty());
+    }
+
+    #[test]
+    fn reentrant_update() {
+        let root = PScope::new();
+        root.set_local("x".to_string(), 1);
+        root.set_local("y".to_string(), 10);
+        let c = root.child();
+        let c2 = c.clone();
+        c.update("x", |x| {
+            *x += c2.get("y").unwrap();
+            c2.set("y".to_string(), 20);
+            assert_eq!(c2.get("x"), None);
+        });
+        assert_eq!(root.get("x"), Some(11));
+        assert_eq!(root.get("y"), Some(20));
+
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            root.update("x", |_| panic!("boom"));
+        }));
+        assert!(r.is_err());
+        assert_eq!(root.get("x"), Some(11));
+    }
+
+    #[test]
+    fn borrowed_access() {
+        let root: PScope<Vec<i32>> = PScope::new();
+        root.set_local("v".to_string(), vec![1, 2, 3]);
+        let c = root.child();
+
+        assert_eq!(c.with("v", |v| v.len()), Some(3));
+        assert_eq!(c.with("w", |v| v.len()), None);
+        {
+            let r = c.get_ref("v").unwrap();
+            assert_eq!(r[1], 2);
+            // other frames can still be changed while the borrow is held
+            c.set_local("w".to_string(), vec![]);
+        }
+        assert!(c.get_ref("x").is_none());
+        root.update("v", |v| v.push(4));
+        assert_eq!(c.get_ref("v").unwrap().len(), 4);
+    }
+
+    #[test]
+    fn set_if_compares_first() {
+        let root = PScope::new();
+        root.set_local("n".to_string(), 1);
+        let c = root.child();
+        assert_eq!(c.set_if("n", &2, 5), Err(5));
+        assert_eq!(c.set_if("n", &1, 5), Ok(()));
+        assert_eq!(root.get("n"), Some(5));
+        assert_eq!(c.set_if("missing", &0, 1), Err(1));
+        assert!(!c.contains("missing"));
+
+        root.freeze();
+        assert_eq!(c.set_if("n", &5, 6), Err(6));
+        assert_eq!(c.get("n"), Some(5));
+    }
 }
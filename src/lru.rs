@@ -0,0 +1,146 @@
+use crate::hash::FxBuild;
+use crate::{PScope, Scope, Sym};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::mem::size_of;
+use std::rc::Rc;
+
+/// The use order of the bindings in a frame made by child_lru
+#[derive(Clone)]
+pub(crate) struct Lru {
+    cap: usize,
+    clock: Cell<u64>,
+    /// When each key was last used
+    at: RefCell<HashMap<Sym, u64, FxBuild>>,
+    /// Each key by when it was last used, oldest first
+    order: RefCell<BTreeMap<u64, Sym>>,
+}
+
+impl Lru {
+    pub(crate) fn heap_size(&self) -> usize {
+        self.at.borrow().capacity() * (size_of::<(Sym, u64)>() + 1)
+            + self.order.borrow().len() * size_of::<(u64, Sym)>() * 3 / 2
+    }
+}
+
+impl<T> Scope<T> {
+    /// Marks k as just used, if this frame keeps a use order
+    pub(crate) fn lru_use(&self, k: Sym) {
+        let l = match self.extras().and_then(|x| x.lru.as_ref()) {
+            Some(l) => l,
+            None => return,
+        };
+        let t = l.clock.get() + 1;
+        l.clock.set(t);
+        let mut order = l.order.borrow_mut();
+        if let Some(old) = l.at.borrow_mut().insert(k, t) {
+            order.remove(&old);
+        }
+        order.insert(t, k);
+    }
+
+    /// Drops k from the use order, once it has been removed
+    pub(crate) fn lru_forget(&self, k: Sym) {
+        if let Some(l) = self.extras().and_then(|x| x.lru.as_ref()) {
+            if let Some(t) = l.at.borrow_mut().remove(&k) {
+                l.order.borrow_mut().remove(&t);
+            }
+        }
+    }
+
+    pub(crate) fn lru_clear(&self) {
+        if let Some(l) = self.extras().and_then(|x| x.lru.as_ref()) {
+            l.at.borrow_mut().clear();
+            l.order.borrow_mut().clear();
+        }
+    }
+
+    /// Removes the least recently used bindings until the frame is back under its cap
+    pub(crate) fn evict(&self) {
+        let l = match self.extras().and_then(|x| x.lru.as_ref()) {
+            Some(l) => l,
+            None => return,
+        };
+        while self.data().len() > l.cap {
+            let oldest = l.order.borrow().values().next().copied();
+            match oldest {
+                Some(k) => {
+                    // a value that was already gone is still dropped from the order
+                    self.lru_forget(k);
+                    self.remove_local(k);
+                }
+                None => return,
+            }
+        }
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// root.set_local("pi".to_string(), 3);
+/// let memo = root.child_lru(2);
+/// memo.set_local("f(1)".to_string(), 1);
+/// memo.set_local("f(2)".to_string(), 2);
+/// assert_eq!(memo.get("f(1)"), Some(1));
+///
+/// memo.set_local("f(3)".to_string(), 6);
+/// assert_eq!(memo.local_keys(), vec!["f(1)", "f(3)"]);
+/// assert_eq!(memo.child().get("pi"), Some(3));
+/// ```
+impl<T> PScope<T> {
+    /// A child that holds at most cap bindings of its own, like a memo cache.
+    /// Each new binding past that removes the one least recently set, changed
+    /// or looked up by name through the frame, as `remove_local` would.
+    /// Lookups still reach its parents, and its children are unbounded.
+    pub fn child_lru(&self, cap: usize) -> Self {
+        let mut s = self.child_frame();
+        s.extras_mut().lru = Some(Box::new(Lru {
+            cap,
+            clock: Cell::new(0),
+            at: RefCell::default(),
+            order: RefCell::default(),
+        }));
+        PScope { p: Rc::new(s) }
+    }
+
+    /// The most bindings this frame holds, if it was made by child_lru
+    pub fn lru_capacity(&self) -> Option<usize> {
+        self.p.extras()?.lru.as_ref().map(|l| l.cap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn lru_frames_drop_the_oldest() {
+        let root = PScope::new();
+        root.set_local("a".to_string(), 0);
+        let memo = root.child_lru(3);
+        assert_eq!(memo.lru_capacity(), Some(3));
+        assert_eq!(root.lru_capacity(), None);
+        for n in 1..=3 {
+            memo.set_local(format!("k{}", n), n);
+        }
+        memo.update("k1", |n| *n += 10);
+        memo.child().get("k2");
+        memo.set_local("k4".to_string(), 4);
+        assert_eq!(memo.local_keys(), vec!["k1", "k2", "k4"]);
+
+        // shadowing a parent counts like any other binding
+        memo.set_local("a".to_string(), 5);
+        assert_eq!(memo.get("k1"), None);
+        memo.remove_local("k2");
+        memo.set_local("k5".to_string(), 5);
+        assert_eq!(memo.local_keys(), vec!["a", "k4", "k5"]);
+        assert_eq!(memo.fork().lru_capacity(), Some(3));
+
+        memo.reset_local();
+        memo.set_local("z".to_string(), 0);
+        assert_eq!(memo.get("a"), Some(0));
+        assert!(memo.memory_estimate() > root.memory_estimate());
+        root.child_lru(0).set_local("gone".to_string(), 1);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::store::Store;
+use crate::sym::Interner;
+use crate::{AttrSets, Meta, PScope, Scope, Sym};
+use std::mem::size_of;
+
+impl<T> Store<T> {
+    /// Bytes allocated for the entries, roughly for the maps
+    fn heap_size(&self) -> usize {
+        let entry = size_of::<(Sym, T)>();
+        match self {
+            Store::Small(v, _) => v.capacity() * entry,
+            // a node holds up to 11 entries and is usually about 2/3 full
+            Store::BTree(m) => m.len() * entry * 3 / 2,
+            Store::Hash(m) => m.capacity() * (entry + 1),
+            #[cfg(feature = "indexmap")]
+            Store::Indexed(m) => m.capacity() * (entry + 2 * size_of::<usize>()),
+        }
+    }
+
+    fn shrink_to_fit(&mut self) {
+        match self {
+            Store::Small(v, _) => v.shrink_to_fit(),
+            Store::BTree(_) => {}
+            Store::Hash(m) => m.shrink_to_fit(),
+            #[cfg(feature = "indexmap")]
+            Store::Indexed(m) => m.shrink_to_fit(),
+        }
+    }
+}
+
+impl AttrSets {
+    fn heap_size(&self) -> usize {
+        let syms = [
+            &self.consts,
+            &self.uninit,
+            &self.masked,
+            &self.global,
+            &self.nonlocal,
+            &self.exports,
+            &self.env,
+        ];
+        let n: usize = syms.iter().map(|s| s.len()).sum();
+        size_of::<AttrSets>()
+            + n * size_of::<Sym>()
+            + (self.meta.values())
+                .map(|m| size_of::<(Sym, Meta)>() + m.file.capacity())
+                .sum::<usize>()
+            + (self.computed.values())
+                .map(|(_, d)| size_of::<(Sym, u64, usize)>() + d.len() * size_of::<Sym>())
+                .sum::<usize>()
+            + self.stamps.len() * size_of::<(Sym, u64)>()
+            + self.expires.len() * size_of::<(Sym, std::time::Instant)>()
+    }
+}
+
+impl Interner {
+    fn heap_size(&self) -> usize {
+        // each name is in the Vec, and its hash in the HashMap with the Sym
+        let per_name = size_of::<std::rc::Rc<str>>() + size_of::<(u64, Sym)>() + 1;
+        self.names().map(|n| n.len() + per_name).sum()
+    }
+}
+
+impl<T> Scope<T> {
+    /// Bytes held by this frame alone, not counting what its values point to
+    fn memory(&self) -> usize {
+        let mut n = size_of::<Scope<T>>() + self.data.borrow().heap_size();
+        if let Some(a) = &self.attrs.borrow().0 {
+            n += a.heap_size();
+        }
+        if let Some(t) = &self.tree {
+            n += size_of::<crate::TreeData>() + t.names.borrow().heap_size();
+        }
+        if let Some(l) = self.extras().and_then(|x| x.lru.as_ref()) {
+            n += size_of::<crate::lru::Lru>() + l.heap_size();
+        }
+        if let Some(x) = self.extras() {
+            n += size_of::<crate::Extras<T>>();
+            n += x.positionals.borrow().capacity() * size_of::<Option<T>>();
+            n += x.valid.borrow().len() * size_of::<(Sym, crate::Validator<T>)>();
+        }
+        n += self.cache.borrow().capacity() * size_of::<(Sym, (u64, Option<usize>))>();
+        n
+    }
+}
+
+/// ```rust
+/// use scope_store::{PScope, Storage};
+/// let root = PScope::with_storage(Storage::Hash);
+/// let c = root.child();
+/// let empty = c.memory_estimate();
+/// c.reserve_local(100);
+/// let reserved = c.memory_estimate();
+/// assert!(reserved > empty);
+///
+/// c.set_local("a".to_string(), 1);
+/// c.shrink_local();
+/// assert!(c.memory_estimate() < reserved);
+/// ```
+impl<T> PScope<T> {
+    /// A rough count of the bytes held by this frame and every frame above it.
+    /// Values are counted by their size alone, so anything they point to is missed.
+    pub fn memory_estimate(&self) -> usize {
+        self.p.frames().map(|f| f.memory()).sum()
+    }
+
+    /// Gives back any spare room in this frame's allocations
+    pub fn shrink_local(&self) {
+        self.p.data.borrow_mut().shrink_to_fit();
+        if let Some(x) = self.p.extras() {
+            x.positionals.borrow_mut().shrink_to_fit();
+        }
+        let mut cache = self.p.cache.borrow_mut();
+        cache.clear();
+        cache.shrink_to_fit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn estimates_grow_with_frames() {
+        let root: PScope<u64> = PScope::new();
+        let base = root.memory_estimate();
+        for n in 0..20 {
+            root.set_local(format!("k{}", n), n);
+        }
+        assert!(root.memory_estimate() >= base + 20 * size_of::<(Sym, u64)>());
+
+        let c = root.child();
+        assert_eq!(
+            c.memory_estimate(),
+            root.memory_estimate() + size_of::<Scope<u64>>()
+        );
+        c.mask("k1");
+        c.set_positionals(vec![1, 2]);
+        assert!(c.memory_estimate() > root.memory_estimate() + size_of::<Scope<u64>>());
+    }
+}
@@ -0,0 +1,71 @@
+use crate::PScope;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Where a binding was defined, for diagnostics
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Meta {
+    pub file: String,
+    pub line: u32,
+    pub col: u32,
+}
+
+///
+/// ```rust
+/// use scope_store::{Meta, PScope};
+/// let root = PScope::new();
+/// let at = Meta { file: "foo.dsl".to_string(), line: 12, col: 4 };
+/// root.set_local_with_meta("x".to_string(), 1, at.clone());
+///
+/// let c = root.child();
+/// c.set("x".to_string(), 2);
+/// assert_eq!(c.meta_of("x"), Some(at));
+/// ```
+impl<T> PScope<T> {
+    /// Like set_local, also recording where id was defined.
+    /// The Meta stays until the binding is removed, or replaced by another with_meta call.
+    pub fn set_local_with_meta(&self, id: String, val: T, meta: Meta) {
+        if self.is_frozen() {
+            return;
+        }
+        let s = self.p.intern(&id);
+        self.p.attrs.borrow_mut().meta.insert(s, meta);
+        self.p.set_local(s, val);
+    }
+
+    /// The Meta of the visible binding for id
+    pub fn meta_of(&self, id: &str) -> Option<Meta> {
+        let s = self.p.sym(id)?;
+        let o = self.p.owner(s)?;
+        let m = o.attrs.borrow().meta.get(&s).cloned();
+        m
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn removal_drops_meta() {
+        let root = PScope::new();
+        let at = |line| Meta {
+            file: "a".to_string(),
+            line,
+            col: 0,
+        };
+        root.set_local_with_meta("x".to_string(), 1, at(1));
+        let c = root.child();
+        c.set_local("x".to_string(), 2);
+        assert_eq!(c.meta_of("x"), None);
+        c.set_local_with_meta("x".to_string(), 3, at(2));
+        assert_eq!(c.meta_of("x"), Some(at(2)));
+
+        c.remove("x");
+        assert_eq!(c.meta_of("x"), Some(at(1)));
+        root.remove("x");
+        root.set_local("x".to_string(), 4);
+        assert_eq!(c.meta_of("x"), None);
+    }
+}
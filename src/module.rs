@@ -0,0 +1,90 @@
+use crate::PScope;
+
+impl<T> PScope<T> {
+    /// The nearest module registered under name, on this frame or a parent
+    pub fn module(&self, name: &str) -> Option<PScope<T>> {
+        self.ancestors()
+            .find_map(|s| s.p.extras()?.modules.borrow().get(name).cloned())
+    }
+
+    /// The module scope a path like "a::b::c" points into, and the last segment
+    fn qualified<'k>(&self, path: &'k str) -> Option<(PScope<T>, &'k str)> {
+        let mut parts = path.split("::");
+        let mut last = parts.next()?;
+        let mut cur: Option<PScope<T>> = None;
+        for p in parts {
+            let m = match &cur {
+                None => self.module(last)?,
+                Some(c) => c.module(last)?,
+            };
+            cur = Some(m);
+            last = p;
+        }
+        Some((cur.unwrap_or_else(|| self.clone()), last))
+    }
+}
+
+/// Modules are scopes registered under a name in a frame, visible from its children,
+/// and read with qualified keys like "math::pi". A plain key reads this scope as usual.
+/// A module scope can belong to any tree, but one that can reach the frame it is
+/// registered in will never be freed. `ScopeTree` does not keep modules.
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let math = PScope::new();
+/// math.set_local("pi".to_string(), 3);
+///
+/// let root = PScope::new();
+/// root.register_module("math", math);
+/// let c = root.child();
+/// assert_eq!(c.get_qualified("math::pi"), Some(3));
+/// assert_eq!(c.get_qualified("math::tau"), None);
+/// assert_eq!(c.get_qualified("pi"), None);
+/// ```
+impl<T> PScope<T> {
+    /// Replaces any module of the same name in this frame
+    pub fn register_module(&self, name: &str, scope: PScope<T>) {
+        (self.p.extras_init().modules.borrow_mut()).insert(name.to_string(), scope);
+    }
+
+    pub fn unregister_module(&self, name: &str) -> Option<PScope<T>> {
+        self.p.extras()?.modules.borrow_mut().remove(name)
+    }
+
+    pub fn with_qualified<R, F: FnOnce(&T) -> R>(&self, path: &str, f: F) -> Option<R> {
+        let (m, k) = self.qualified(path)?;
+        m.with(k, f)
+    }
+}
+
+impl<T: Clone> PScope<T> {
+    pub fn get_qualified(&self, path: &str) -> Option<T> {
+        self.with_qualified(path, T::clone)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn nested_modules() {
+        let consts = PScope::new();
+        consts.set_local("e".to_string(), 2);
+        let math = PScope::new();
+        math.register_module("consts", consts);
+        let root = PScope::new();
+        root.register_module("math", math);
+        root.set_local("x".to_string(), 1);
+
+        let c = root.child();
+        let local = PScope::new();
+        c.register_module("math", local);
+        assert_eq!(c.get_qualified("math::consts::e"), None);
+        assert_eq!(root.get_qualified("math::consts::e"), Some(2));
+        assert_eq!(root.get_qualified("x"), Some(1));
+        assert_eq!(root.get_qualified("nope::x"), None);
+
+        assert!(c.unregister_module("math").is_some());
+        assert_eq!(c.get_qualified("math::consts::e"), Some(2));
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{PScope, Scope, Sym};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_SUB: AtomicUsize = AtomicUsize::new(0);
+
+type Callback<T> = Rc<dyn Fn(&str, &T)>;
+
+/// Identifies a subscription so it can be removed again
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SubId(usize);
+
+pub(crate) struct Subscriber<T> {
+    id: usize,
+    bubble: bool,
+    cb: Callback<T>,
+}
+
+impl<T> Scope<T> {
+    /// Whether a change to this frame would fire anything
+    pub(crate) fn watched(&self) -> bool {
+        self.extras().is_some_and(|x| !x.subs.borrow().is_empty()) || self.tree().bubbling.get() > 0
+    }
+
+    /// Records and fires the subscribers for the current value of k in this frame
+    pub(crate) fn changed(&self, k: Sym) {
+        self.stamp(k);
+        self.keep_version(k);
+        self.journal_value(k, false);
+        self.lru_use(k);
+        self.announce(k);
+        self.evict();
+    }
+
+    /// Like changed, for a value changed in place
+    pub(crate) fn updated(&self, k: Sym) {
+        self.stamp(k);
+        self.keep_version(k);
+        self.journal_value(k, true);
+        self.lru_use(k);
+        self.announce(k);
+    }
+
+    fn announce(&self, k: Sym) {
+        if !self.watched() {
+            return;
+        }
+        if let Some(v) = self.data().get(k) {
+            self.notify(k, v);
+        }
+        self.run_deferred();
+    }
+
+    /// Runs what subscribers put off with `defer`, now that no frame is borrowed
+    pub(crate) fn run_deferred(&self) {
+        loop {
+            let job = self.tree().deferred.borrow_mut().pop_front();
+            match job {
+                Some(j) => j(),
+                None => return,
+            }
+        }
+    }
+
+    /// Puts off f until the change being announced is done,
+    /// for subscribers that need to change the scope
+    pub(crate) fn defer<F: FnOnce() + 'static>(&self, f: F) {
+        self.tree().deferred.borrow_mut().push_back(Box::new(f));
+    }
+
+    pub(crate) fn notify(&self, k: Sym, v: &T) {
+        if !self.watched() {
+            return;
+        }
+        let k = self.name(k);
+        let bubbling = self.tree().bubbling.get() > 0;
+        let mut cur = Some(self);
+        let mut here = true;
+        while let Some(c) = cur {
+            // Cloned out so callbacks are free to subscribe or unsubscribe
+            let cbs: Vec<_> = match c.extras() {
+                Some(x) => (x.subs.borrow().iter())
+                    .filter(|s| here || s.bubble)
+                    .map(|s| s.cb.clone())
+                    .collect(),
+                None => Vec::new(),
+            };
+            for cb in cbs {
+                cb(&k, v);
+            }
+            if !bubbling {
+                return;
+            }
+            here = false;
+            cur = c.parent.as_ref().map(|p| &*p.p);
+        }
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+///
+/// let root = PScope::new();
+/// let seen = Rc::new(RefCell::new(Vec::new()));
+/// let s2 = seen.clone();
+/// root.subscribe_bubbling(move |k, v: &i32| s2.borrow_mut().push((k.to_string(), *v)));
+///
+/// let c = root.child();
+/// root.set_local("a".to_string(), 1);
+/// c.set_local("b".to_string(), 2);
+/// c.update("a", |n| *n += 10);
+/// assert_eq!(
+///     *seen.borrow(),
+///     vec![("a".to_string(), 1), ("b".to_string(), 2), ("a".to_string(), 11)]
+/// );
+/// ```
+impl<T> PScope<T> {
+    fn add_subscriber(&self, bubble: bool, cb: Callback<T>) -> SubId {
+        let id = NEXT_SUB.fetch_add(1, Ordering::Relaxed);
+        (self.p.extras_init().subs.borrow_mut()).push(Subscriber { id, bubble, cb });
+        if bubble {
+            let r = self.p.tree();
+            r.bubbling.set(r.bubbling.get() + 1);
+        }
+        SubId(id)
+    }
+
+    /// Calls cb whenever a binding in this frame is set, updated or removed.
+    /// Removal passes the value that was removed.
+    pub fn subscribe<F: Fn(&str, &T) + 'static>(&self, cb: F) -> SubId {
+        self.add_subscriber(false, Rc::new(cb))
+    }
+
+    /// Like subscribe, but also called for changes made in any descendant frame
+    pub fn subscribe_bubbling<F: Fn(&str, &T) + 'static>(&self, cb: F) -> SubId {
+        self.add_subscriber(true, Rc::new(cb))
+    }
+
+    /// Returns false if the subscription was not on this frame
+    pub fn unsubscribe(&self, id: SubId) -> bool {
+        let x = match self.p.extras() {
+            Some(x) => x,
+            None => return false,
+        };
+        let mut subs = x.subs.borrow_mut();
+        let pos = match subs.iter().position(|s| s.id == id.0) {
+            Some(p) => p,
+            None => return false,
+        };
+        if subs.remove(pos).bubble {
+            let r = self.p.tree();
+            r.bubbling.set(r.bubbling.get() - 1);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn only_own_frame_without_bubbling() {
+        let root = PScope::new();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let s2 = seen.clone();
+        let id = root.subscribe(move |k, v: &i32| s2.borrow_mut().push((k.to_string(), *v)));
+
+        let c = root.child();
+        root.set_local("a".to_string(), 1);
+        c.set_local("b".to_string(), 2);
+        c.set("a".to_string(), 3);
+        assert_eq!(c.remove("a"), Some(3));
+        root.remove_local("nothing");
+        assert_eq!(
+            *seen.borrow(),
+            vec![
+                ("a".to_string(), 1),
+                ("a".to_string(), 3),
+                ("a".to_string(), 3)
+            ]
+        );
+
+        assert!(root.unsubscribe(id));
+        assert!(!root.unsubscribe(id));
+        root.set_local("a".to_string(), 4);
+        assert_eq!(seen.borrow().len(), 3);
+    }
+
+    #[test]
+    fn callbacks_can_read_scope() {
+        let root = PScope::new();
+        let c = root.child();
+        let r2 = root.clone();
+        let seen = Rc::new(RefCell::new(None));
+        let s2 = seen.clone();
+        c.subscribe(move |_, v: &i32| *s2.borrow_mut() = Some(v + r2.get("base").unwrap()));
+        root.set_local("base".to_string(), 100);
+        c.set_local("x".to_string(), 1);
+        assert_eq!(*seen.borrow(), Some(101));
+    }
+}
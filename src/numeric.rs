@@ -0,0 +1,114 @@
+use crate::PScope;
+use std::ops::{AddAssign, SubAssign};
+
+/// The number incr and decr step by
+pub trait One {
+    fn one() -> Self;
+}
+
+macro_rules! one {
+    ($($t:ty = $one:expr),*) => {
+        $(impl One for $t {
+            fn one() -> Self {
+                $one
+            }
+        })*
+    };
+}
+
+one!(i8 = 1, i16 = 1, i32 = 1, i64 = 1, i128 = 1, isize = 1);
+one!(u8 = 1, u16 = 1, u32 = 1, u64 = 1, u128 = 1, usize = 1);
+one!(f32 = 1.0, f64 = 1.0);
+
+/// Arithmetic on the visible value of a key, done in place where it is defined.
+/// Each gives back the result, or None if the key is undefined or frozen,
+/// or a hook or validator refuses the change, which is then undone.
+///
+/// ```rust
+/// use scope_store::{NumericScope, PScope};
+/// let root = PScope::new();
+/// root.set_local("count".to_string(), 0i8);
+/// let c = root.child();
+/// assert_eq!(c.incr("count"), Some(1));
+/// assert_eq!(c.add_assign("count", 10), Some(11));
+/// assert_eq!(c.decr("count"), Some(10));
+/// assert_eq!(root.get("count"), Some(10));
+/// assert_eq!(c.incr("missing"), None);
+/// ```
+pub trait NumericScope<T> {
+    fn add_assign(&self, id: &str, delta: T) -> Option<T>
+    where
+        T: AddAssign;
+
+    fn sub_assign(&self, id: &str, delta: T) -> Option<T>
+    where
+        T: SubAssign;
+
+    fn incr(&self, id: &str) -> Option<T>
+    where
+        T: AddAssign + One,
+    {
+        self.add_assign(id, T::one())
+    }
+
+    fn decr(&self, id: &str) -> Option<T>
+    where
+        T: SubAssign + One,
+    {
+        self.sub_assign(id, T::one())
+    }
+}
+
+impl<T: Clone> NumericScope<T> for PScope<T> {
+    fn add_assign(&self, id: &str, delta: T) -> Option<T>
+    where
+        T: AddAssign,
+    {
+        self.update(id, |v| {
+            *v += delta;
+            v.clone()
+        })
+    }
+
+    fn sub_assign(&self, id: &str, delta: T) -> Option<T>
+    where
+        T: SubAssign,
+    {
+        self.update(id, |v| {
+            *v -= delta;
+            v.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn counters_change_in_place() {
+        let root = PScope::new();
+        root.set_local("f".to_string(), 1.5);
+        let c = root.child();
+        c.set_local("f".to_string(), 0.5);
+        assert_eq!(c.add_assign("f", 2.0), Some(2.5));
+        assert_eq!(c.sub_assign("f", 0.25), Some(2.25));
+        assert_eq!(c.incr("f"), Some(3.25));
+        assert_eq!(root.get("f"), Some(1.5));
+
+        c.freeze();
+        assert_eq!(c.decr("f"), None);
+        assert_eq!(root.child().decr("f"), Some(0.5));
+
+        // deltas need not be Copy
+        #[derive(Clone, Debug, PartialEq)]
+        struct Big(Vec<u32>);
+        impl AddAssign for Big {
+            fn add_assign(&mut self, o: Big) {
+                self.0.extend(o.0);
+            }
+        }
+        let b = PScope::new();
+        b.set_local("b".to_string(), Big(vec![1]));
+        assert_eq!(b.add_assign("b", Big(vec![2])), Some(Big(vec![1, 2])));
+    }
+}
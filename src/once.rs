@@ -0,0 +1,74 @@
+use crate::{AlreadySet, PScope};
+
+///
+/// ```rust
+/// use scope_store::{AlreadySet, PScope};
+/// let root = PScope::new();
+/// let c = root.child();
+/// assert_eq!(root.set_once("print".to_string(), 1), Ok(()));
+/// assert_eq!(c.set_once("print".to_string(), 2), Ok(()));
+/// assert_eq!(root.set_once("print".to_string(), 3), Err(AlreadySet));
+///
+/// let mut runs = 0;
+/// for _ in 0..3 {
+///     root.get_or_init("math", || {
+///         runs += 1;
+///         10
+///     });
+/// }
+/// assert_eq!(runs, 1);
+/// assert_eq!(c.get("math"), Some(10));
+/// ```
+impl<T> PScope<T> {
+    /// Sets id in this frame, unless it already has a value here.
+    /// Outer bindings don't count, so it may shadow one. A name declared without
+    /// a value may be set once. A frozen frame refuses, as it can't be set at all.
+    pub fn set_once(&self, id: String, val: T) -> Result<(), AlreadySet> {
+        if self.is_frozen() || self.contains_local(&id) {
+            return Err(AlreadySet);
+        }
+        self.set_local(id, val);
+        Ok(())
+    }
+}
+
+impl<T: Clone> PScope<T> {
+    /// The visible value of id, or else the result of f, set once in this frame.
+    /// If f sets id here itself, that value is kept and f's result is dropped.
+    pub fn get_or_init<F: FnOnce() -> T>(&self, id: &str, f: F) -> T {
+        if let Some(v) = self.get(id) {
+            return v;
+        }
+        let v = f();
+        match self.set_once(id.to_string(), v.clone()) {
+            Ok(()) => v,
+            Err(AlreadySet) => self.get(id).unwrap_or(v),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn set_only_once() {
+        let root = PScope::new();
+        root.declare_uninit("x").unwrap();
+        assert_eq!(root.set_once("x".to_string(), 1), Ok(()));
+        assert_eq!(root.set_once("x".to_string(), 2), Err(AlreadySet));
+        assert_eq!(root.get("x"), Some(1));
+
+        let r2 = root.clone();
+        let v = root.get_or_init("y", || {
+            r2.set_local("y".to_string(), 5);
+            6
+        });
+        assert_eq!(v, 5);
+
+        let c = root.child();
+        c.freeze();
+        assert_eq!(c.set_once("z".to_string(), 1), Err(AlreadySet));
+        assert_eq!(c.get_or_init("z", || 3), 3);
+        assert!(!c.contains("z"));
+    }
+}
@@ -0,0 +1,33 @@
+use crate::{NameHasher, Storage};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Where `set` puts a name that no frame defines yet
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SetMode {
+    /// In the frame set was called on, the default
+    #[default]
+    CreateLocal,
+    /// In the root, like an undeclared JS assignment
+    CreateInRoot,
+    /// Nowhere. The plain setters ignore the write,
+    /// and `set_checked` returns `ScopeError::Undefined`.
+    Error,
+}
+
+/// Settings for a whole tree, see `PScope::with_options`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ScopeOptions {
+    pub storage: Storage,
+    pub set_mode: SetMode,
+    /// The deepest a frame may be, with the root at depth 0
+    pub max_depth: Option<usize>,
+    /// Count lookups, see `PScope::stats`
+    pub stats: bool,
+    /// Remember when each binding last changed, see `PScope::changed_keys_since`
+    pub track_changes: bool,
+    /// How names are hashed, when they are first seen and on every lookup by name
+    pub hasher: NameHasher,
+}
@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+
+/// What a frame needs from the frames above it
+trait Chain<T> {
+    fn find(&self, id: &str) -> Option<&T>;
+    fn find_mut(&mut self, id: &str) -> Option<&mut T>;
+    fn take(&mut self, id: &str) -> Option<T>;
+    fn root_data(&mut self) -> &mut BTreeMap<String, T>;
+}
+
+/// A frame that owns its bindings and mutably borrows its parent, for code that
+/// keeps scopes on the Rust stack rather than sharing handles. While a child is
+/// alive its parent can only be reached through it, so every method is checked
+/// by the borrow checker instead of at runtime.
+///
+/// ```rust
+/// use scope_store::owned::ScopeOwned;
+/// let mut root = ScopeOwned::new();
+/// root.set_local("x".to_string(), 1);
+/// {
+///     let mut c = root.child();
+///     c.set("x".to_string(), 2);
+///     c.set_local("y".to_string(), 3);
+///     *c.get_mut("x").unwrap() += 10;
+///     assert_eq!(c.get("y"), Some(3));
+/// }
+/// assert_eq!(root.get("x"), Some(12));
+/// assert_eq!(root.get("y"), None);
+/// ```
+pub struct ScopeOwned<'p, T> {
+    data: BTreeMap<String, T>,
+    parent: Option<&'p mut dyn Chain<T>>,
+    depth: usize,
+}
+
+impl<T> ScopeOwned<'static, T> {
+    pub fn new() -> Self {
+        ScopeOwned {
+            data: BTreeMap::new(),
+            parent: None,
+            depth: 0,
+        }
+    }
+}
+
+impl<T> Default for ScopeOwned<'static, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'p, T> ScopeOwned<'p, T> {
+    pub fn child(&mut self) -> ScopeOwned<'_, T> {
+        ScopeOwned {
+            data: BTreeMap::new(),
+            depth: self.depth + 1,
+            parent: Some(self),
+        }
+    }
+
+    /// Number of parents above this frame
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn set_local(&mut self, id: String, val: T) {
+        self.data.insert(id, val);
+    }
+
+    pub fn set_global(&mut self, id: String, val: T) {
+        self.root_data().insert(id, val);
+    }
+
+    /// Replaces id where it is defined, or creates it here
+    pub fn set(&mut self, id: String, val: T) {
+        if let Some(v) = self.try_replace(&id, val) {
+            self.data.insert(id, v);
+        }
+    }
+
+    /// Some<T> means id is not defined, and gives back val
+    pub fn try_replace(&mut self, id: &str, val: T) -> Option<T> {
+        match self.get_mut(id) {
+            Some(v) => {
+                *v = val;
+                None
+            }
+            None => Some(val),
+        }
+    }
+
+    pub fn update<F: FnOnce(&mut T) -> A, A>(&mut self, id: &str, f: F) -> Option<A> {
+        self.get_mut(id).map(f)
+    }
+
+    pub fn get_mut(&mut self, id: &str) -> Option<&mut T> {
+        if self.data.contains_key(id) {
+            return self.data.get_mut(id);
+        }
+        self.parent.as_mut()?.find_mut(id)
+    }
+
+    pub fn with<R, F: FnOnce(&T) -> R>(&self, id: &str, f: F) -> Option<R> {
+        self.find(id).map(f)
+    }
+
+    pub fn remove_local(&mut self, id: &str) -> Option<T> {
+        self.data.remove(id)
+    }
+
+    /// Removes from the nearest frame that defines id
+    pub fn remove(&mut self, id: &str) -> Option<T> {
+        match self.data.remove(id) {
+            Some(v) => Some(v),
+            None => self.parent.as_mut()?.take(id),
+        }
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.find(id).is_some()
+    }
+
+    pub fn contains_local(&self, id: &str) -> bool {
+        self.data.contains_key(id)
+    }
+
+    /// The names set in this frame, sorted
+    pub fn local_keys(&self) -> Vec<String> {
+        self.data.keys().cloned().collect()
+    }
+}
+
+impl<'p, T: Clone> ScopeOwned<'p, T> {
+    pub fn get(&self, id: &str) -> Option<T> {
+        self.with(id, T::clone)
+    }
+}
+
+impl<'p, T> Chain<T> for ScopeOwned<'p, T> {
+    fn find(&self, id: &str) -> Option<&T> {
+        match self.data.get(id) {
+            Some(v) => Some(v),
+            None => self.parent.as_ref()?.find(id),
+        }
+    }
+
+    fn find_mut(&mut self, id: &str) -> Option<&mut T> {
+        self.get_mut(id)
+    }
+
+    fn take(&mut self, id: &str) -> Option<T> {
+        self.remove(id)
+    }
+
+    fn root_data(&mut self) -> &mut BTreeMap<String, T> {
+        match &mut self.parent {
+            Some(p) => p.root_data(),
+            None => &mut self.data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn children_borrow_parents() {
+        let mut root = ScopeOwned::new();
+        root.set_local("a".to_string(), 1);
+        let mut b = root.child();
+        let mut c = b.child();
+        assert_eq!(c.depth(), 2);
+        c.set_global("g".to_string(), 2);
+        c.set_local("a".to_string(), 3);
+        assert_eq!(c.local_keys(), vec!["a"]);
+        assert_eq!(c.remove_local("a"), Some(3));
+        assert_eq!(c.update("a", |n| *n * 10), Some(10));
+        assert_eq!(c.try_replace("z", 5), Some(5));
+        assert!(c.contains("g"));
+        assert!(!c.contains_local("g"));
+        assert_eq!(c.remove("a"), Some(1));
+        assert_eq!(c.with("g", |n| n + 1), Some(3));
+        drop(c);
+        b.set("q".to_string(), 4);
+        assert!(b.contains_local("q"));
+        drop(b);
+        assert_eq!(root.local_keys(), vec!["g"]);
+    }
+}
@@ -0,0 +1,180 @@
+use rpds::RedBlackTreeMap;
+use std::rc::Rc;
+
+type Map<T> = RedBlackTreeMap<Rc<str>, T>;
+
+struct Frame<T> {
+    data: Map<T>,
+    parent: Option<PersistentScope<T>>,
+    /// Number of parents above this frame
+    depth: usize,
+}
+
+/// A scope that is never changed in place. Every setter returns a new handle,
+/// which shares the untouched frames and most of the changed map with the old
+/// one, so keeping a handle from each step of an evaluation is cheap.
+///
+/// ```rust
+/// use scope_store::persistent::PersistentScope;
+/// let root = PersistentScope::new().set_local("x", 1);
+/// let c = root.child().set_local("y", 2);
+/// let later = c.set("x", 10).set("y", 20);
+///
+/// assert_eq!(later.get("x"), Some(&10));
+/// assert_eq!(later.parent().unwrap().get("x"), Some(&10));
+/// // the old handles still see the old bindings
+/// assert_eq!(c.get("x"), Some(&1));
+/// assert_eq!(c.get("y"), Some(&2));
+/// assert_eq!(root.get("y"), None);
+/// ```
+pub struct PersistentScope<T> {
+    f: Rc<Frame<T>>,
+}
+
+impl<T> Clone for PersistentScope<T> {
+    fn clone(&self) -> Self {
+        PersistentScope { f: self.f.clone() }
+    }
+}
+
+impl<T> PersistentScope<T> {
+    pub fn new() -> Self {
+        Self::frame(Map::new(), None)
+    }
+
+    fn frame(data: Map<T>, parent: Option<Self>) -> Self {
+        let depth = parent.as_ref().map_or(0, |p| p.f.depth + 1);
+        PersistentScope {
+            f: Rc::new(Frame {
+                data,
+                parent,
+                depth,
+            }),
+        }
+    }
+
+    /// A new empty frame below this one
+    pub fn child(&self) -> Self {
+        Self::frame(Map::new(), Some(self.clone()))
+    }
+
+    /// The frame above this one, as it was when this one was made
+    pub fn parent(&self) -> Option<Self> {
+        self.f.parent.clone()
+    }
+
+    /// Number of parents above this frame
+    pub fn depth(&self) -> usize {
+        self.f.depth
+    }
+
+    /// Whether both handles are the same version of the same frame
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.f, &other.f)
+    }
+
+    /// This frame then each of its parents
+    fn frames(&self) -> impl Iterator<Item = &Frame<T>> {
+        std::iter::successors(Some(&*self.f), |f| f.parent.as_ref().map(|p| &*p.f))
+    }
+
+    /// How many frames up the nearest one holding id is
+    fn owner(&self, id: &str) -> Option<usize> {
+        self.frames().position(|f| f.data.contains_key(id))
+    }
+
+    /// This scope with the map of the frame up levels above replaced,
+    /// making new copies of only the frames between
+    fn rebuild(&self, up: usize, data: Map<T>) -> Self {
+        let chain: Vec<&Frame<T>> = self.frames().take(up + 1).collect();
+        let mut res = Self::frame(data, chain[up].parent.clone());
+        for f in chain[..up].iter().rev() {
+            res = Self::frame(f.data.clone(), Some(res));
+        }
+        res
+    }
+
+    pub fn set_local(&self, id: &str, val: T) -> Self {
+        self.rebuild(0, self.f.data.insert(Rc::from(id), val))
+    }
+
+    pub fn set_global(&self, id: &str, val: T) -> Self {
+        let up = self.f.depth;
+        let root = self.frames().last().expect("a chain has a root");
+        self.rebuild(up, root.data.insert(Rc::from(id), val))
+    }
+
+    /// Replaces id where it is defined, or creates it here
+    pub fn set(&self, id: &str, val: T) -> Self {
+        let up = self.owner(id).unwrap_or(0);
+        let f = self.frames().nth(up).expect("the owner is in the chain");
+        self.rebuild(up, f.data.insert(Rc::from(id), val))
+    }
+
+    /// Removes id from the nearest frame that defines it
+    pub fn remove(&self, id: &str) -> Self {
+        match self.owner(id) {
+            Some(up) => {
+                let f = self.frames().nth(up).expect("the owner is in the chain");
+                self.rebuild(up, f.data.remove(id))
+            }
+            None => self.clone(),
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&T> {
+        self.frames().find_map(|f| f.data.get(id))
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.owner(id).is_some()
+    }
+
+    pub fn contains_local(&self, id: &str) -> bool {
+        self.f.data.contains_key(id)
+    }
+
+    /// The names set in this frame, sorted
+    pub fn local_keys(&self) -> Vec<String> {
+        self.f.data.keys().map(|k| k.to_string()).collect()
+    }
+}
+
+impl<T> Default for PersistentScope<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn versions_share_frames() {
+        let root = PersistentScope::new().set_local("g", 0);
+        let a = root.child().set_local("a", 1);
+        let b = a.child().set_local("b", 2);
+        assert_eq!(b.depth(), 2);
+
+        let b2 = b.set_local("b", 3);
+        assert!(b2.parent().unwrap().ptr_eq(&a));
+
+        let b3 = b2.set("a", 10).set_global("g", 5).set("new", 7);
+        assert_eq!(b3.get("a"), Some(&10));
+        assert_eq!(b3.parent().unwrap().parent().unwrap().get("g"), Some(&5));
+        assert_eq!(b3.local_keys(), vec!["b", "new"]);
+        assert!(b3.contains("g"));
+        assert!(!b3.contains_local("g"));
+
+        let b4 = b3.remove("a");
+        assert!(!b4.contains("a"));
+        assert!(b4.remove("a").ptr_eq(&b4));
+
+        // nothing made earlier has changed
+        assert_eq!(b.get("b"), Some(&2));
+        assert_eq!(b.get("a"), Some(&1));
+        assert_eq!(b.get("g"), Some(&0));
+        assert_eq!(b3.get("a"), Some(&10));
+        assert!(!root.contains("new"));
+    }
+}
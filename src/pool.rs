@@ -0,0 +1,172 @@
+use crate::cache::KeyCache;
+use crate::fork::Bindings;
+use crate::store::Store;
+use crate::{PScope, Scope, ScopeOptions};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// The maps of a dropped frame, emptied but keeping their allocations
+struct Spare<T> {
+    data: Store<T>,
+    cache: KeyCache,
+}
+
+struct Spares<T> {
+    frames: RefCell<Vec<Spare<T>>>,
+    limit: usize,
+}
+
+/// Keeps the maps of dropped frames so new children can reuse them, for
+/// interpreters that make and drop frames faster than the allocator likes.
+/// A pool can be shared by several trees, and holds at most `limit` frames.
+///
+/// ```rust
+/// use scope_store::{PScope, ScopeOptions, ScopePool};
+/// let pool = ScopePool::new(16);
+/// let root = PScope::with_pool(ScopeOptions::default(), &pool);
+/// for n in 0..3 {
+///     let c = root.child();
+///     c.set_local("n".to_string(), n);
+/// }
+/// assert_eq!(pool.len(), 1);
+///
+/// let c = root.child();
+/// assert_eq!(pool.len(), 0);
+/// assert_eq!(c.get("n"), None);
+/// ```
+pub struct ScopePool<T> {
+    spares: Rc<Spares<T>>,
+}
+
+impl<T> Clone for ScopePool<T> {
+    fn clone(&self) -> Self {
+        ScopePool {
+            spares: self.spares.clone(),
+        }
+    }
+}
+
+impl<T> ScopePool<T> {
+    pub fn new(limit: usize) -> Self {
+        ScopePool {
+            spares: Rc::new(Spares {
+                frames: RefCell::new(Vec::new()),
+                limit,
+            }),
+        }
+    }
+
+    /// How many frames are waiting to be reused
+    pub fn len(&self) -> usize {
+        self.spares.frames.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops every spare frame
+    pub fn clear(&self) {
+        let old = std::mem::take(&mut *self.spares.frames.borrow_mut());
+        drop(old);
+    }
+
+    /// Empties the maps of a frame and keeps them, unless the pool is full
+    fn give(&self, mut data: Store<T>, mut cache: KeyCache) {
+        if self.len() >= self.spares.limit {
+            return;
+        }
+        // values may drop frames of their own, so they go before the pool is borrowed
+        data.clear();
+        cache.clear();
+        let mut frames = self.spares.frames.borrow_mut();
+        if frames.len() < self.spares.limit {
+            frames.push(Spare { data, cache });
+        }
+    }
+
+    /// Fills a new frame with spare maps of the same kind, if there are any
+    fn take(&self, s: &mut Scope<T>) {
+        let kind = s.data.get_mut().kind();
+        let mut frames = self.spares.frames.borrow_mut();
+        if let Some(n) = frames.iter().rposition(|f| f.data.kind() == kind) {
+            let f = frames.swap_remove(n);
+            *s.data.get_mut() = f.data.into();
+            *s.cache.get_mut() = f.cache;
+        }
+    }
+}
+
+impl<T> Scope<T> {
+    fn pool(&self) -> Option<&ScopePool<T>> {
+        self.root_frame().pool.as_ref()
+    }
+
+    /// Hands the maps of a child frame that is going away to its tree's pool
+    pub(crate) fn recycle(&mut self) {
+        let pool = match (&self.root, self.pool()) {
+            (Some(_), Some(p)) => p.clone(),
+            _ => return,
+        };
+        let kind = self.data.get_mut().kind();
+        let data = match std::mem::replace(self.data.get_mut(), Store::new(kind).into()) {
+            Bindings::Own(d) => d,
+            // still read by a fork
+            Bindings::Shared(..) => return,
+        };
+        pool.give(data, std::mem::take(self.cache.get_mut()));
+    }
+
+    /// Reuses spare maps for a new frame in this tree
+    pub(crate) fn fill_from_pool(&self, s: &mut Scope<T>) {
+        if let Some(p) = self.pool() {
+            p.take(s);
+        }
+    }
+}
+
+impl<T> PScope<T> {
+    /// A new root whose dropped frames go to pool, and whose new frames come from it
+    pub fn with_pool(opts: ScopeOptions, pool: &ScopePool<T>) -> Self {
+        let mut s = Scope::new(opts);
+        s.pool = Some(pool.clone());
+        PScope { p: Rc::new(s) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Storage;
+    #[test]
+    fn frames_are_recycled() {
+        let pool = ScopePool::new(2);
+        let opts = ScopeOptions {
+            storage: Storage::Hash,
+            ..Default::default()
+        };
+        let root = PScope::with_pool(opts, &pool);
+        root.set_local("g".to_string(), 0);
+
+        let kids: Vec<_> = (0..3).map(|_| root.child_with_capacity(50)).collect();
+        kids[0].set_local("a".to_string(), 1);
+        drop(kids);
+        assert_eq!(pool.len(), 2);
+
+        let c = root.child();
+        assert_eq!(pool.len(), 1);
+        assert_eq!(c.local_keys(), Vec::<String>::new());
+        assert_eq!(c.get("g"), Some(0));
+        match &**c.p.data.borrow() {
+            Store::Hash(m) => assert!(m.capacity() >= 50),
+            _ => panic!("the spare map should have been reused"),
+        }
+
+        // a pool shared with a tree of another kind has nothing to give it
+        let other = PScope::with_pool(ScopeOptions::default(), &pool);
+        drop(other.child());
+        assert_eq!(pool.len(), 2);
+        pool.clear();
+        assert!(pool.is_empty());
+    }
+}
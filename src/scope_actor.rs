@@ -0,0 +1,220 @@
+use crate::PScope;
+use std::collections::HashMap;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+
+type Job<T> = Box<dyn FnOnce(&PScope<T>) + Send>;
+
+enum Msg<T> {
+    /// Run a job on a frame, which replies itself if it needs to
+    Run(usize, Job<T>),
+    /// Make a child of a frame, replying with its number
+    Child(usize, Sender<usize>),
+    /// The last handle on a frame is gone
+    Release(usize),
+}
+
+/// A frame on the actor, which is let go when its last handle drops
+struct Frame<T> {
+    id: usize,
+    tx: Sender<Msg<T>>,
+}
+
+impl<T> Drop for Frame<T> {
+    fn drop(&mut self) {
+        // if the actor has stopped there is nothing to let go
+        let _ = self.tx.send(Msg::Release(self.id));
+    }
+}
+
+/// A handle on one frame of a `PScope` tree owned by its own thread.
+/// The handle is `Send` and `Sync` whenever T is `Send`, so any thread may hold
+/// one. Every call is a message to the owning thread, answered on a channel of
+/// its own, so calls from one thread happen in order. The thread stops when
+/// every handle on the tree has been dropped.
+///
+/// A closure that panics on the owning thread is caught there, and the panic
+/// carries on in the thread whose call gave it, so the tree and every other
+/// handle on it keep working.
+pub struct ScopeHandle<T> {
+    f: Arc<Frame<T>>,
+}
+
+impl<T> Clone for ScopeHandle<T> {
+    fn clone(&self) -> Self {
+        ScopeHandle { f: self.f.clone() }
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::scope_actor::ScopeHandle;
+/// let root = ScopeHandle::spawn();
+/// root.set_local("x".to_string(), 1);
+/// let c = root.child();
+///
+/// let t = std::thread::spawn(move || {
+///     c.set("x".to_string(), 2);
+///     c.set_local("y".to_string(), 3);
+///     c.update("y", |n| *n * 10)
+/// });
+/// assert_eq!(t.join().unwrap(), Some(30));
+/// assert_eq!(root.get("x"), Some(2));
+/// assert_eq!(root.get("y"), None);
+/// ```
+impl<T: Send + 'static> ScopeHandle<T> {
+    /// A new tree on its own thread, with an empty root
+    pub fn spawn() -> Self {
+        Self::spawn_with(PScope::new)
+    }
+
+    /// A new tree on its own thread, whose root is made there by make,
+    /// so it can be given options and builtins that are not `Send`
+    pub fn spawn_with<F: FnOnce() -> PScope<T> + Send + 'static>(make: F) -> Self {
+        let (tx, rx) = channel::<Msg<T>>();
+        std::thread::spawn(move || {
+            let mut frames = HashMap::new();
+            frames.insert(0, make());
+            let mut next = 1;
+            for m in rx {
+                match m {
+                    Msg::Run(f, job) => job(&frames[&f]),
+                    Msg::Child(f, reply) => {
+                        frames.insert(next, frames[&f].child());
+                        let _ = reply.send(next);
+                        next += 1;
+                    }
+                    Msg::Release(f) => drop(frames.remove(&f)),
+                }
+            }
+        });
+        ScopeHandle {
+            f: Arc::new(Frame { id: 0, tx }),
+        }
+    }
+
+    fn send(&self, m: Msg<T>) {
+        self.f.tx.send(m).expect("the scope actor stopped");
+    }
+
+    /// Runs f on this frame on the owning thread, and waits for its result.
+    /// Every other call is made through this.
+    pub fn call<R, F>(&self, f: F) -> R
+    where
+        R: Send + 'static,
+        F: FnOnce(&PScope<T>) -> R + Send + 'static,
+    {
+        let (tx, rx) = channel();
+        let job = move |s: &PScope<T>| drop(tx.send(catch_unwind(AssertUnwindSafe(|| f(s)))));
+        self.send(Msg::Run(self.f.id, Box::new(job)));
+        match rx.recv().expect("the scope actor stopped") {
+            Ok(r) => r,
+            Err(p) => resume_unwind(p),
+        }
+    }
+
+    pub fn child(&self) -> Self {
+        let (tx, rx) = channel();
+        self.send(Msg::Child(self.f.id, tx));
+        let id = rx.recv().expect("the scope actor stopped");
+        ScopeHandle {
+            f: Arc::new(Frame {
+                id,
+                tx: self.f.tx.clone(),
+            }),
+        }
+    }
+
+    pub fn set_local(&self, id: String, val: T) {
+        self.call(move |s| s.set_local(id, val))
+    }
+
+    pub fn set_global(&self, id: String, val: T) {
+        self.call(move |s| s.set_global(id, val))
+    }
+
+    pub fn set(&self, id: String, val: T) {
+        self.call(move |s| s.set(id, val))
+    }
+
+    /// f runs on the owning thread, so must be `Send`, as must its result
+    pub fn update<F, A>(&self, id: &str, f: F) -> Option<A>
+    where
+        F: FnOnce(&mut T) -> A + Send + 'static,
+        A: Send + 'static,
+    {
+        let id = id.to_string();
+        self.call(move |s| s.update(&id, f))
+    }
+
+    pub fn remove(&self, id: &str) -> Option<T> {
+        let id = id.to_string();
+        self.call(move |s| s.remove(&id))
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        let id = id.to_string();
+        self.call(move |s| s.contains(&id))
+    }
+}
+
+impl<T: Clone + Send + 'static> ScopeHandle<T> {
+    pub fn get(&self, id: &str) -> Option<T> {
+        let id = id.to_string();
+        self.call(move |s| s.get(&id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn handles_share_one_tree() {
+        let root = ScopeHandle::spawn_with(|| {
+            let r = PScope::new();
+            r.set_local("n".to_string(), 0);
+            r
+        });
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let c = root.child();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        c.update("n", |n| *n += 1);
+                    }
+                    c.set_local("mine".to_string(), 1);
+                    c.get("mine")
+                })
+            })
+            .collect();
+        for h in handles {
+            assert_eq!(h.join().unwrap(), Some(1));
+        }
+        assert_eq!(root.get("n"), Some(400));
+        let more = vec![1, 2];
+        root.update("n", move |n| *n -= more.into_iter().sum::<i32>());
+        assert_eq!(root.get("n"), Some(397));
+        assert!(!root.contains("mine"));
+
+        // the four children were let go with their handles
+        assert_eq!(root.call(|s| std::rc::Rc::strong_count(&s.p)), 1);
+        assert_eq!(root.child().remove("n"), Some(397));
+    }
+
+    #[test]
+    fn a_panicking_call_stops_only_itself() {
+        let root = ScopeHandle::spawn();
+        root.set_local("n".to_string(), 1);
+        let c = root.child();
+        let r2 = root.clone();
+        let t = std::thread::spawn(move || r2.update("n", |_| panic!("in update")));
+        assert!(t.join().is_err());
+        let res = catch_unwind(|| c.call(|_| panic!("in call")));
+        assert_eq!(res.unwrap_err().downcast_ref(), Some(&"in call"));
+
+        assert_eq!(c.get("n"), Some(1));
+        c.set("n".to_string(), 2);
+        assert_eq!(root.get("n"), Some(2));
+    }
+}
@@ -0,0 +1,184 @@
+use crate::{PScope, Sym};
+
+/// A binding resolved ahead of time, see `PScope::resolve`.
+/// It holds on to the frame it points at, and never walks the chain again,
+/// so a binding made later that would shadow it is not seen.
+pub struct Slot<T> {
+    frame: PScope<T>,
+    sym: Sym,
+}
+
+impl<T> Clone for Slot<T> {
+    fn clone(&self) -> Self {
+        Slot {
+            frame: self.frame.clone(),
+            sym: self.sym,
+        }
+    }
+}
+
+impl<T> Slot<T> {
+    /// The frame holding the binding
+    pub fn frame(&self) -> &PScope<T> {
+        &self.frame
+    }
+
+    pub fn sym(&self) -> Sym {
+        self.sym
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// root.set_local("x".to_string(), 1);
+/// let c = root.child().child();
+///
+/// let x = c.resolve("x").unwrap();
+/// c.set_slot(&x, 5);
+/// assert_eq!(root.get("x"), Some(5));
+/// assert_eq!(c.get_slot(&x), Some(5));
+/// assert!(c.resolve("y").is_none());
+/// ```
+impl<T> PScope<T> {
+    /// Finds the frame defining name once, for repeated access through get_slot and set_slot
+    pub fn resolve(&self, name: &str) -> Option<Slot<T>> {
+        let sym = self.p.sym(name)?;
+        let owner = self.p.owner(sym)?;
+        Some(Slot {
+            frame: self.handle(owner),
+            sym,
+        })
+    }
+
+    /// A slot in this frame, whether or not it is set yet
+    pub fn local_slot(&self, name: &str) -> Slot<T> {
+        Slot {
+            frame: self.clone(),
+            sym: self.p.intern(name),
+        }
+    }
+
+    /// Writes to the slot's frame, recreating the binding there if it was removed
+    pub fn set_slot(&self, s: &Slot<T>, val: T) {
+        s.frame.p.set_local(s.sym, val);
+    }
+
+    pub fn update_slot<F: FnOnce(&mut T) -> A, A>(&self, s: &Slot<T>, f: F) -> Option<A> {
+        s.frame.p.update_local(&self.p, s.sym, f)
+    }
+
+    pub fn with_slot<R, F: FnOnce(&T) -> R>(&self, s: &Slot<T>, f: F) -> Option<R> {
+        if !s.frame.p.allow_get(s.sym) {
+            return None;
+        }
+        s.frame.p.purge_here(s.sym);
+        s.frame.p.data().get(s.sym).map(f)
+    }
+}
+
+impl<T: Clone> PScope<T> {
+    pub fn get_slot(&self, s: &Slot<T>) -> Option<T> {
+        self.with_slot(s, T::clone)
+    }
+
+    pub fn get_at(&self, up: usize, s: Sym) -> Option<T> {
+        self.with_at(up, s, T::clone)
+    }
+}
+
+/// Addresses a binding by how many frames above this one it sits, and its Sym.
+/// Unlike a Slot, the coordinates hold no frame, so they can be computed once
+/// and used from any scope of the same shape.
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// root.set_local("x".to_string(), 1);
+/// let c = root.child();
+///
+/// let (up, x) = c.locate("x").unwrap();
+/// assert_eq!(up, 1);
+/// c.set_at(up, x, 2);
+///
+/// let d = root.child();
+/// assert_eq!(d.get_at(up, x), Some(2));
+/// assert_eq!(d.get_at(5, x), None);
+/// ```
+impl<T> PScope<T> {
+    pub fn locate(&self, name: &str) -> Option<(usize, Sym)> {
+        let sym = self.p.sym(name)?;
+        let up = self.p.frames().position(|f| f.defines(sym))?;
+        Some((up, sym))
+    }
+
+    /// Does nothing if there are not that many frames above this one
+    pub fn set_at(&self, up: usize, s: Sym, val: T) {
+        if let Some(f) = self.p.frames().nth(up) {
+            f.set_local(s, val);
+        }
+    }
+
+    pub fn update_at<F: FnOnce(&mut T) -> A, A>(&self, up: usize, s: Sym, f: F) -> Option<A> {
+        self.p.frames().nth(up)?.update_local(&self.p, s, f)
+    }
+
+    pub fn with_at<R, F: FnOnce(&T) -> R>(&self, up: usize, s: Sym, f: F) -> Option<R> {
+        if !self.p.allow_get(s) {
+            return None;
+        }
+        let fr = self.p.frames().nth(up)?;
+        fr.purge_here(s);
+        fr.data().get(s).map(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn slots_ignore_later_shadowing() {
+        let root = PScope::new();
+        root.set_local("x".to_string(), 1);
+        let c = root.child();
+        let x = c.resolve("x").unwrap();
+        assert!(PScope::ptr_eq(x.frame(), &root));
+
+        c.set_local("x".to_string(), 10);
+        assert_eq!(c.get_slot(&x), Some(1));
+        assert_eq!(c.update_slot(&x, |n| *n += 1), Some(()));
+        assert_eq!(root.get("x"), Some(2));
+
+        root.remove("x");
+        assert_eq!(c.get_slot(&x), None);
+        assert_eq!(c.update_slot(&x, |n| *n += 1), None);
+        c.set_slot(&x, 3);
+        assert_eq!(root.get("x"), Some(3));
+
+        let y = c.local_slot("y");
+        assert_eq!(c.get_slot(&y), None);
+        c.set_slot(&y, 4);
+        assert_eq!(c.get("y"), Some(4));
+        assert_eq!(root.get("y"), None);
+    }
+
+    #[test]
+    fn coordinates_count_frames() {
+        let root = PScope::new();
+        root.set_local("x".to_string(), 1);
+        let c = root.child().child();
+        c.set_local("y".to_string(), 2);
+
+        let (up, x) = c.locate("x").unwrap();
+        assert_eq!(up, 2);
+        assert_eq!(c.locate("y").map(|(up, _)| up), Some(0));
+        assert_eq!(c.locate("z"), None);
+
+        assert_eq!(c.update_at(up, x, |n| *n + 1), Some(2));
+        assert_eq!(c.get_at(up, x), Some(1));
+        assert_eq!(c.get_at(0, x), None);
+        c.set_at(3, x, 9);
+        assert_eq!(c.get("x"), Some(1));
+    }
+}
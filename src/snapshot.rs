@@ -0,0 +1,141 @@
+use crate::store::Store;
+use crate::{Attrs, PScope, Scope};
+use std::rc::{Rc, Weak};
+
+/// The saved bindings of some frames, see `PScope::snapshot`
+pub struct ScopeSnapshot<T> {
+    frames: Vec<Saved<T>>,
+}
+
+impl<T> ScopeSnapshot<T> {
+    /// The innermost frame saved
+    pub(crate) fn frame(&self) -> &PScope<T> {
+        &self.frames[0].frame
+    }
+
+    pub(crate) fn downgrade(self) -> WeakSnapshot<T> {
+        let frames = (self.frames.into_iter())
+            .map(|s| (Rc::downgrade(&s.frame.p), s.data, s.attrs))
+            .collect();
+        WeakSnapshot { frames }
+    }
+}
+
+/// A snapshot holding its frames weakly, so it can be kept on the tree itself
+pub(crate) struct WeakSnapshot<T> {
+    frames: Vec<(Weak<Scope<T>>, Store<T>, Attrs)>,
+}
+
+impl<T: Clone> WeakSnapshot<T> {
+    /// A copy of the saved frames that are still alive, None if none are
+    pub(crate) fn upgrade(&self) -> Option<ScopeSnapshot<T>> {
+        let frames: Vec<Saved<T>> = (self.frames.iter())
+            .filter_map(|(f, data, attrs)| {
+                Some(Saved {
+                    frame: PScope { p: f.upgrade()? },
+                    data: data.clone(),
+                    attrs: attrs.clone(),
+                })
+            })
+            .collect();
+        (!frames.is_empty()).then_some(ScopeSnapshot { frames })
+    }
+}
+
+struct Saved<T> {
+    frame: PScope<T>,
+    data: Store<T>,
+    attrs: Attrs,
+}
+
+impl<T: Clone> Saved<T> {
+    fn new(frame: &PScope<T>) -> Self {
+        Saved {
+            frame: frame.clone(),
+            data: frame.p.data().clone(),
+            attrs: frame.p.attrs.borrow().clone(),
+        }
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// root.set_local("a".to_string(), 1);
+/// let c = root.child();
+///
+/// let snap = c.snapshot();
+/// c.set("a".to_string(), 2);
+/// c.set_local("b".to_string(), 3);
+/// c.restore(&snap);
+/// assert_eq!(root.get("a"), Some(1));
+/// assert_eq!(c.get("b"), None);
+/// ```
+impl<T: Clone> PScope<T> {
+    /// Saves every frame from here to the root
+    pub fn snapshot(&self) -> ScopeSnapshot<T> {
+        let mut frames = Vec::new();
+        let mut cur = Some(self);
+        while let Some(c) = cur {
+            frames.push(Saved::new(c));
+            cur = c.p.parent.as_ref();
+        }
+        ScopeSnapshot { frames }
+    }
+
+    /// Saves only this frame
+    pub fn snapshot_local(&self) -> ScopeSnapshot<T> {
+        ScopeSnapshot {
+            frames: vec![Saved::new(self)],
+        }
+    }
+
+    /// Resets every frame on this chain that is held in the snapshot,
+    /// back to how it was when the snapshot was taken, except for frozen frames.
+    /// Subscribers are not notified.
+    pub fn restore(&self, snap: &ScopeSnapshot<T>) {
+        let mut cur = Some(self);
+        while let Some(c) = cur {
+            let saved = snap.frames.iter().find(|s| Rc::ptr_eq(&s.frame.p, &c.p));
+            if let (Some(s), false) = (saved, c.p.frozen.get()) {
+                *c.p.data_mut() = s.data.clone();
+                *c.p.attrs.borrow_mut() = s.attrs.clone();
+                c.p.touch();
+            }
+            cur = c.p.parent.as_ref();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn restore_only_touches_own_chain() {
+        let root = PScope::new();
+        root.set_local("a".to_string(), 1);
+        let b = root.child();
+        b.set_local("b".to_string(), 2);
+        let snap = b.snapshot();
+
+        let d = root.child();
+        root.set_local("a".to_string(), 10);
+        b.set_local("b".to_string(), 20);
+
+        // d only shares the root with the snapshot
+        d.restore(&snap);
+        assert_eq!(root.get("a"), Some(1));
+        assert_eq!(b.get("b"), Some(20));
+
+        b.restore(&snap);
+        assert_eq!(b.get("b"), Some(2));
+
+        let local = b.snapshot_local();
+        root.set_local("a".to_string(), 10);
+        b.set_local("b".to_string(), 20);
+        b.restore(&local);
+        assert_eq!(b.get("b"), Some(2));
+        assert_eq!(b.get("a"), Some(10));
+    }
+}
@@ -0,0 +1,188 @@
+use crate::store::Store;
+use crate::sym::Interner;
+use crate::{Storage, Sym};
+
+/// A single chain of frames owned outright, for evaluators that only ever
+/// look at the innermost scope. There are no handles, so nothing is shared
+/// and nothing can be borrowed twice; the frames are a Vec with the root first.
+///
+/// ```rust
+/// use scope_store::stack::ScopeStack;
+/// let mut s = ScopeStack::new();
+/// s.set_local("x".to_string(), 1);
+/// s.push_frame();
+/// s.set("x".to_string(), 2);
+/// s.set_local("y".to_string(), 3);
+/// *s.get_mut("y").unwrap() += 1;
+/// assert_eq!(s.get("y"), Some(4));
+///
+/// assert!(s.pop_frame());
+/// assert_eq!(s.get("x"), Some(2));
+/// assert_eq!(s.get("y"), None);
+/// assert!(!s.pop_frame());
+/// ```
+pub struct ScopeStack<T> {
+    frames: Vec<Store<T>>,
+    names: Interner,
+}
+
+impl<T> ScopeStack<T> {
+    pub fn new() -> Self {
+        Self::with_storage(Storage::default())
+    }
+
+    /// A stack whose frames use the given map
+    pub fn with_storage(storage: Storage) -> Self {
+        ScopeStack {
+            frames: vec![Store::new(storage)],
+            names: Interner::default(),
+        }
+    }
+
+    /// Starts a new innermost frame
+    pub fn push_frame(&mut self) {
+        let kind = self.frames[0].kind();
+        self.frames.push(Store::new(kind));
+    }
+
+    /// Drops the innermost frame and its bindings.
+    /// Returns false, doing nothing, if only the root is left.
+    pub fn pop_frame(&mut self) -> bool {
+        if self.frames.len() == 1 {
+            return false;
+        }
+        self.frames.pop();
+        true
+    }
+
+    /// Number of frames above the root
+    pub fn depth(&self) -> usize {
+        self.frames.len() - 1
+    }
+
+    fn top(&mut self) -> &mut Store<T> {
+        self.frames.last_mut().expect("the root is never popped")
+    }
+
+    /// Index of the innermost frame holding id
+    fn owner(&self, id: &str) -> Option<(usize, Sym)> {
+        let s = self.names.get(id)?;
+        let n = self.frames.iter().rposition(|f| f.contains_key(s))?;
+        Some((n, s))
+    }
+
+    pub fn set_local(&mut self, id: String, val: T) {
+        let s = self.names.intern(&id);
+        self.top().insert(s, val);
+    }
+
+    pub fn set_global(&mut self, id: String, val: T) {
+        let s = self.names.intern(&id);
+        self.frames[0].insert(s, val);
+    }
+
+    /// Replaces id where it is defined, or creates it in the innermost frame
+    pub fn set(&mut self, id: String, val: T) {
+        if let Some(v) = self.try_replace(&id, val) {
+            self.set_local(id, v);
+        }
+    }
+
+    /// Some<T> means id is not defined, and gives back val
+    pub fn try_replace(&mut self, id: &str, val: T) -> Option<T> {
+        match self.get_mut(id) {
+            Some(v) => {
+                *v = val;
+                None
+            }
+            None => Some(val),
+        }
+    }
+
+    pub fn update<F: FnOnce(&mut T) -> A, A>(&mut self, id: &str, f: F) -> Option<A> {
+        self.get_mut(id).map(f)
+    }
+
+    pub fn get_mut(&mut self, id: &str) -> Option<&mut T> {
+        let (n, s) = self.owner(id)?;
+        self.frames[n].get_mut(s)
+    }
+
+    pub fn with<R, F: FnOnce(&T) -> R>(&self, id: &str, f: F) -> Option<R> {
+        let (n, s) = self.owner(id)?;
+        self.frames[n].get(s).map(f)
+    }
+
+    pub fn remove_local(&mut self, id: &str) -> Option<T> {
+        let s = self.names.get(id)?;
+        self.top().remove(s)
+    }
+
+    /// Removes from the innermost frame that defines id
+    pub fn remove(&mut self, id: &str) -> Option<T> {
+        let (n, s) = self.owner(id)?;
+        self.frames[n].remove(s)
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.owner(id).is_some()
+    }
+
+    pub fn contains_local(&self, id: &str) -> bool {
+        match self.names.get(id) {
+            Some(s) => self.frames[self.frames.len() - 1].contains_key(s),
+            None => false,
+        }
+    }
+
+    /// The names set in the innermost frame, sorted
+    pub fn local_keys(&self) -> Vec<String> {
+        let top = &self.frames[self.frames.len() - 1];
+        let mut res: Vec<String> = (top.iter())
+            .map(|(k, _)| self.names.name(*k).to_string())
+            .collect();
+        res.sort();
+        res
+    }
+}
+
+impl<T> Default for ScopeStack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> ScopeStack<T> {
+    pub fn get(&self, id: &str) -> Option<T> {
+        self.with(id, T::clone)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn frames_push_and_pop() {
+        let mut s = ScopeStack::with_storage(Storage::Hash);
+        s.set_local("a".to_string(), 1);
+        s.push_frame();
+        s.push_frame();
+        assert_eq!(s.depth(), 2);
+        s.set_global("g".to_string(), 2);
+        s.set_local("b".to_string(), 3);
+        s.set_local("a".to_string(), 4);
+        assert_eq!(s.local_keys(), vec!["a", "b"]);
+        assert!(s.contains_local("b"));
+        assert!(!s.contains_local("g"));
+
+        assert_eq!(s.remove_local("a"), Some(4));
+        assert_eq!(s.update("a", |n| *n * 10), Some(10));
+        assert_eq!(s.try_replace("z", 5), Some(5));
+        assert_eq!(s.remove("g"), Some(2));
+        assert!(!s.contains("g"));
+
+        s.pop_frame();
+        assert_eq!(s.get("b"), None);
+        assert_eq!(s.with("a", |n| n + 1), Some(2));
+    }
+}
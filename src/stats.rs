@@ -0,0 +1,128 @@
+use crate::{PScope, Scope, Sym};
+use std::collections::BTreeMap;
+
+/// Counts of the lookups made in a tree, see `ScopeOptions::stats`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScopeStats {
+    /// Searches for the frame defining a key
+    pub lookups: u64,
+    /// Frames checked over all the lookups, including the one that was found.
+    /// Frames that define nothing are passed over without a check.
+    pub frames_walked: u64,
+    /// Lookups of keys no frame defined
+    pub misses: u64,
+    /// Lookups answered from the cache, which walk no frames
+    pub cached: u64,
+    /// Lookups that found the key, by key
+    pub hits: BTreeMap<String, u64>,
+}
+
+impl ScopeStats {
+    /// Frames looked at per lookup
+    pub fn average_depth(&self) -> f64 {
+        match self.lookups {
+            0 => 0.0,
+            n => self.frames_walked as f64 / n as f64,
+        }
+    }
+}
+
+/// Kept on the root of a tree made with stats on
+#[derive(Default)]
+pub(crate) struct Counters {
+    lookups: u64,
+    frames_walked: u64,
+    misses: u64,
+    cached: u64,
+    hits: BTreeMap<Sym, u64>,
+}
+
+impl<T> Scope<T> {
+    /// walked is 0 when the lookup cache answered
+    pub(crate) fn record(&self, k: Sym, walked: usize, found: bool) {
+        let mut c = match &self.tree().stats {
+            Some(c) => c.borrow_mut(),
+            None => return,
+        };
+        c.lookups += 1;
+        c.frames_walked += walked as u64;
+        if walked == 0 {
+            c.cached += 1;
+        }
+        match found {
+            true => *c.hits.entry(k).or_insert(0) += 1,
+            false => c.misses += 1,
+        }
+    }
+}
+
+/// ```rust
+/// use scope_store::{PScope, ScopeOptions};
+/// let root = PScope::with_options(ScopeOptions {
+///     stats: true,
+///     ..Default::default()
+/// });
+/// root.set_local("x".to_string(), 1);
+/// let c = root.child().child();
+/// c.get("x");
+/// c.get("x");
+///
+/// let s = c.stats().unwrap();
+/// assert_eq!(s.hits["x"], 2);
+/// assert_eq!(s.average_depth(), 2.0);
+/// assert!(PScope::<i32>::new().stats().is_none());
+/// ```
+impl<T> PScope<T> {
+    /// The counts so far for the whole tree, or None if it was made without stats
+    pub fn stats(&self) -> Option<ScopeStats> {
+        let c = self.p.tree().stats.as_ref()?.borrow();
+        Some(ScopeStats {
+            lookups: c.lookups,
+            frames_walked: c.frames_walked,
+            misses: c.misses,
+            cached: c.cached,
+            hits: (c.hits.iter())
+                .map(|(k, n)| (self.p.name(*k).to_string(), *n))
+                .collect(),
+        })
+    }
+
+    /// Starts the counts again from zero
+    pub fn reset_stats(&self) {
+        if let Some(c) = &self.p.tree().stats {
+            *c.borrow_mut() = Counters::default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ScopeOptions;
+    #[test]
+    fn lookups_are_counted() {
+        let root = PScope::with_options(ScopeOptions {
+            stats: true,
+            ..Default::default()
+        });
+        root.set_local("a".to_string(), 1);
+        let b = root.child();
+        b.set_local("b".to_string(), 2);
+        assert_eq!(b.get("a"), Some(1));
+        assert_eq!(b.get("b"), Some(2));
+        assert_eq!(b.get("c"), None);
+        b.intern("d");
+        assert_eq!(b.get("d"), None);
+
+        let s = root.stats().unwrap();
+        assert_eq!(s.lookups, 3);
+        assert_eq!(s.misses, 1);
+        assert_eq!(s.frames_walked, 5);
+        assert_eq!(s.hits.get("b"), Some(&1));
+        assert!(root.options().stats);
+
+        b.reset_stats();
+        assert_eq!(root.stats(), Some(ScopeStats::default()));
+        assert_eq!(ScopeStats::default().average_depth(), 0.0);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::hash::FxBuild;
+use crate::Sym;
+use std::collections::{BTreeMap, HashMap};
+
+#[cfg(feature = "indexmap")]
+use indexmap::IndexMap;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Which map backs the frames of a tree
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Storage {
+    /// Keys ordered by when the tree first saw them, the default
+    #[default]
+    BTree,
+    /// Faster lookups when key order doesn't matter
+    Hash,
+    /// Keys in the order they were set in each frame, which `local_keys`
+    /// and `visible` keep to
+    #[cfg(feature = "indexmap")]
+    Indexed,
+}
+
+/// Frames with at most this many bindings keep them in a sorted Vec
+const SMALL: usize = 8;
+
+/// The bindings of a single frame. Every frame starts Small,
+/// and moves to the map for its tree once it outgrows that.
+#[derive(Clone)]
+pub(crate) enum Store<T> {
+    /// Sorted by key, so it iterates in the same order as BTree
+    Small(Vec<(Sym, T)>, Storage),
+    BTree(BTreeMap<Sym, T>),
+    Hash(HashMap<Sym, T, FxBuild>),
+    /// Never Small, as that would lose the order
+    #[cfg(feature = "indexmap")]
+    Indexed(IndexMap<Sym, T>),
+}
+
+impl<T> Store<T> {
+    pub fn new(kind: Storage) -> Self {
+        match kind {
+            #[cfg(feature = "indexmap")]
+            Storage::Indexed => Store::Indexed(IndexMap::new()),
+            _ => Store::Small(Vec::new(), kind),
+        }
+    }
+
+    pub fn kind(&self) -> Storage {
+        match self {
+            Store::Small(_, k) => *k,
+            Store::BTree(_) => Storage::BTree,
+            Store::Hash(_) => Storage::Hash,
+            #[cfg(feature = "indexmap")]
+            Store::Indexed(_) => Storage::Indexed,
+        }
+    }
+
+    pub fn get(&self, k: Sym) -> Option<&T> {
+        match self {
+            Store::Small(v, _) => v.binary_search_by_key(&k, |e| e.0).ok().map(|n| &v[n].1),
+            Store::BTree(m) => m.get(&k),
+            Store::Hash(m) => m.get(&k),
+            #[cfg(feature = "indexmap")]
+            Store::Indexed(m) => m.get(&k),
+        }
+    }
+
+    pub fn get_mut(&mut self, k: Sym) -> Option<&mut T> {
+        match self {
+            Store::Small(v, _) => match v.binary_search_by_key(&k, |e| e.0) {
+                Ok(n) => Some(&mut v[n].1),
+                Err(_) => None,
+            },
+            Store::BTree(m) => m.get_mut(&k),
+            Store::Hash(m) => m.get_mut(&k),
+            #[cfg(feature = "indexmap")]
+            Store::Indexed(m) => m.get_mut(&k),
+        }
+    }
+
+    pub fn contains_key(&self, k: Sym) -> bool {
+        self.get(k).is_some()
+    }
+
+    pub fn insert(&mut self, k: Sym, val: T) -> Option<T> {
+        if let Store::Small(v, _) = self {
+            match v.binary_search_by_key(&k, |e| e.0) {
+                Ok(n) => return Some(std::mem::replace(&mut v[n].1, val)),
+                Err(n) if v.len() < SMALL => {
+                    v.insert(n, (k, val));
+                    return None;
+                }
+                Err(_) => self.grow(),
+            }
+        }
+        match self {
+            Store::Small(..) => unreachable!("small stores grow before they overflow"),
+            Store::BTree(m) => m.insert(k, val),
+            Store::Hash(m) => m.insert(k, val),
+            #[cfg(feature = "indexmap")]
+            Store::Indexed(m) => m.insert(k, val),
+        }
+    }
+
+    /// Makes room for n more keys. BTree maps can't reserve,
+    /// so they only skip the Small stage.
+    pub fn reserve(&mut self, n: usize) {
+        if let Store::Small(v, _) = self {
+            match v.len() + n <= SMALL {
+                true => return v.reserve(n),
+                false => self.grow(),
+            }
+        }
+        match self {
+            Store::Hash(m) => m.reserve(n),
+            #[cfg(feature = "indexmap")]
+            Store::Indexed(m) => m.reserve(n),
+            _ => {}
+        }
+    }
+
+    /// Moves a Small store into the map for its tree
+    fn grow(&mut self) {
+        if let Store::Small(v, kind) = self {
+            let items = std::mem::take(v).into_iter();
+            *self = match kind {
+                Storage::BTree => Store::BTree(items.collect()),
+                Storage::Hash => Store::Hash(items.collect()),
+                #[cfg(feature = "indexmap")]
+                Storage::Indexed => unreachable!("indexed stores are never small"),
+            };
+        }
+    }
+
+    pub fn remove(&mut self, k: Sym) -> Option<T> {
+        match self {
+            Store::Small(v, _) => match v.binary_search_by_key(&k, |e| e.0) {
+                Ok(n) => Some(v.remove(n).1),
+                Err(_) => None,
+            },
+            Store::BTree(m) => m.remove(&k),
+            Store::Hash(m) => m.remove(&k),
+            #[cfg(feature = "indexmap")]
+            Store::Indexed(m) => m.shift_remove(&k),
+        }
+    }
+
+    /// Whether iteration follows the order keys were set in
+    pub fn keeps_order(&self) -> bool {
+        match self {
+            #[cfg(feature = "indexmap")]
+            Store::Indexed(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Store::Small(v, _) => v.len(),
+            Store::BTree(m) => m.len(),
+            Store::Hash(m) => m.len(),
+            #[cfg(feature = "indexmap")]
+            Store::Indexed(m) => m.len(),
+        }
+    }
+
+    /// Removes every binding, keeping the allocation where the map allows
+    pub fn clear(&mut self) {
+        match self {
+            Store::Small(v, _) => v.clear(),
+            Store::BTree(m) => m.clear(),
+            Store::Hash(m) => m.clear(),
+            #[cfg(feature = "indexmap")]
+            Store::Indexed(m) => m.clear(),
+        }
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&Sym, &T)> + '_> {
+        match self {
+            Store::Small(v, _) => Box::new(v.iter().map(|(k, v)| (k, v))),
+            Store::BTree(m) => Box::new(m.iter()),
+            Store::Hash(m) => Box::new(m.iter()),
+            #[cfg(feature = "indexmap")]
+            Store::Indexed(m) => Box::new(m.iter()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn small_stores_grow() {
+        for kind in [Storage::BTree, Storage::Hash] {
+            let mut s = Store::new(kind);
+            for n in (0..20).rev() {
+                assert_eq!(s.insert(Sym(n), n), None);
+                assert_eq!(matches!(s, Store::Small(..)), n >= 12);
+            }
+            assert_eq!(s.kind(), kind);
+            assert_eq!(s.len(), 20);
+            assert_eq!(s.insert(Sym(3), 30), Some(3));
+            assert_eq!(s.remove(Sym(4)), Some(4));
+            assert_eq!(s.get(Sym(3)), Some(&30));
+            assert!(!s.contains_key(Sym(4)));
+        }
+
+        let mut s = Store::new(Storage::BTree);
+        for n in [5, 1, 3] {
+            s.insert(Sym(n), n);
+        }
+        *s.get_mut(Sym(1)).unwrap() = 10;
+        let keys: Vec<_> = s.iter().map(|(k, _)| k.0).collect();
+        assert_eq!(keys, vec![1, 3, 5]);
+        assert_eq!(s.remove(Sym(2)), None);
+    }
+
+    #[test]
+    fn reserve_sizes_stores() {
+        let mut s: Store<i32> = Store::new(Storage::Hash);
+        s.reserve(4);
+        assert!(matches!(&s, Store::Small(v, _) if v.capacity() >= 4));
+        s.reserve(20);
+        assert!(matches!(&s, Store::Hash(m) if m.capacity() >= 20));
+
+        let mut s: Store<i32> = Store::new(Storage::BTree);
+        s.reserve(20);
+        assert!(matches!(s, Store::BTree(_)));
+    }
+
+    #[cfg(feature = "indexmap")]
+    #[test]
+    fn indexed_keeps_order() {
+        let mut s = Store::new(Storage::Indexed);
+        for n in [5, 1, 3, 4] {
+            s.insert(Sym(n), n);
+        }
+        s.remove(Sym(1));
+        s.insert(Sym(5), 50);
+        let keys: Vec<_> = s.iter().map(|(k, _)| k.0).collect();
+        assert_eq!(keys, vec![5, 3, 4]);
+        assert_eq!(s.kind(), Storage::Indexed);
+    }
+}
@@ -0,0 +1,557 @@
+//! Fallible versions of the scope operations, for when silently
+//! creating a binding would hide a mistake, or a frame may already be borrowed.
+
+use crate::{PScope, Refused, Scope, Sym};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScopeError {
+    /// No frame in the chain defines the key
+    Undefined(String),
+    /// The key is already declared in this frame
+    AlreadyDeclared(String),
+    /// The key is const, where it is defined
+    ConstReassignment(String),
+    /// The frame that would be changed is frozen
+    Frozen(String),
+    /// The key is declared but has not been given a value yet
+    Uninitialized(String),
+    /// A new frame would be deeper than the tree's max_depth
+    DepthExceeded(usize),
+    /// The key's validator refused the value, with its reason
+    Invalid(String, String),
+    /// An `on_set` hook refused the value
+    Refused(String),
+}
+
+impl fmt::Display for ScopeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScopeError::Undefined(k) => write!(f, "'{}' is not defined", k),
+            ScopeError::AlreadyDeclared(k) => write!(f, "'{}' is already declared", k),
+            ScopeError::ConstReassignment(k) => write!(f, "'{}' is const", k),
+            ScopeError::Frozen(k) => write!(f, "cannot change '{}' in a frozen scope", k),
+            ScopeError::Uninitialized(k) => write!(f, "'{}' is used before it is set", k),
+            ScopeError::DepthExceeded(m) => write!(f, "scope depth is limited to {}", m),
+            ScopeError::Invalid(k, why) => write!(f, "invalid value for '{}': {}", k, why),
+            ScopeError::Refused(k) => write!(f, "the write to '{}' was refused", k),
+        }
+    }
+}
+
+impl std::error::Error for ScopeError {}
+
+/// A frame needed by the operation was already borrowed,
+/// such as while a `get_ref` guard on it is held
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BorrowError;
+
+impl fmt::Display for BorrowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "scope frame already borrowed")
+    }
+}
+
+impl std::error::Error for BorrowError {}
+
+/// The frame already has a value for the key, see `PScope::set_once`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AlreadySet;
+
+impl fmt::Display for AlreadySet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "binding already set")
+    }
+}
+
+impl std::error::Error for AlreadySet {}
+
+/// An event named a frame with no Frame event before it, see `PScope::replay`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnknownFrame(pub u64);
+
+impl fmt::Display for UnknownFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no frame {} in the journal", self.0)
+    }
+}
+
+impl std::error::Error for UnknownFrame {}
+
+impl<T> Scope<T> {
+    /// Like owner, failing instead of panicking on a borrowed frame
+    fn try_owner(&self, k: Sym) -> Result<Option<&Scope<T>>, BorrowError> {
+        for f in self.frames() {
+            let here = f.try_data()?.contains_key(k) || f.attrs.borrow().is_hole(k);
+            if here || f.try_import_of(k)?.is_some() {
+                return Ok(Some(f));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Like contains
+    pub(crate) fn try_has(&self, id: &str) -> Result<bool, BorrowError> {
+        let k = match self.sym(id) {
+            Some(k) if self.allow_get(k) => k,
+            _ => return Ok(false),
+        };
+        match self.try_owner(k)? {
+            Some(o) => Ok(o.try_data()?.contains_key(k) || o.try_import_of(k)?.is_some()),
+            None => Ok(false),
+        }
+    }
+
+    /// Like assign, once the hooks have passed val
+    fn try_assign(&self, k: Sym, val: T) -> Result<(), BorrowError> {
+        let (global, nonlocal) = {
+            let a = self.attrs.borrow();
+            (a.global.contains(&k), a.nonlocal.contains(&k))
+        };
+        if global {
+            return self.root_frame().try_store_local(k, val);
+        }
+        if let (true, Some(p)) = (nonlocal, &self.parent) {
+            return p.p.try_assign(k, val);
+        }
+        let target = match self.try_owner(k)? {
+            Some(o) if !o.frozen.get() => o,
+            Some(_) => self,
+            None => match self.create_target() {
+                Some(t) => t,
+                None => return Ok(()),
+            },
+        };
+        let here = target.try_data()?.contains_key(k) || target.attrs.borrow().is_hole(k);
+        if let (false, false, Some((s, n))) = (target.frozen.get(), here, target.try_import_of(k)?)
+        {
+            let mut val = val;
+            return match s.p.sym(&n) {
+                Some(k) if s.p.allow_set(k, &mut val) => s.p.try_assign(k, val),
+                _ => Ok(()),
+            };
+        }
+        target.try_store_local(k, val)
+    }
+
+    /// Like store_local
+    fn try_store_local(&self, k: Sym, val: T) -> Result<(), BorrowError> {
+        if self.frozen.get() {
+            return Ok(());
+        }
+        let old = self.try_data_mut()?.insert(k, val);
+        if old.is_none() {
+            self.touch();
+        }
+        self.fill(k);
+        self.undo_value(k, old.as_ref());
+        self.changed(k);
+        Ok(())
+    }
+}
+
+/// Versions of get, set and update that never panic on a frame that is already borrowed.
+/// Subscribers are still called, and may panic on their own.
+///
+/// ```rust
+/// use scope_store::{BorrowError, PScope};
+/// let root = PScope::new();
+/// root.set_local("x".to_string(), 1);
+/// root.set_local("y".to_string(), 2);
+///
+/// let r2 = root.clone();
+/// let res = root.try_update("x", |x| {
+///     assert_eq!(r2.try_get("y"), Err(BorrowError));
+///     *x += 1;
+/// });
+/// assert_eq!(res, Ok(Some(())));
+/// assert_eq!(root.try_get("x"), Ok(Some(2)));
+/// ```
+impl<T> PScope<T> {
+    pub fn try_set(&self, id: String, mut val: T) -> Result<(), BorrowError> {
+        let s = self.p.intern(&id);
+        match self.p.allow_set(s, &mut val) {
+            true => self.p.try_assign(s, val),
+            false => Ok(()),
+        }
+    }
+
+    pub fn try_update<F: FnOnce(&mut T) -> A, A>(
+        &self,
+        id: &str,
+        f: F,
+    ) -> Result<Option<A>, BorrowError> {
+        let s = match self.p.sym(id) {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+        let o = match self.p.try_owner(s)? {
+            Some(o) => o,
+            None => return Ok(None),
+        };
+        if !o.try_data()?.contains_key(s) {
+            return match o.try_import_of(s)? {
+                Some((src, n)) => src.try_update(&n, f),
+                None => Ok(None),
+            };
+        }
+        if o.frozen.get() {
+            return Ok(None);
+        }
+        let old = o.keep_for_check(&self.p, s);
+        let mut d = o.try_data_mut()?;
+        if let Some(v) = d.get(s) {
+            o.undo_value(s, Some(v));
+        }
+        let res = d.get_mut(s).map(f);
+        drop(d);
+        if o.check_change(&self.p, s, old).is_err() {
+            return Ok(None);
+        }
+        o.updated(s);
+        Ok(res)
+    }
+}
+
+impl<T: Clone> PScope<T> {
+    pub fn try_get(&self, id: &str) -> Result<Option<T>, BorrowError> {
+        let s = match self.p.sym(id) {
+            Some(s) if self.p.allow_get(s) => s,
+            _ => return Ok(None),
+        };
+        let o = match self.p.try_owner(s)? {
+            Some(o) => o,
+            None => return Ok(None),
+        };
+        if let Some(v) = o.try_data()?.get(s) {
+            return Ok(Some(v.clone()));
+        }
+        match o.try_import_of(s)? {
+            Some((src, n)) => src.try_get(&n),
+            None => Ok(None),
+        }
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::{PScope, ScopeError};
+/// let root = PScope::new();
+/// root.set_local("a".to_string(), 1);
+/// let c = root.child();
+///
+/// assert_eq!(c.assign("a", 2), Ok(()));
+/// assert_eq!(root.get("a"), Some(2));
+/// assert_eq!(c.assign("b", 2), Err(ScopeError::Undefined("b".to_string())));
+/// assert_eq!(c.get("b"), None);
+///
+/// assert_eq!(c.declare("a", 5), Ok(()));
+/// assert_eq!(c.declare("a", 6), Err(ScopeError::AlreadyDeclared("a".to_string())));
+/// assert_eq!(c.get("a"), Some(5));
+/// assert_eq!(root.get("a"), Some(2));
+/// ```
+impl<T> PScope<T> {
+    /// Replaces the value in the nearest frame that defines id
+    pub fn assign(&self, id: &str, val: T) -> Result<(), ScopeError> {
+        self.check_write(id)?;
+        self.check_valid(id, &val)?;
+        match self.try_replace(id, val) {
+            None => Ok(()),
+            Some(_) => Err(ScopeError::Undefined(id.to_string())),
+        }
+    }
+
+    /// Creates id in this frame, shadowing any outer binding
+    pub fn declare(&self, id: &str, val: T) -> Result<(), ScopeError> {
+        if self.is_frozen() {
+            return Err(ScopeError::Frozen(id.to_string()));
+        }
+        if self.p.defines_local(id) {
+            return Err(ScopeError::AlreadyDeclared(id.to_string()));
+        }
+        self.check_const(id)?;
+        self.check_valid(id, &val)?;
+        self.set_local(id.to_string(), val);
+        Ok(())
+    }
+
+    /// Declares id in this frame without a value. Until it is first set,
+    /// lookups stop here and find nothing, and `get_checked` fails with Uninitialized.
+    pub fn declare_uninit(&self, id: &str) -> Result<(), ScopeError> {
+        if self.is_frozen() {
+            return Err(ScopeError::Frozen(id.to_string()));
+        }
+        if self.p.defines_local(id) {
+            return Err(ScopeError::AlreadyDeclared(id.to_string()));
+        }
+        self.check_const(id)?;
+        let s = self.p.intern(id);
+        self.p.attrs.borrow_mut().uninit.insert(s);
+        self.p.touch();
+        Ok(())
+    }
+
+    /// The error for a lookup of id that found nothing
+    fn missing(&self, id: &str) -> ScopeError {
+        let uninit = match (self.p.sym(id), self.p.owner_str(id)) {
+            (Some(s), Some(o)) => o.attrs.borrow().uninit.contains(&s),
+            _ => false,
+        };
+        match uninit {
+            true => ScopeError::Uninitialized(id.to_string()),
+            false => ScopeError::Undefined(id.to_string()),
+        }
+    }
+
+    /// Whether the visible binding for id is const
+    pub fn is_const(&self, id: &str) -> bool {
+        let s = match self.p.sym(id) {
+            Some(s) => s,
+            None => return false,
+        };
+        match self.p.owner(s) {
+            Some(o) => o.attrs.borrow().consts.contains(&s),
+            None => false,
+        }
+    }
+
+    /// For changes that land in the frame defining id, or here if none does
+    fn check_write(&self, id: &str) -> Result<(), ScopeError> {
+        let frozen = match self.p.owner_str(id) {
+            Some(o) => o.frozen.get(),
+            None => self.is_frozen(),
+        };
+        if frozen {
+            return Err(ScopeError::Frozen(id.to_string()));
+        }
+        self.check_const(id)
+    }
+
+    /// For changes that land in this frame, and may shadow id
+    fn check_local(&self, id: &str) -> Result<(), ScopeError> {
+        if self.is_frozen() {
+            return Err(ScopeError::Frozen(id.to_string()));
+        }
+        self.check_const(id)
+    }
+
+    fn check_valid(&self, id: &str, val: &T) -> Result<(), ScopeError> {
+        match self.p.sym(id) {
+            Some(s) => {
+                (self.p.validate(s, val)).map_err(|why| ScopeError::Invalid(id.to_string(), why))
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn check_const(&self, id: &str) -> Result<(), ScopeError> {
+        match self.is_const(id) {
+            true => Err(ScopeError::ConstReassignment(id.to_string())),
+            false => Ok(()),
+        }
+    }
+
+    /// Creates id in this frame as a const.
+    /// Only the checked and strict methods refuse to change or shadow a const,
+    /// the plain `set` family ignores it.
+    pub fn set_const(&self, id: String, val: T) -> Result<(), ScopeError> {
+        self.check_local(&id)?;
+        self.check_valid(&id, &val)?;
+        let s = self.p.intern(&id);
+        self.p.attrs.borrow_mut().consts.insert(s);
+        self.set_local(id, val);
+        Ok(())
+    }
+
+    pub fn set_checked(&self, id: String, val: T) -> Result<(), ScopeError> {
+        self.check_write(&id)?;
+        if self.p.create_target().is_none() && self.p.owner_str(&id).is_none() {
+            return Err(ScopeError::Undefined(id));
+        }
+        self.check_valid(&id, &val)?;
+        self.set(id, val);
+        Ok(())
+    }
+
+    /// Fails if id is const here, or would shadow an outer const
+    pub fn set_local_checked(&self, id: String, val: T) -> Result<(), ScopeError> {
+        self.check_local(&id)?;
+        self.check_valid(&id, &val)?;
+        self.set_local(id, val);
+        Ok(())
+    }
+
+    /// Fails with `ScopeError::Invalid`, leaving the value as it was,
+    /// if the validator for id refuses the changed value
+    pub fn update_checked<F: FnOnce(&mut T) -> A, A>(
+        &self,
+        id: &str,
+        f: F,
+    ) -> Result<A, ScopeError> {
+        self.check_write(id)?;
+        let res = self.p.sym(id).and_then(|s| self.p.modify(s, f));
+        match res {
+            Some((_, Err(Refused::Invalid(why)))) => Err(ScopeError::Invalid(id.to_string(), why)),
+            // a change refused by a hook is dropped, as for set_checked
+            Some((a, _)) => Ok(a),
+            None => Err(self.missing(id)),
+        }
+    }
+}
+
+impl<T: Clone> PScope<T> {
+    pub fn get_checked(&self, id: &str) -> Result<T, ScopeError> {
+        self.get(id).ok_or_else(|| self.missing(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn consts_refuse_change() {
+        let root = PScope::new();
+        root.set_const("pi".to_string(), 3).unwrap();
+        let c = root.child();
+        let e = Err(ScopeError::ConstReassignment("pi".to_string()));
+
+        assert!(c.is_const("pi"));
+        assert_eq!(c.set_checked("pi".to_string(), 4), e);
+        assert_eq!(c.set_local_checked("pi".to_string(), 4), e.clone());
+        assert_eq!(c.update_checked("pi", |n| *n += 1), e.clone().map(|_| ()));
+        assert_eq!(c.assign("pi", 4), e.clone());
+        assert_eq!(c.declare("pi", 4), e.clone());
+        assert_eq!(c.set_const("pi".to_string(), 4), e.clone());
+        assert_eq!(c.get("pi"), Some(3));
+
+        assert_eq!(
+            c.update_checked("x", |n| *n),
+            Err(ScopeError::Undefined("x".to_string()))
+        );
+        c.set_local_checked("x".to_string(), 1).unwrap();
+        assert_eq!(c.update_checked("x", |n| *n + 1), Ok(2));
+        assert!(!c.is_const("x"));
+
+        // removing the binding removes the mark
+        root.remove("pi");
+        assert!(!c.is_const("pi"));
+        c.set_checked("pi".to_string(), 4).unwrap();
+    }
+
+    #[test]
+    fn frozen_refuses_change() {
+        let root = PScope::new();
+        root.set_local("print".to_string(), 1);
+        root.freeze();
+        let c = root.child();
+        let e = Err(ScopeError::Frozen("print".to_string()));
+
+        assert_eq!(c.assign("print", 2), e);
+        assert_eq!(c.set_checked("print".to_string(), 2), e.clone());
+        assert_eq!(c.update_checked("print", |n| *n), e.clone().map(|_| 0));
+        assert_eq!(root.declare("print", 2), e.clone());
+        assert_eq!(
+            root.set_local_checked("x".to_string(), 2),
+            Err(ScopeError::Frozen("x".to_string()))
+        );
+
+        // children can still shadow it
+        assert_eq!(c.declare("print", 2), Ok(()));
+        assert_eq!(c.get("print"), Some(2));
+        assert_eq!(root.get("print"), Some(1));
+    }
+
+    #[test]
+    fn uninit_until_set() {
+        let root = PScope::new();
+        root.set_local("x".to_string(), 1);
+        let c = root.child();
+        c.declare_uninit("x").unwrap();
+        let u = Err(ScopeError::Uninitialized("x".to_string()));
+
+        assert_eq!(c.get_checked("x"), u);
+        assert_eq!(c.get("x"), None);
+        assert!(!c.contains("x"));
+        assert_eq!(c.update_checked("x", |n| *n), u.clone());
+        assert_eq!(
+            c.declare("x", 2),
+            Err(ScopeError::AlreadyDeclared("x".to_string()))
+        );
+        assert_eq!(c.visible().count(), 0);
+        assert_eq!(
+            c.get_checked("y"),
+            Err(ScopeError::Undefined("y".to_string()))
+        );
+        root.mask("y");
+        assert_eq!(
+            c.get_checked("y"),
+            Err(ScopeError::Undefined("y".to_string()))
+        );
+
+        let d = c.child();
+        d.set("x".to_string(), 3);
+        assert_eq!(c.get_checked("x"), Ok(3));
+        assert_eq!(root.get("x"), Some(1));
+        assert!(!d.contains_local("x"));
+    }
+
+    #[test]
+    fn try_set_while_borrowed() {
+        let root = PScope::new();
+        root.set_local("a".to_string(), 1);
+        let c = root.child();
+        {
+            let _held = root.get_ref("a").unwrap();
+            assert_eq!(c.try_set("a".to_string(), 5), Err(BorrowError));
+            assert_eq!(c.try_update("a", |n| *n += 1), Err(BorrowError));
+            assert_eq!(c.try_get("a"), Ok(Some(1)));
+        }
+        c.try_set("b".to_string(), 6).unwrap();
+        c.try_set("a".to_string(), 7).unwrap();
+        assert_eq!(c.try_get("b"), Ok(Some(6)));
+        assert_eq!(root.try_get("a"), Ok(Some(7)));
+        assert_eq!(c.try_update("z", |n| *n), Ok(None));
+    }
+
+    #[test]
+    fn try_set_follows_marks() {
+        let root = PScope::new();
+        let outer = root.child();
+        let f = outer.child();
+        f.mark_global("x");
+        f.mark_nonlocal("n");
+
+        f.try_set("x".to_string(), 5).unwrap();
+        assert_eq!(root.get("x"), Some(5));
+        assert!(!f.contains_local("x"));
+        // made in the enclosing frame, as set would
+        f.try_set("n".to_string(), 2).unwrap();
+        assert!(outer.contains_local("n") && !f.contains_local("n"));
+        f.try_set("n".to_string(), 3).unwrap();
+        assert_eq!(outer.try_get("n"), Ok(Some(3)));
+        {
+            let _held = root.get_ref("x").unwrap();
+            assert_eq!(f.try_set("x".to_string(), 6), Err(BorrowError));
+        }
+    }
+
+    #[test]
+    fn try_calls_see_imports() {
+        let lib = PScope::new();
+        lib.set_local("a".to_string(), 1);
+        let root = PScope::new();
+        root.import(&lib, &[("a", "b")]);
+        let c = root.child();
+
+        assert_eq!(c.try_get("b"), Ok(Some(1)));
+        assert_eq!(c.try_update("b", |n| *n += 1), Ok(Some(())));
+        assert_eq!(lib.get("a"), Some(2));
+        c.try_set("b".to_string(), 5).unwrap();
+        assert_eq!(lib.get("a"), Some(5));
+        assert!(!root.contains_local("b") && !c.contains_local("b"));
+        {
+            let _held = lib.get_ref("a").unwrap();
+            assert_eq!(c.try_set("b".to_string(), 6), Err(BorrowError));
+        }
+    }
+}
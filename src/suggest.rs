@@ -0,0 +1,85 @@
+use crate::{PScope, Scope, Sym};
+use std::collections::BTreeSet;
+
+impl<T> Scope<T> {
+    /// Every key with a value visible from this frame
+    pub(crate) fn visible_keys(&self) -> BTreeSet<Sym> {
+        let mut res = BTreeSet::new();
+        let mut hidden = BTreeSet::new();
+        for f in self.frames() {
+            for (k, _) in f.data().iter() {
+                if !hidden.contains(k) {
+                    res.insert(*k);
+                }
+            }
+            for k in f.imported_keys() {
+                if !hidden.contains(&k) {
+                    res.insert(k);
+                }
+            }
+            let a = f.attrs.borrow();
+            hidden.extend(a.uninit.iter().chain(&a.masked).copied());
+        }
+        res
+    }
+}
+
+/// The number of single char edits to turn a into b
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let sub = prev[j] + (ca != *cb) as usize;
+            cur.push(sub.min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// root.set_local("count".to_string(), 1);
+/// root.set_local("counter".to_string(), 1);
+/// root.set_local("total".to_string(), 1);
+/// assert_eq!(root.suggest("cont", 3), vec!["count"]);
+/// ```
+impl<T> PScope<T> {
+    /// Up to max visible keys close to id, closest first, for "did you mean" messages.
+    /// Keys count as close within one edit for every three chars of id, and at least one.
+    pub fn suggest(&self, id: &str, max: usize) -> Vec<String> {
+        let limit = (id.chars().count() / 3).max(1);
+        let mut found: Vec<(usize, String)> = (self.p.visible_keys().into_iter())
+            .map(|k| self.p.name(k).to_string())
+            .map(|k| (edit_distance(id, &k), k))
+            .filter(|(d, _)| *d <= limit)
+            .collect();
+        found.sort();
+        found.into_iter().take(max).map(|(_, k)| k).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn suggestions() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("same", "same"), 0);
+
+        let root = PScope::new();
+        root.set_local("value".to_string(), 1);
+        root.set_local("valve".to_string(), 1);
+        let c = root.child();
+        c.set_local("vale".to_string(), 1);
+        c.mask("valve");
+        assert_eq!(c.suggest("valu", 5), vec!["vale", "value"]);
+        assert_eq!(c.suggest("valu", 1), vec!["vale"]);
+        assert!(c.suggest("zzzz", 5).is_empty());
+    }
+}
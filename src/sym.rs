@@ -0,0 +1,213 @@
+use crate::hash::{Hashed, NameBuild, PassBuild};
+use crate::NameHasher;
+use crate::{PScope, Scope};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT_INTERNER: AtomicU64 = AtomicU64::new(0);
+
+/// An interned key. Every frame stores its bindings by Sym, so lookups by Sym
+/// never compare strings. A Sym only means something within the tree that made it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Sym(pub(crate) u32);
+
+/// Kept on the root, shared by the whole tree. Names are found by their hash,
+/// made once by the tree's hasher, so a `Hashed` name is never hashed again.
+pub(crate) struct Interner {
+    /// Never reused, so a Hashed can tell it was made here
+    id: u64,
+    build: NameBuild,
+    ids: HashMap<u64, Sym, PassBuild>,
+    /// Names whose hash another name already had, almost always empty
+    spill: Vec<Sym>,
+    names: Vec<Rc<str>>,
+}
+
+impl Default for Interner {
+    fn default() -> Self {
+        Self::with_build(NameBuild::default())
+    }
+}
+
+impl Interner {
+    pub fn new(h: NameHasher) -> Self {
+        Self::with_build(NameBuild::new(h))
+    }
+
+    pub fn with_build(build: NameBuild) -> Self {
+        Interner {
+            id: NEXT_INTERNER.fetch_add(1, Ordering::Relaxed),
+            build,
+            ids: HashMap::default(),
+            spill: Vec::new(),
+            names: Vec::new(),
+        }
+    }
+
+    pub fn hasher(&self) -> NameHasher {
+        self.build.kind()
+    }
+
+    pub fn hashed<K: AsRef<str>>(&self, key: K) -> Hashed<K> {
+        let hash = self.build.hash(key.as_ref());
+        Hashed::new(key, hash, self.id)
+    }
+
+    /// The hash of a name, reusing h's if it was made here
+    fn hash_of<K: AsRef<str>>(&self, h: &Hashed<K>) -> u64 {
+        match h.by == self.id {
+            true => h.hash,
+            false => self.build.hash(h.name()),
+        }
+    }
+
+    fn find(&self, hash: u64, name: &str) -> Option<Sym> {
+        let s = *self.ids.get(&hash)?;
+        if *self.names[s.0 as usize] == *name {
+            return Some(s);
+        }
+        (self.spill.iter().copied()).find(|s| *self.names[s.0 as usize] == *name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Sym> {
+        self.find(self.build.hash(name), name)
+    }
+
+    pub fn get_hashed<K: AsRef<str>>(&self, h: &Hashed<K>) -> Option<Sym> {
+        self.find(self.hash_of(h), h.name())
+    }
+
+    pub fn intern(&mut self, name: &str) -> Sym {
+        let hash = self.build.hash(name);
+        if let Some(s) = self.find(hash, name) {
+            return s;
+        }
+        let s = Sym(self.names.len() as u32);
+        self.names.push(Rc::from(name));
+        match self.ids.contains_key(&hash) {
+            true => self.spill.push(s),
+            false => drop(self.ids.insert(hash, s)),
+        }
+        s
+    }
+
+    pub fn name(&self, s: Sym) -> Rc<str> {
+        self.names[s.0 as usize].clone()
+    }
+
+    /// Every name, in the order they were interned
+    pub fn names(&self) -> impl Iterator<Item = &Rc<str>> {
+        self.names.iter()
+    }
+}
+
+impl<T> Scope<T> {
+    /// The Sym for k, if anything in the tree has ever used it.
+    /// Once the tree has an import_all any name may be visible, so every
+    /// name gets one, as does any the environment fallback has a variable for.
+    /// Any expired binding for k on the chain is also purged.
+    pub(crate) fn sym(&self, k: &str) -> Option<Sym> {
+        let s = self.tree().names.borrow().get(k);
+        self.sym_found(k, s)
+    }
+
+    /// Like sym, for a name hashed ahead of time
+    pub(crate) fn sym_hashed<K: AsRef<str>>(&self, k: &Hashed<K>) -> Option<Sym> {
+        let s = self.tree().names.borrow().get_hashed(k);
+        self.sym_found(k.name(), s)
+    }
+
+    /// The rest of sym, once the interner has been asked for k
+    fn sym_found(&self, k: &str, s: Option<Sym>) -> Option<Sym> {
+        let r = self.tree();
+        let s = match s {
+            None if r.imports_all.get() || self.env_has(k) => Some(self.intern(k)),
+            _ => s,
+        };
+        if let Some(s) = s {
+            self.purge_key(s);
+        }
+        s
+    }
+
+    pub(crate) fn intern(&self, k: &str) -> Sym {
+        self.tree().names.borrow_mut().intern(k)
+    }
+
+    pub(crate) fn name(&self, s: Sym) -> Rc<str> {
+        self.tree().names.borrow().name(s)
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// let c = root.child();
+/// let x = c.intern("x");
+///
+/// root.set_local("x".to_string(), 1);
+/// assert_eq!(c.update_sym(x, |n| *n += 1), Some(()));
+/// assert_eq!(c.get_sym(x), Some(2));
+/// c.set_sym(x, 3);
+/// assert_eq!(root.get("x"), Some(3));
+/// assert_eq!(&*root.sym_name(x), "x");
+/// ```
+impl<T> PScope<T> {
+    /// The Sym for name in this tree, the same from every frame
+    pub fn intern(&self, name: &str) -> Sym {
+        self.p.intern(name)
+    }
+
+    /// Panics if s was not made by this tree
+    pub fn sym_name(&self, s: Sym) -> Rc<str> {
+        self.p.name(s)
+    }
+
+    pub fn set_sym(&self, s: Sym, val: T) {
+        self.p.set(s, val);
+    }
+
+    pub fn set_local_sym(&self, s: Sym, val: T) {
+        self.p.set_local(s, val);
+    }
+
+    pub fn update_sym<F: FnOnce(&mut T) -> A, A>(&self, s: Sym, f: F) -> Option<A> {
+        self.p.update(s, f)
+    }
+
+    pub fn with_sym<R, F: FnOnce(&T) -> R>(&self, s: Sym, f: F) -> Option<R> {
+        self.p.with(s, f)
+    }
+}
+
+impl<T: Clone> PScope<T> {
+    pub fn get_sym(&self, s: Sym) -> Option<T> {
+        self.p.get(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn syms_shared_across_tree() {
+        let root: PScope<i32> = PScope::new();
+        let a = root.child();
+        let b = root.child();
+        let x = a.intern("x");
+        assert_eq!(b.intern("x"), x);
+        assert_ne!(b.intern("y"), x);
+
+        b.set_local_sym(x, 5);
+        assert_eq!(b.get("x"), Some(5));
+        assert_eq!(a.get_sym(x), None);
+        assert_eq!(b.with_sym(x, |n| n + 1), Some(6));
+
+        // another tree has its own syms
+        let other: PScope<i32> = PScope::new();
+        assert_eq!(other.get("x"), None);
+        assert!(other.p.sym("x").is_none());
+    }
+}
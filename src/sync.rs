@@ -0,0 +1,549 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashSet};
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+#[cfg(feature = "parking_lot")]
+use parking_lot::{Mutex, RwLock};
+
+#[cfg(not(feature = "parking_lot"))]
+use locks::{Mutex, RwLock};
+
+/// The std locks in the shape of parking_lot's, which ignore poisoning, so a
+/// thread that panics while holding one doesn't break the tree for the rest
+#[cfg(not(feature = "parking_lot"))]
+mod locks {
+    use std::sync::{MutexGuard, PoisonError, RwLockReadGuard, RwLockWriteGuard};
+
+    pub struct RwLock<T>(std::sync::RwLock<T>);
+
+    impl<T> RwLock<T> {
+        pub fn new(v: T) -> Self {
+            RwLock(std::sync::RwLock::new(v))
+        }
+
+        pub fn read(&self) -> RwLockReadGuard<'_, T> {
+            self.0.read().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+            self.0.write().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+
+    pub struct Mutex<T>(std::sync::Mutex<T>);
+
+    impl<T> Mutex<T> {
+        pub fn new(v: T) -> Self {
+            Mutex(std::sync::Mutex::new(v))
+        }
+
+        pub fn lock(&self) -> MutexGuard<'_, T> {
+            self.0.lock().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+}
+
+type Map<V> = BTreeMap<Arc<str>, V>;
+
+/// The map behind a root shard. With the `sync-rpds` feature it is persistent,
+/// so the copy a writer makes shares all but the path to the key it changes.
+#[cfg(feature = "sync-rpds")]
+type RootMap<V> = rpds::RedBlackTreeMapSync<Arc<str>, V>;
+#[cfg(not(feature = "sync-rpds"))]
+type RootMap<V> = Map<V>;
+
+#[cfg(feature = "sync-rpds")]
+fn put<V: Clone>(m: &mut RootMap<V>, k: Arc<str>, v: V) {
+    m.insert_mut(k, v);
+}
+
+#[cfg(not(feature = "sync-rpds"))]
+fn put<V>(m: &mut RootMap<V>, k: Arc<str>, v: V) {
+    m.insert(k, v);
+}
+
+/// The number of shards a root made by `new` has
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// Part of the root's bindings, replaced whole on every change. A reader only holds
+/// the lock long enough to copy the pointer, so it never waits on a writer's work.
+struct Shard<T> {
+    map: RwLock<Arc<RootMap<Arc<T>>>>,
+    /// Held by writers, so one change can't be lost to another
+    writer: Mutex<()>,
+    /// Every key in the tree that hashes to this shard, so each name is stored once
+    names: Mutex<HashSet<Arc<str>>>,
+}
+
+/// State shared by every frame in a tree
+struct Globals<T> {
+    /// Each key lives in the shard its hash picks
+    shards: Vec<Shard<T>>,
+    hasher: RandomState,
+}
+
+impl<T> Globals<T> {
+    fn new(shards: usize) -> Self {
+        Globals {
+            shards: (0..shards.max(1))
+                .map(|_| Shard {
+                    map: RwLock::new(Arc::new(RootMap::default())),
+                    writer: Mutex::new(()),
+                    names: Mutex::new(HashSet::new()),
+                })
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    fn shard(&self, id: &str) -> &Shard<T> {
+        let n = self.shards.len();
+        match n {
+            1 => &self.shards[0],
+            _ => &self.shards[(self.hasher.hash_one(id) % n as u64) as usize],
+        }
+    }
+
+    /// Under the lock of id's shard only, so writers of other keys don't wait
+    fn intern(&self, id: String) -> Arc<str> {
+        let mut names = self.shard(&id).names.lock();
+        match names.get(id.as_str()) {
+            Some(k) => k.clone(),
+            None => {
+                let k: Arc<str> = Arc::from(id);
+                names.insert(k.clone());
+                k
+            }
+        }
+    }
+
+    /// The map of the shard holding id
+    fn load(&self, id: &str) -> Arc<RootMap<Arc<T>>> {
+        self.shard(id).map.read().clone()
+    }
+
+    /// Runs f on a copy of the map of the shard holding id, then swaps it in.
+    /// Only the Arcs are copied, not the values, and with the `sync-rpds`
+    /// feature only the part of the map f changes.
+    fn modify<R, F: FnOnce(&mut RootMap<Arc<T>>) -> R>(&self, id: &str, f: F) -> R {
+        let s = self.shard(id);
+        let _w = s.writer.lock();
+        let mut next = (**s.map.read()).clone();
+        let res = f(&mut next);
+        *s.map.write() = Arc::new(next);
+        res
+    }
+}
+
+/// A frame below the root. The link to the parent sits outside the lock,
+/// so a call can walk the chain, holding each frame's lock as it goes.
+struct Scope<T> {
+    data: RwLock<Map<T>>,
+    parent: ASScope<T>,
+}
+
+/// Unlinks the chain one frame at a time, so dropping a very deep chain can't overflow the stack
+impl<T> Drop for Scope<T> {
+    fn drop(&mut self) {
+        let mut next = self.parent.p.take();
+        while let Some(p) = next {
+            next = match Arc::try_unwrap(p) {
+                Ok(mut s) => s.parent.p.take(),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+/// A thread safe equivalent of `PScope`, where each frame below the root sits
+/// behind an `Arc<RwLock>`. The root's bindings are copied on write instead,
+/// so global lookups never wait for a writer. The root is split into shards,
+/// `DEFAULT_SHARDS` of them unless made by `with_shards`, which lets writers to
+/// different keys work at the same time, and makes each copy smaller.
+/// The locks come from parking_lot with the `parking_lot` feature. Otherwise
+/// they are std's, and a lock poisoned by a panic is used as if it were not.
+///
+/// # Lock order
+///
+/// Calls that reach past their own frame, like `set`, `try_replace`, `update`
+/// and `get`, lock the frames of their chain one at a time from the innermost
+/// outward, keeping each lock until the call is done, so a name can't be made
+/// in two frames at once. Nothing ever locks a frame while holding a lock on
+/// one of its parents, and a chain has only one frame at each depth, so every
+/// thread takes locks in order of falling depth and none can wait on another
+/// in a cycle. A root shard is only locked last and one at a time, and `flatten`
+/// lets each frame go before locking the next.
+///
+/// The closure given to `update` runs while its chain is locked up to the frame
+/// holding the value, so it must not use a frame of that chain itself.
+pub struct ASScope<T> {
+    /// None for the root
+    p: Option<Arc<Scope<T>>>,
+    g: Arc<Globals<T>>,
+}
+
+impl<T> Clone for ASScope<T> {
+    fn clone(&self) -> Self {
+        ASScope {
+            p: self.p.clone(),
+            g: self.g.clone(),
+        }
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::sync::ASScope;
+/// let root = ASScope::new();
+/// let a1 = root.child();
+///
+/// let t = std::thread::spawn(move || {
+///     a1.set_global("a".to_string(), 23);
+///     a1.set_local("b".to_string(), 4);
+///     a1.get("b")
+/// });
+/// assert_eq!(t.join().unwrap(), Some(4));
+/// assert_eq!(root.get("a"), Some(23));
+/// assert_eq!(root.get("b"), None);
+/// ```
+///
+impl<T> ASScope<T> {
+    pub fn new() -> Self {
+        Self::with_shards(DEFAULT_SHARDS)
+    }
+
+    /// A new root whose bindings are split over n maps
+    pub fn with_shards(n: usize) -> Self {
+        ASScope {
+            p: None,
+            g: Arc::new(Globals::new(n)),
+        }
+    }
+
+    pub fn set_local(&self, id: String, val: T) {
+        let p = match &self.p {
+            Some(p) => p,
+            None => return self.set_global(id, val),
+        };
+        let mut s = p.data.write();
+        match s.get_mut(id.as_str()) {
+            Some(v) => *v = val,
+            None => drop(s.insert(self.g.intern(id), val)),
+        }
+    }
+
+    pub fn set_global(&self, id: String, val: T) {
+        self.set_global_key(self.g.intern(id), val);
+    }
+
+    fn set_global_key(&self, k: Arc<str>, val: T) {
+        let id = k.clone();
+        self.g.modify(&id, |m| put(m, k, Arc::new(val)));
+    }
+
+    pub fn set(&self, id: String, val: T) {
+        let p = match &self.p {
+            Some(p) => p,
+            None => return self.set_global(id, val),
+        };
+        let mut s = p.data.write();
+        if let Some(v) = s.get_mut(id.as_str()) {
+            *v = val;
+            return;
+        }
+        if let Some(v) = p.parent.try_replace(&id, val) {
+            s.insert(self.g.intern(id), v);
+        }
+    }
+
+    /// Some<T> means not set, use T, to insert in local scope
+    pub fn try_replace(&self, id: &str, val: T) -> Option<T> {
+        let mut held = Vec::new();
+        let mut at = self;
+        while let Some(p) = &at.p {
+            let mut s = p.data.write();
+            if let Some(v) = s.get_mut(id) {
+                *v = val;
+                return None;
+            }
+            held.push(s);
+            at = &p.parent;
+        }
+        self.g.modify(id, |m| match m.get_mut(id) {
+            Some(v) => {
+                *v = Arc::new(val);
+                None
+            }
+            None => Some(val),
+        })
+    }
+
+    pub fn child(&self) -> Self {
+        ASScope {
+            p: Some(Arc::new(Scope {
+                data: RwLock::new(BTreeMap::new()),
+                parent: self.clone(),
+            })),
+            g: self.g.clone(),
+        }
+    }
+}
+
+impl<T> Default for ASScope<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> ASScope<T> {
+    pub fn get(&self, id: &str) -> Option<T> {
+        let mut held = Vec::new();
+        let mut at = self;
+        while let Some(p) = &at.p {
+            let s = p.data.read();
+            if let Some(v) = s.get(id) {
+                return Some(v.clone());
+            }
+            held.push(s);
+            at = &p.parent;
+        }
+        self.g.load(id).get(id).map(|v| T::clone(v))
+    }
+
+    /// A global binding is copied before f changes it,
+    /// so readers of the old value never wait for f
+    pub fn update<F: FnOnce(&mut T) -> A, A>(&self, id: &str, f: F) -> Option<A> {
+        let mut held = Vec::new();
+        let mut at = self;
+        while let Some(p) = &at.p {
+            let mut s = p.data.write();
+            if let Some(v) = s.get_mut(id) {
+                return Some(f(v));
+            }
+            held.push(s);
+            at = &p.parent;
+        }
+        (self.g).modify(id, |m| m.get_mut(id).map(|v| f(Arc::make_mut(v))))
+    }
+}
+
+impl<T: Clone> ASScope<T> {
+    /// Every binding visible from here, with the nearest frame's value for each
+    /// name. Each frame is read in turn, so a change made meanwhile to a frame
+    /// already read is not seen.
+    pub fn flatten(&self) -> BTreeMap<Arc<str>, T> {
+        let mut res = Map::new();
+        let mut at = self;
+        while let Some(p) = &at.p {
+            for (k, v) in p.data.read().iter() {
+                res.entry(k.clone()).or_insert_with(|| v.clone());
+            }
+            at = &p.parent;
+        }
+        for sh in &self.g.shards {
+            for (k, v) in sh.map.read().iter() {
+                res.entry(k.clone()).or_insert_with(|| T::clone(v));
+            }
+        }
+        res
+    }
+}
+
+/// With the `rayon` feature
+///
+/// ```rust
+/// use rayon::prelude::*;
+/// use scope_store::sync::ASScope;
+/// let root = ASScope::with_shards(4);
+/// for n in 0..100 {
+///     root.set_global(format!("v{}", n), n);
+/// }
+/// let c = root.child();
+/// c.set_local("v0".to_string(), 1000);
+/// assert_eq!(c.par_visible().map(|(_, v)| v).sum::<i32>(), 5950);
+/// ```
+#[cfg(feature = "rayon")]
+impl<T: Clone + Send> ASScope<T> {
+    /// The bindings visible from here, copied out by `flatten`
+    /// and then handed out in parallel
+    pub fn par_visible(&self) -> impl rayon::iter::ParallelIterator<Item = (Arc<str>, T)> {
+        rayon::iter::IntoParallelIterator::into_par_iter(self.flatten())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn shared_between_threads() {
+        let root = ASScope::new();
+        root.set_local("n".to_string(), 0);
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let c = root.child();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        c.update("n", |n| *n += 1);
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(root.get("n"), Some(400));
+    }
+
+    #[test]
+    fn keys_are_shared() {
+        let root = ASScope::new();
+        let (a, b) = (root.child(), root.child());
+        a.set_local("x".to_string(), 1);
+        b.set_local("x".to_string(), 2);
+        let key = |s: &ASScope<i32>| {
+            let p = s.p.as_ref().unwrap().data.read();
+            p.keys().next().cloned().unwrap()
+        };
+        assert!(Arc::ptr_eq(&key(&a), &key(&b)));
+        let names: usize = (root.g.shards.iter()).map(|s| s.names.lock().len()).sum();
+        assert_eq!(names, 1);
+    }
+
+    #[test]
+    fn root_reads_dont_wait_for_writers() {
+        let root = ASScope::new();
+        root.set_global("g".to_string(), 1);
+        let c = root.child();
+        c.set("g".to_string(), 2);
+        assert_eq!(c.try_replace("h", 0), Some(0));
+
+        let r = root.clone();
+        // the reader runs while the update still holds the writer lock
+        root.update("g", |n| {
+            let seen = std::thread::scope(|s| s.spawn(|| r.get("g")).join().unwrap());
+            assert_eq!(seen, Some(2));
+            *n += 1;
+        });
+        assert_eq!(c.get("g"), Some(3));
+        // f may give away what it captured
+        let more = vec![1, 2];
+        root.update("g", move |n| *n += more.into_iter().sum::<i32>());
+        assert_eq!(c.get("g"), Some(6));
+    }
+
+    #[test]
+    fn threads_at_every_depth() {
+        let root = ASScope::new();
+        root.set_global("n".to_string(), 0);
+        let mut frames = vec![root.child()];
+        for _ in 0..4 {
+            let c = frames.last().unwrap().child();
+            frames.push(c);
+        }
+        frames[1].set_local("m".to_string(), 0);
+        let handles: Vec<_> = (frames.iter().enumerate())
+            .map(|(d, f)| {
+                let f = f.clone();
+                std::thread::spawn(move || {
+                    for n in 0..200 {
+                        f.update("n", |v| *v += 1);
+                        f.set("m".to_string(), n);
+                        f.set_local(format!("l{}", d), n);
+                        assert_eq!(f.try_replace("none", 0), Some(0));
+                        f.get("l0");
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(root.get("n"), Some(1000));
+        assert_eq!(frames[4].flatten().len(), 7);
+        assert!(!root.flatten().contains_key("m"));
+    }
+
+    #[test]
+    fn a_panic_under_a_lock_breaks_nothing() {
+        let root = ASScope::new();
+        root.set_global("g".to_string(), 0);
+        let c = root.child();
+        c.set_local("l".to_string(), 0);
+        for id in ["g", "l"] {
+            let c2 = c.clone();
+            let t = std::thread::spawn(move || c2.update(id, |_| panic!("in update")));
+            assert!(t.join().is_err());
+        }
+        assert_eq!(c.update("l", |n| *n += 1), Some(()));
+        assert_eq!(c.update("g", |n| *n += 1), Some(()));
+        c.set_local("m".to_string(), 2);
+        assert_eq!(c.flatten().len(), 3);
+    }
+
+    #[test]
+    fn deep_chains_dont_overflow() {
+        let root = ASScope::new();
+        root.set_global("g".to_string(), 0);
+        let mut c = root.child();
+        for _ in 0..100_000 {
+            c = c.child();
+        }
+        assert_eq!(c.get("g"), Some(0));
+        assert_eq!(c.update("g", |n| *n += 1), Some(()));
+        c.set("g".to_string(), 5);
+        assert_eq!(c.try_replace("none", 1), Some(1));
+        assert_eq!(root.get("g"), Some(5));
+        assert_eq!(c.get("none"), None);
+        drop(c);
+    }
+
+    #[test]
+    fn sharded_root() {
+        let root = ASScope::with_shards(4);
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let c = root.child();
+                std::thread::spawn(move || {
+                    for n in 0..50 {
+                        c.set_global(format!("k{}_{}", t, n), n);
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(root.get("k3_49"), Some(49));
+        let sizes: Vec<_> = (root.g.shards.iter())
+            .map(|s| s.map.read().iter().count())
+            .collect();
+        assert_eq!(sizes.iter().sum::<usize>(), 200);
+        assert!(sizes.iter().all(|n| *n < 200));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_visible() {
+        use rayon::prelude::*;
+        let root = ASScope::new();
+        root.set_global("a".to_string(), 1);
+        root.set_global("b".to_string(), 2);
+        let c = root.child();
+        c.set_local("a".to_string(), 10);
+        let c2 = c.child();
+        c2.set_local("c".to_string(), 3);
+
+        let mut seen: Vec<_> = c2.par_visible().map(|(k, v)| (k.to_string(), v)).collect();
+        seen.sort();
+        let want = vec![("a", 10), ("b", 2), ("c", 3)];
+        assert_eq!(
+            seen,
+            want.into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(root.par_visible().count(), 2);
+    }
+}
@@ -0,0 +1,56 @@
+/// Declares a function giving each thread its own root `PScope`, made the first
+/// time that thread calls it, for hosts that would rather not pass the root
+/// around. Every call on a thread returns a handle on the same root.
+///
+/// `thread_root!(T)` declares a private `fn thread_root() -> PScope<T>`.
+/// A visibility, a name and an expression making the root can also be given,
+/// as in `thread_root!(pub fn globals: T = make_root())`.
+///
+/// ```rust
+/// use scope_store::{thread_root, PScope, ScopeOptions, SetMode};
+/// thread_root!(pub fn script_root: i32 = PScope::with_options(ScopeOptions {
+///     set_mode: SetMode::CreateInRoot,
+///     ..Default::default()
+/// }));
+///
+/// script_root().child().set("x".to_string(), 3);
+/// assert_eq!(script_root().get("x"), Some(3));
+/// let other = std::thread::spawn(|| script_root().get("x"));
+/// assert_eq!(other.join().unwrap(), None);
+/// ```
+#[macro_export]
+macro_rules! thread_root {
+    ($vis:vis fn $name:ident : $t:ty) => {
+        $crate::thread_root!($vis fn $name: $t = $crate::PScope::new());
+    };
+    ($vis:vis fn $name:ident : $t:ty = $init:expr) => {
+        $vis fn $name() -> $crate::PScope<$t> {
+            ::std::thread_local! {
+                static ROOT: $crate::PScope<$t> = $init;
+            }
+            ROOT.with(|r| r.clone())
+        }
+    };
+    ($t:ty) => {
+        $crate::thread_root!(fn thread_root: $t);
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::PScope;
+    thread_root!(String);
+    #[test]
+    fn one_root_per_thread() {
+        let r = thread_root();
+        assert!(PScope::ptr_eq(&r, &thread_root()));
+        r.set_local("a".to_string(), String::from("here"));
+        let seen = std::thread::spawn(|| {
+            let r = thread_root();
+            r.set_local("b".to_string(), String::new());
+            r.local_keys()
+        });
+        assert_eq!(seen.join().unwrap(), vec!["b"]);
+        assert_eq!(thread_root().local_keys(), vec!["a"]);
+    }
+}
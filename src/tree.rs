@@ -0,0 +1,514 @@
+use crate::{Meta, NameHasher, PScope, Scope, ScopeOptions, SetMode, Storage, Sym};
+use std::collections::{BTreeMap, BTreeSet};
+use std::rc::Rc;
+
+#[cfg(any(feature = "json", feature = "binary"))]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "serde")]
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct Frame<T> {
+    parent: Option<usize>,
+    /// Only used by root frames, children share their parent's
+    #[cfg_attr(feature = "serde", serde(default))]
+    storage: Storage,
+    /// Only used by root frames
+    #[cfg_attr(feature = "serde", serde(default))]
+    set_mode: SetMode,
+    /// Only used by root frames
+    #[cfg_attr(feature = "serde", serde(default))]
+    max_depth: Option<usize>,
+    /// Only used by root frames
+    #[cfg_attr(feature = "serde", serde(default))]
+    stats: bool,
+    /// Only used by root frames
+    #[cfg_attr(feature = "serde", serde(default))]
+    track_changes: bool,
+    /// Only used by root frames
+    #[cfg_attr(feature = "serde", serde(default))]
+    hasher: NameHasher,
+    data: BTreeMap<String, T>,
+    /// The order keys were set in, only kept for Indexed storage.
+    /// Always written, as binary formats can't tell a field was left out.
+    #[cfg_attr(feature = "serde", serde(default))]
+    order: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    consts: BTreeSet<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    uninit: BTreeSet<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    masked: BTreeSet<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    global: BTreeSet<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    nonlocal: BTreeSet<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    meta: BTreeMap<String, Meta>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    exports: BTreeSet<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    env: BTreeSet<String>,
+    #[cfg_attr(feature = "serde", serde(default = "Vec::new"))]
+    positionals: Vec<Option<T>>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    frozen: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    function: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    name: Option<String>,
+}
+
+/// A flat copy of some scopes and all of their ancestors.
+/// Frames shared between the scopes are stored once, and shared again on restore.
+///
+/// ```rust
+/// use scope_store::{PScope, ScopeTree};
+/// let root = PScope::new();
+/// root.set_local("a".to_string(), 1);
+/// let (b, c) = (root.child(), root.child());
+/// b.set_local("b".to_string(), 2);
+///
+/// let mut v = ScopeTree::capture(&[b, c]).restore();
+/// let (c2, b2) = (v.pop().unwrap(), v.pop().unwrap());
+/// assert_eq!(b2.get("b"), Some(2));
+/// assert_eq!(c2.get("b"), None);
+///
+/// b2.set_global("a".to_string(), 5);
+/// assert_eq!(c2.get("a"), Some(5));
+/// assert_eq!(root.get("a"), Some(1));
+/// ```
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ScopeTree<T> {
+    /// Parents always come before their children
+    frames: Vec<Frame<T>>,
+    scopes: Vec<usize>,
+}
+
+impl<T: Clone> ScopeTree<T> {
+    pub fn capture(scopes: &[PScope<T>]) -> Self {
+        let mut ids: BTreeMap<*const Scope<T>, usize> = BTreeMap::new();
+        let mut frames = Vec::new();
+        let mut res = Vec::new();
+        for s in scopes {
+            let mut chain = Vec::new();
+            let mut cur = Some(s);
+            let mut parent = None;
+            while let Some(c) = cur {
+                if let Some(n) = ids.get(&Rc::as_ptr(&c.p)) {
+                    parent = Some(*n);
+                    break;
+                }
+                chain.push(c);
+                cur = c.p.parent.as_ref();
+            }
+            for c in chain.into_iter().rev() {
+                let data = c.p.data();
+                let attrs = c.p.attrs.borrow();
+                let t = c.p.tree.as_deref();
+                frames.push(Frame {
+                    parent,
+                    storage: data.kind(),
+                    set_mode: t.map(|t| t.set_mode).unwrap_or_default(),
+                    max_depth: t.and_then(|t| t.max_depth),
+                    stats: t.is_some_and(|t| t.stats.is_some()),
+                    track_changes: t.is_some_and(|t| t.changes.is_some()),
+                    hasher: t.map(|t| t.names.borrow().hasher()).unwrap_or_default(),
+                    data: (data.iter())
+                        .map(|(k, v)| (c.p.name(*k).to_string(), v.clone()))
+                        .collect(),
+                    order: match data.keeps_order() {
+                        true => (data.iter())
+                            .map(|(k, _)| c.p.name(*k).to_string())
+                            .collect(),
+                        false => Vec::new(),
+                    },
+                    consts: c.p.names_of(&attrs.consts),
+                    uninit: c.p.names_of(&attrs.uninit),
+                    masked: c.p.names_of(&attrs.masked),
+                    global: c.p.names_of(&attrs.global),
+                    nonlocal: c.p.names_of(&attrs.nonlocal),
+                    exports: c.p.names_of(&attrs.exports),
+                    env: c.p.names_of(&attrs.env),
+                    positionals: (c.p.extras())
+                        .map(|x| x.positionals.borrow().clone())
+                        .unwrap_or_default(),
+                    meta: (attrs.meta.iter())
+                        .map(|(k, m)| (c.p.name(*k).to_string(), m.clone()))
+                        .collect(),
+                    frozen: c.is_frozen(),
+                    function: c.is_function(),
+                    name: c.p.extras().and_then(|x| x.name.clone()),
+                });
+                let n = frames.len() - 1;
+                ids.insert(Rc::as_ptr(&c.p), n);
+                parent = Some(n);
+            }
+            res.push(parent.expect("a scope always has at least one frame"));
+        }
+        ScopeTree {
+            frames,
+            scopes: res,
+        }
+    }
+}
+
+impl<T> Scope<T> {
+    fn names_of(&self, syms: &BTreeSet<Sym>) -> BTreeSet<String> {
+        syms.iter().map(|k| self.name(*k).to_string()).collect()
+    }
+}
+
+impl<T> ScopeTree<T> {
+    /// Rebuilds the captured scopes in the order they were given to capture
+    pub fn restore(self) -> Vec<PScope<T>> {
+        let mut built: Vec<PScope<T>> = Vec::with_capacity(self.frames.len());
+        for f in self.frames {
+            let mut sc = match f.parent {
+                Some(p) => built[p].child_frame(),
+                None => Scope::new(ScopeOptions {
+                    storage: f.storage,
+                    set_mode: f.set_mode,
+                    max_depth: f.max_depth,
+                    stats: f.stats,
+                    track_changes: f.track_changes,
+                    hasher: f.hasher,
+                }),
+            };
+            sc.boundary = f.function;
+            if f.name.is_some() {
+                sc.extras_mut().name = f.name;
+            }
+            let s = PScope { p: Rc::new(sc) };
+            {
+                let mut data = s.p.data_mut();
+                let mut vals = f.data;
+                for k in f.order {
+                    if let Some(v) = vals.remove(&k) {
+                        data.insert(s.p.intern(&k), v);
+                    }
+                }
+                for (k, v) in vals {
+                    data.insert(s.p.intern(&k), v);
+                }
+            }
+            {
+                let mut attrs = s.p.attrs.borrow_mut();
+                attrs.consts = f.consts.iter().map(|k| s.p.intern(k)).collect();
+                attrs.uninit = f.uninit.iter().map(|k| s.p.intern(k)).collect();
+                attrs.masked = f.masked.iter().map(|k| s.p.intern(k)).collect();
+                attrs.global = f.global.iter().map(|k| s.p.intern(k)).collect();
+                attrs.nonlocal = f.nonlocal.iter().map(|k| s.p.intern(k)).collect();
+                attrs.exports = f.exports.iter().map(|k| s.p.intern(k)).collect();
+                attrs.env = f.env.iter().map(|k| s.p.intern(k)).collect();
+                attrs.meta = (f.meta.into_iter())
+                    .map(|(k, m)| (s.p.intern(&k), m))
+                    .collect();
+            }
+            if !f.positionals.is_empty() {
+                *s.p.extras_init().positionals.borrow_mut() = f.positionals;
+            }
+            s.p.frozen.set(f.frozen);
+            built.push(s);
+        }
+        self.scopes.iter().map(|n| built[*n].clone()).collect()
+    }
+}
+
+/// A scope and its ancestors copied out of their tree, see `PScope::to_owned_tree`.
+/// It shares nothing with the tree it came from, so it is Send whenever T is.
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// root.set_local("a".to_string(), 1);
+/// let c = root.child();
+/// c.set_local("b".to_string(), 2);
+///
+/// let owned = c.to_owned_tree();
+/// let t = std::thread::spawn(move || {
+///     let c = owned.into_scope();
+///     c.set_global("a".to_string(), 10);
+///     (c.get("a"), c.get("b"))
+/// });
+/// assert_eq!(t.join().unwrap(), (Some(10), Some(2)));
+/// assert_eq!(root.get("a"), Some(1));
+/// ```
+pub struct OwnedScopeTree<T>(ScopeTree<T>);
+
+impl<T> OwnedScopeTree<T> {
+    /// Rebuilds the scope, in a new tree on the current thread
+    pub fn into_scope(self) -> PScope<T> {
+        self.0.restore().remove(0)
+    }
+}
+
+impl<T: Clone> PScope<T> {
+    /// Copies this frame and every frame above it. As with `ScopeTree`,
+    /// subscribers and imports are left behind.
+    pub fn to_owned_tree(&self) -> OwnedScopeTree<T> {
+        OwnedScopeTree(ScopeTree::capture(std::slice::from_ref(self)))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T> ScopeTree<T> {
+    fn validate(&self) -> Result<(), String> {
+        // (depth, root) for each frame
+        let mut depths: Vec<(usize, usize)> = Vec::with_capacity(self.frames.len());
+        for (n, f) in self.frames.iter().enumerate() {
+            let d = match f.parent {
+                Some(p) if p >= n => return Err(format!("frame {} has later parent {}", n, p)),
+                Some(p) => (depths[p].0 + 1, depths[p].1),
+                None => (0, n),
+            };
+            if let Some(m) = self.frames[d.1].max_depth {
+                if d.0 > m {
+                    return Err(format!("frame {} is deeper than the limit {}", n, m));
+                }
+            }
+            depths.push(d);
+        }
+        for s in &self.scopes {
+            if *s >= self.frames.len() {
+                return Err(format!("scope refers to missing frame {}", s));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct RawTree<T> {
+    frames: Vec<Frame<T>>,
+    scopes: Vec<usize>,
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for ScopeTree<T> {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let raw = RawTree::deserialize(d)?;
+        let res = ScopeTree {
+            frames: raw.frames,
+            scopes: raw.scopes,
+        };
+        res.validate().map_err(D::Error::custom)?;
+        Ok(res)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: Serialize + Clone> Serialize for PScope<T> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        ScopeTree::capture(std::slice::from_ref(self)).serialize(s)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for PScope<T> {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let tree = ScopeTree::deserialize(d)?;
+        if tree.scopes.len() != 1 {
+            return Err(D::Error::custom(
+                "expected a tree holding exactly one scope",
+            ));
+        }
+        Ok(tree.restore().remove(0))
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::{PScope, ScopeTree};
+/// let root = PScope::new();
+/// root.set_local("session".to_string(), 1);
+/// let (a, b) = (root.child(), root.child());
+/// a.set_local("a".to_string(), 2);
+///
+/// let saved = ScopeTree::capture(&[a, b]).to_json_string().unwrap();
+/// let v = ScopeTree::<i32>::from_json_str(&saved).unwrap().restore();
+/// v[1].set_global("session".to_string(), 3);
+/// assert_eq!(v[0].get("session"), Some(3));
+/// assert_eq!(v[0].get("a"), Some(2));
+/// ```
+#[cfg(feature = "json")]
+impl<T: Serialize> ScopeTree<T> {
+    pub fn to_json_string(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+}
+
+#[cfg(feature = "json")]
+impl<T: DeserializeOwned> ScopeTree<T> {
+    /// Fails on bad JSON, and on frames that don't make a tree
+    pub fn from_json_str(s: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(s)
+    }
+}
+
+#[cfg(feature = "json")]
+impl<T: Serialize + Clone> PScope<T> {
+    /// This frame and every frame above it as JSON, in the form of a
+    /// `ScopeTree`. To keep several scopes that share frames, save a
+    /// `ScopeTree` of them instead.
+    pub fn to_json_string(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+}
+
+#[cfg(feature = "json")]
+impl<T: DeserializeOwned> PScope<T> {
+    /// Rebuilds a chain saved by to_json_string, giving back its innermost frame
+    pub fn from_json_str(s: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(s)
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// root.set_local("hp".to_string(), 30u32);
+/// let level = root.child();
+/// level.set_local("x".to_string(), 4);
+///
+/// let save = level.to_bytes().unwrap();
+/// let back = PScope::<u32>::from_bytes(&save).unwrap();
+/// assert_eq!(back.flatten(), level.flatten());
+/// assert_eq!(back.depth(), 1);
+/// ```
+#[cfg(feature = "binary")]
+impl<T: Serialize> ScopeTree<T> {
+    /// The tree in bincode's compact binary form
+    pub fn to_bytes(&self) -> bincode::Result<Vec<u8>> {
+        bincode::serialize(self)
+    }
+}
+
+#[cfg(feature = "binary")]
+impl<T: DeserializeOwned> ScopeTree<T> {
+    /// Fails on bytes not made by to_bytes for the same T, and on frames that don't make a tree
+    pub fn from_bytes(b: &[u8]) -> bincode::Result<Self> {
+        bincode::deserialize(b)
+    }
+}
+
+#[cfg(feature = "binary")]
+impl<T: Serialize + Clone> PScope<T> {
+    /// This frame and every frame above it in bincode's compact binary form,
+    /// far smaller and faster to make and read than JSON
+    pub fn to_bytes(&self) -> bincode::Result<Vec<u8>> {
+        bincode::serialize(self)
+    }
+}
+
+#[cfg(feature = "binary")]
+impl<T: DeserializeOwned> PScope<T> {
+    /// Rebuilds a chain saved by to_bytes, giving back its innermost frame
+    pub fn from_bytes(b: &[u8]) -> bincode::Result<Self> {
+        bincode::deserialize(b)
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    #[test]
+    fn json_round_trip() {
+        let root = PScope::new();
+        root.set_local("a".to_string(), 1);
+        let b = root.child();
+        b.set_local("b".to_string(), 2);
+        let c = b.child();
+        let d = root.child();
+        root.set_const("k".to_string(), 0).unwrap();
+
+        let s = serde_json::to_string(&ScopeTree::capture(&[c, d])).unwrap();
+        let tree: ScopeTree<i32> = serde_json::from_str(&s).unwrap();
+        let v = tree.restore();
+        assert_eq!(v[0].get("b"), Some(2));
+        assert_eq!(v[1].get("b"), None);
+        v[0].set_global("a".to_string(), 3);
+        assert_eq!(v[1].get("a"), Some(3));
+        assert!(v[1].is_const("k"));
+
+        let f = root.child_named("fn f").child_function();
+        let f2: PScope<i32> = serde_json::from_str(&serde_json::to_string(&f).unwrap()).unwrap();
+        assert!(f2.is_function());
+        assert_eq!(f2.trace(), f.trace());
+
+        b.freeze();
+        let s = serde_json::to_string(&b).unwrap();
+        let b2: PScope<i32> = serde_json::from_str(&s).unwrap();
+        assert_eq!(b2.get("a"), Some(1));
+        assert_eq!(b2.get("b"), Some(2));
+        assert!(b2.is_frozen());
+
+        let bad = r#"{"frames":[{"parent":0,"data":{}}],"scopes":[0]}"#;
+        assert!(serde_json::from_str::<ScopeTree<i32>>(bad).is_err());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn sessions_save_as_json() {
+        let root = PScope::new();
+        root.set_local("x".to_string(), vec![1, 2]);
+        let f = root.child_named("main");
+        f.set_local("y".to_string(), vec![]);
+        f.mask("x");
+
+        let f2: PScope<Vec<i32>> = PScope::from_json_str(&f.to_json_string().unwrap()).unwrap();
+        assert_eq!(f2.trace(), vec!["<root>", "main"]);
+        assert_eq!(f2.get("x"), None);
+        assert_eq!(f2.parent().unwrap().get("x"), Some(vec![1, 2]));
+        assert_eq!(f2.flatten(), f.flatten());
+
+        let two = ScopeTree::capture(&[f.clone(), f2])
+            .to_json_string()
+            .unwrap();
+        assert!(PScope::<Vec<i32>>::from_json_str(&two).is_err());
+        assert!(PScope::<Vec<i32>>::from_json_str("{").is_err());
+        assert_eq!(
+            ScopeTree::<Vec<i32>>::from_json_str(&two)
+                .unwrap()
+                .restore()
+                .len(),
+            2
+        );
+    }
+
+    #[cfg(feature = "binary")]
+    #[test]
+    fn savestates_round_trip_as_bytes() {
+        let root = PScope::with_storage(crate::Storage::Hash);
+        for n in 0..50 {
+            root.set_local(format!("v{}", n), n);
+        }
+        let (a, b) = (root.child_function(), root.child());
+        a.set_local("pos".to_string(), -1);
+        b.declare_uninit("later").unwrap();
+
+        let tree = ScopeTree::capture(&[a.clone(), b]);
+        let bytes = tree.to_bytes().unwrap();
+        assert!(bytes.len() < serde_json::to_string(&tree).unwrap().len());
+        let v = ScopeTree::<i32>::from_bytes(&bytes).unwrap().restore();
+        assert!(v[0].is_function());
+        assert_eq!(v[0].flatten(), a.flatten());
+        v[1].set_global("v0".to_string(), 100);
+        assert_eq!(v[0].get("v0"), Some(100));
+        assert_eq!(
+            v[1].get_checked("later"),
+            Err(crate::ScopeError::Uninitialized("later".to_string()))
+        );
+
+        assert!(ScopeTree::<i32>::from_bytes(&bytes[..bytes.len() / 2]).is_err());
+        assert!(PScope::<i32>::from_bytes(&bytes).is_err());
+        assert_eq!(
+            PScope::<i32>::from_bytes(&a.to_bytes().unwrap())
+                .unwrap()
+                .get("pos"),
+            Some(-1)
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use crate::hooks::Refused;
+use crate::{PScope, ScopeError};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+/// Buffers changes to a scope chain until `commit` is called.
+/// Dropping the transaction without committing discards them.
+/// The changes land where the plain setters would put them, with
+/// global and nonlocal marks as they are when each change is made.
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// root.set_local("a".to_string(), 1);
+/// let c = root.child();
+///
+/// let mut t = c.begin();
+/// t.update("a", |n| *n += 1);
+/// t.set_local("b".to_string(), 5);
+/// assert_eq!(t.get("a"), Some(2));
+/// assert_eq!(c.get("a"), Some(1));
+/// t.commit().unwrap();
+/// assert_eq!(root.get("a"), Some(2));
+/// assert_eq!(c.get("b"), Some(5));
+/// ```
+pub struct ScopeTxn<T> {
+    scope: PScope<T>,
+    /// Pending writes, per frame
+    changes: Vec<(PScope<T>, BTreeMap<String, T>)>,
+}
+
+impl<T: Clone> PScope<T> {
+    pub fn begin(&self) -> ScopeTxn<T> {
+        ScopeTxn {
+            scope: self.clone(),
+            changes: Vec::new(),
+        }
+    }
+}
+
+impl<T: Clone> ScopeTxn<T> {
+    fn pending(&mut self, frame: &PScope<T>) -> &mut BTreeMap<String, T> {
+        let pos = match self
+            .changes
+            .iter()
+            .position(|(f, _)| Rc::ptr_eq(&f.p, &frame.p))
+        {
+            Some(n) => n,
+            None => {
+                self.changes.push((frame.clone(), BTreeMap::new()));
+                self.changes.len() - 1
+            }
+        };
+        &mut self.changes[pos].1
+    }
+
+    fn pending_get(&self, frame: &PScope<T>, id: &str) -> Option<&T> {
+        self.changes
+            .iter()
+            .find(|(f, _)| Rc::ptr_eq(&f.p, &frame.p))
+            .and_then(|(_, m)| m.get(id))
+    }
+
+    /// The frame from up that would define id if the transaction were committed
+    fn owner(&self, up: &PScope<T>, id: &str) -> Option<PScope<T>> {
+        let mut cur = Some(up);
+        while let Some(c) = cur {
+            if self.pending_get(c, id).is_some() || c.p.defines_local(id) {
+                return Some(c.clone());
+            }
+            cur = c.p.parent.as_ref();
+        }
+        None
+    }
+
+    /// The frame set would write id to, as for `PScope::set`
+    fn target(&self, id: &str) -> Option<PScope<T>> {
+        let mut at = self.scope.clone();
+        if let Some(s) = self.scope.p.sym(id) {
+            loop {
+                let (global, nonlocal) = {
+                    let a = at.p.attrs.borrow();
+                    (a.global.contains(&s), a.nonlocal.contains(&s))
+                };
+                if global {
+                    return Some(at.root());
+                }
+                match (nonlocal, at.p.parent.clone()) {
+                    (true, Some(p)) => at = p,
+                    _ => break,
+                }
+            }
+        }
+        match self.owner(&at, id) {
+            // a frozen owner is shadowed, as set does
+            Some(f) if !f.is_frozen() => Some(f),
+            _ => at.p.create_target().map(|t| at.handle(t)),
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<T> {
+        let f = self.owner(&self.scope, id)?;
+        match self.pending_get(&f, id) {
+            Some(v) => Some(v.clone()),
+            None => f.p.get(f.p.sym(id)?),
+        }
+    }
+
+    pub fn set_local(&mut self, id: String, val: T) {
+        let s = self.scope.clone();
+        self.pending(&s).insert(id, val);
+    }
+
+    pub fn set_global(&mut self, id: String, val: T) {
+        let r = match &self.scope.p.root {
+            Some(r) => r.clone(),
+            None => self.scope.clone(),
+        };
+        self.pending(&r).insert(id, val);
+    }
+
+    pub fn set(&mut self, id: String, val: T) {
+        if let Some(f) = self.target(&id) {
+            self.pending(&f).insert(id, val);
+        }
+    }
+
+    pub fn update<F: FnOnce(&mut T) -> A, A>(&mut self, id: &str, f: F) -> Option<A> {
+        let mut v = self.get(id)?;
+        let res = f(&mut v);
+        let owner = self.owner(&self.scope, id)?;
+        self.pending(&owner).insert(id.to_string(), v);
+        Some(res)
+    }
+
+    /// Writes every pending change into its frame, or none of them. Every change
+    /// is first passed through the `on_set` hooks and the validators seen from
+    /// the frame the transaction began on, and it fails, changing nothing,
+    /// if one of them refuses a value or a frame to be written is frozen.
+    pub fn commit(self) -> Result<(), ScopeError> {
+        let by = &self.scope.p;
+        let mut checked = Vec::new();
+        for (f, m) in self.changes {
+            for (k, mut v) in m {
+                if f.is_frozen() {
+                    return Err(ScopeError::Frozen(k));
+                }
+                let s = f.p.intern(&k);
+                match by.check_set(s, &mut v) {
+                    Ok(()) => checked.push((f.clone(), s, v)),
+                    Err(Refused::Hook) => return Err(ScopeError::Refused(k)),
+                    Err(Refused::Invalid(why)) => return Err(ScopeError::Invalid(k, why)),
+                }
+            }
+        }
+        for (f, s, v) in checked {
+            f.p.store_local(s, v);
+        }
+        Ok(())
+    }
+
+    /// Discards every pending change
+    pub fn abort(self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn abort_discards() {
+        let root = PScope::new();
+        root.set_local("a".to_string(), 1);
+        let c = root.child();
+
+        let mut t = c.begin();
+        t.set("a".to_string(), 2);
+        t.set("b".to_string(), 3);
+        t.set_global("g".to_string(), 4);
+        assert_eq!(t.get("a"), Some(2));
+        assert_eq!(t.get("b"), Some(3));
+        assert_eq!(t.get("g"), Some(4));
+        assert_eq!(t.update("a", |n| *n * 10), Some(20));
+        t.abort();
+        assert_eq!(c.get("a"), Some(1));
+        assert_eq!(c.get("b"), None);
+        assert_eq!(root.get("g"), None);
+
+        let mut t = c.begin();
+        t.set("a".to_string(), 2);
+        t.set("b".to_string(), 3);
+        t.commit().unwrap();
+        assert_eq!(root.get("a"), Some(2));
+        assert_eq!(root.get("b"), None);
+        assert_eq!(c.get("b"), Some(3));
+    }
+
+    #[test]
+    fn commits_are_all_or_nothing() {
+        let root = PScope::new();
+        root.set_local("a".to_string(), 1);
+        root.set_validator("v", |n: &i32| match *n < 10 {
+            true => Ok(()),
+            false => Err("too big".to_string()),
+        });
+        let c = root.child();
+
+        let mut t = c.begin();
+        t.set("a".to_string(), 2);
+        t.set_local("b".to_string(), 3);
+        t.set("v".to_string(), 10);
+        let e = ScopeError::Invalid("v".to_string(), "too big".to_string());
+        assert_eq!(t.commit(), Err(e));
+        assert_eq!(root.get("a"), Some(1));
+        assert_eq!(c.get("b"), None);
+
+        root.on_set(|k, _| match k {
+            "no" => std::ops::ControlFlow::Break(()),
+            _ => std::ops::ControlFlow::Continue(()),
+        });
+        let mut t = c.begin();
+        t.set_local("b".to_string(), 3);
+        t.set_global("no".to_string(), 0);
+        assert_eq!(t.commit(), Err(ScopeError::Refused("no".to_string())));
+        assert_eq!(c.get("b"), None);
+
+        let mut t = c.begin();
+        t.set("a".to_string(), 2);
+        root.freeze();
+        assert_eq!(t.commit(), Err(ScopeError::Frozen("a".to_string())));
+    }
+
+    #[test]
+    fn set_follows_marks_and_skips_frozen_owners() {
+        let root = PScope::new();
+        root.set_local("g".to_string(), 0);
+        let a = root.child();
+        a.set_local("f".to_string(), 0);
+        a.freeze();
+        let c = a.child();
+        c.set_local("n".to_string(), 0);
+        let d = c.child();
+        d.set_local("g".to_string(), 1);
+        d.set_local("n".to_string(), 1);
+        d.mark_global("g");
+        d.mark_nonlocal("n");
+
+        let mut t = d.begin();
+        t.set("g".to_string(), 5);
+        t.set("n".to_string(), 6);
+        t.set("f".to_string(), 7);
+        assert_eq!(t.get("n"), Some(1));
+        t.commit().unwrap();
+        assert_eq!((root.get("g"), d.get("g")), (Some(5), Some(1)));
+        assert_eq!((c.get("n"), d.get("n")), (Some(6), Some(1)));
+        assert_eq!((a.get("f"), d.get("f")), (Some(0), Some(7)));
+        assert!(d.contains_local("f"));
+    }
+}
@@ -0,0 +1,239 @@
+use crate::store::Store;
+use crate::{PScope, Scope, Sym};
+use std::collections::VecDeque;
+
+/// What a frame held before a change, which putting back undoes it
+enum Was<T> {
+    /// The value of a key, None if it was not set there
+    Value(Sym, Option<T>),
+    /// Every value in the frame, before `reset_local` emptied it
+    Data(Store<T>),
+}
+
+struct Op<T> {
+    /// By address, and dropped with the frame, so never stale
+    frame: *const Scope<T>,
+    was: Was<T>,
+}
+
+/// The changes that `undo` and `redo` can step through, latest last
+pub(crate) struct Undo<T> {
+    cap: usize,
+    copy: fn(&T) -> T,
+    copy_data: fn(&Store<T>) -> Store<T>,
+    undo: VecDeque<Op<T>>,
+    redo: VecDeque<Op<T>>,
+    /// Set while an op is being put back, so it is not recorded as a new change
+    replaying: bool,
+}
+
+impl<T> Undo<T> {
+    fn push(stack: &mut VecDeque<Op<T>>, cap: usize, op: Op<T>) {
+        if stack.len() == cap {
+            stack.pop_front();
+        }
+        stack.push_back(op);
+    }
+}
+
+impl<T> Scope<T> {
+    fn undo_op(&self, was: Was<T>) {
+        let mut u = self.hooks().undo.borrow_mut();
+        if let Some(u) = u.as_mut().filter(|u| !u.replaying && u.cap > 0) {
+            u.redo.clear();
+            let frame = self as *const _;
+            Undo::push(&mut u.undo, u.cap, Op { frame, was });
+        }
+    }
+
+    /// Records that k held old in this frame, before the change being made
+    pub(crate) fn undo_value(&self, k: Sym, old: Option<&T>) {
+        let copy = match self.hooks().undo.borrow().as_ref() {
+            Some(u) if !u.replaying => u.copy,
+            _ => return,
+        };
+        self.undo_op(Was::Value(k, old.map(copy)));
+    }
+
+    /// Records everything in this frame, before it is emptied
+    pub(crate) fn undo_cleared(&self) {
+        let copy = match self.hooks().undo.borrow().as_ref() {
+            Some(u) if !u.replaying => u.copy_data,
+            _ => return,
+        };
+        let data = copy(&self.data());
+        self.undo_op(Was::Data(data));
+    }
+
+    /// Drops the ops on a frame that is going away
+    pub(crate) fn undo_dropped(&self) {
+        if self.root.is_none() {
+            return;
+        }
+        if let Some(u) = self.hooks().undo.borrow_mut().as_mut() {
+            let me = self as *const _;
+            u.undo.retain(|op| op.frame != me);
+            u.redo.retain(|op| op.frame != me);
+        }
+    }
+
+    /// Puts back what the latest op on one stack saved, if it was on this chain,
+    /// and saves what it replaced on the other
+    fn step(&self, back: bool) -> bool {
+        let mut guard = self.hooks().undo.borrow_mut();
+        let u = match guard.as_mut() {
+            Some(u) => u,
+            None => return false,
+        };
+        let (from, to) = match back {
+            true => (&mut u.undo, &mut u.redo),
+            false => (&mut u.redo, &mut u.undo),
+        };
+        let on = |op: &Op<T>| self.frames().find(|f| std::ptr::eq(*f, op.frame));
+        let f = match from.back().and_then(on) {
+            Some(f) if !f.frozen.get() => f,
+            _ => return false,
+        };
+        let op = from.pop_back().expect("checked above");
+        let now = match &op.was {
+            Was::Value(k, _) => Was::Value(*k, f.data().get(*k).map(u.copy)),
+            Was::Data(_) => Was::Data((u.copy_data)(&f.data())),
+        };
+        Undo::push(
+            to,
+            u.cap,
+            Op {
+                frame: op.frame,
+                was: now,
+            },
+        );
+        u.replaying = true;
+        drop(guard);
+
+        match op.was {
+            Was::Value(k, Some(v)) => f.store_local(k, v),
+            Was::Value(k, None) => drop(f.remove_local(k)),
+            Was::Data(d) => {
+                let old = std::mem::replace(&mut *f.data_mut(), d);
+                f.touch();
+                drop(old);
+            }
+        }
+        if let Some(u) = self.hooks().undo.borrow_mut().as_mut() {
+            u.replaying = false;
+        }
+        true
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// root.enable_undo(100);
+/// root.set_local("x".to_string(), 1);
+/// root.set("x".to_string(), 2);
+/// root.set_local("f".to_string(), 10);
+///
+/// assert!(root.undo());
+/// assert!(!root.contains("f"));
+/// assert!(root.undo());
+/// assert_eq!(root.get("x"), Some(1));
+/// assert!(root.redo());
+/// assert_eq!(root.get("x"), Some(2));
+/// ```
+impl<T: Clone> PScope<T> {
+    /// Starts recording what each change anywhere in the tree replaced, keeping
+    /// the last capacity of them to undo, and as many to redo. If already on,
+    /// only the capacity changes. Restoring a snapshot is not recorded,
+    /// and forks start without undo.
+    pub fn enable_undo(&self, capacity: usize) {
+        let mut u = self.p.hooks().undo.borrow_mut();
+        let u = u.get_or_insert_with(|| Undo {
+            cap: capacity,
+            copy: T::clone,
+            copy_data: Store::clone,
+            undo: VecDeque::new(),
+            redo: VecDeque::new(),
+            replaying: false,
+        });
+        u.cap = capacity;
+        for s in [&mut u.undo, &mut u.redo] {
+            while s.len() > capacity {
+                s.pop_front();
+            }
+        }
+    }
+}
+
+impl<T> PScope<T> {
+    /// Undoes the latest change in the tree not yet undone, putting back the
+    /// value it replaced, or removing what it made. Only the values come back
+    /// after `reset_local`. Changes to frames since dropped are forgotten.
+    /// False if there is nothing to undo, or the change was not to this frame
+    /// or one of its parents, or its frame is frozen.
+    pub fn undo(&self) -> bool {
+        self.p.step(true)
+    }
+
+    /// Makes the latest undone change again. Any new change clears what can be redone.
+    /// False for the same reasons as `undo`.
+    pub fn redo(&self) -> bool {
+        self.p.step(false)
+    }
+
+    /// Drops every recorded change, and stops recording
+    pub fn disable_undo(&self) {
+        *self.p.hooks().undo.borrow_mut() = None;
+    }
+
+    /// The number of changes that can be undone
+    pub fn undo_len(&self) -> usize {
+        (self.p.hooks().undo.borrow().as_ref()).map_or(0, |u| u.undo.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn undo_walks_back_through_changes() {
+        let root = PScope::new();
+        root.set_local("a".to_string(), 0);
+        root.enable_undo(3);
+        let c = root.child();
+        c.set_local("b".to_string(), 1);
+        c.update("a", |n| *n += 5);
+        root.remove("a");
+        c.set_local("b".to_string(), 2);
+        assert_eq!(root.undo_len(), 3);
+
+        // the latest change is to c, which root can't reach
+        assert!(!root.undo());
+        assert!(c.undo());
+        assert_eq!(c.get("b"), Some(1));
+        assert!(c.undo());
+        assert_eq!(root.get("a"), Some(5));
+        assert!(c.undo());
+        assert_eq!(root.get("a"), Some(0));
+        // the first set of b was pushed out by the capacity
+        assert!(!c.undo());
+
+        assert!(c.redo());
+        assert_eq!(root.get("a"), Some(5));
+        c.set_local("z".to_string(), 9);
+        assert!(!c.redo());
+
+        c.reset_local();
+        assert!(c.local_keys().is_empty());
+        assert!(c.undo());
+        assert_eq!(c.get("z"), Some(9));
+
+        drop(c);
+        assert_eq!(root.undo_len(), 1);
+        assert!(root.undo());
+        assert_eq!(root.get("a"), Some(0));
+        root.disable_undo();
+        assert!(!root.undo());
+    }
+}
@@ -0,0 +1,211 @@
+//! A ready made dynamic value, for interpreters that don't need their own.
+
+use crate::PScope;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Value {
+    #[default]
+    Unit,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    /// The name of the variant, for error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Unit => "unit",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Str(_) => "str",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Unit => write!(f, "()"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::List(l) => {
+                write!(f, "[")?;
+                for (i, v) in l.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, "]")
+            }
+            Value::Map(m) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in m.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", k, v)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// A Value was not the variant asked for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WrongType {
+    pub expected: &'static str,
+    pub found: &'static str,
+}
+
+impl fmt::Display for WrongType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "expected {}, found {}", self.expected, self.found)
+    }
+}
+
+impl std::error::Error for WrongType {}
+
+macro_rules! convert {
+    ($t:ty, $var:ident, $name:expr) => {
+        impl From<$t> for Value {
+            fn from(v: $t) -> Self {
+                Value::$var(v)
+            }
+        }
+
+        impl TryFrom<Value> for $t {
+            type Error = WrongType;
+            fn try_from(v: Value) -> Result<Self, WrongType> {
+                match v {
+                    Value::$var(x) => Ok(x),
+                    other => Err(WrongType {
+                        expected: $name,
+                        found: other.type_name(),
+                    }),
+                }
+            }
+        }
+    };
+}
+
+convert!(bool, Bool, "bool");
+convert!(i64, Int, "int");
+convert!(f64, Float, "float");
+convert!(String, Str, "str");
+convert!(Vec<Value>, List, "list");
+convert!(BTreeMap<String, Value>, Map, "map");
+
+impl From<()> for Value {
+    fn from(_: ()) -> Self {
+        Value::Unit
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.to_string())
+    }
+}
+
+/// Typed reads, each None if id is unset or holds another variant
+///
+/// ```rust
+/// use scope_store::value::Value;
+/// use scope_store::PScope;
+/// let root: PScope<Value> = PScope::new();
+/// root.set_local("n".to_string(), 3.into());
+/// root.set_local("s".to_string(), "hi".into());
+///
+/// assert_eq!(root.get_int("n"), Some(3));
+/// assert_eq!(root.get_str("s"), Some("hi".to_string()));
+/// assert_eq!(root.get_str("n"), None);
+/// ```
+impl PScope<Value> {
+    pub fn get_bool(&self, id: &str) -> Option<bool> {
+        self.with(id, |v| match v {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        })?
+    }
+
+    pub fn get_int(&self, id: &str) -> Option<i64> {
+        self.with(id, |v| match v {
+            Value::Int(n) => Some(*n),
+            _ => None,
+        })?
+    }
+
+    /// Also accepts an Int
+    pub fn get_float(&self, id: &str) -> Option<f64> {
+        self.with(id, |v| match v {
+            Value::Float(n) => Some(*n),
+            Value::Int(n) => Some(*n as f64),
+            _ => None,
+        })?
+    }
+
+    pub fn get_str(&self, id: &str) -> Option<String> {
+        self.with(id, |v| match v {
+            Value::Str(s) => Some(s.clone()),
+            _ => None,
+        })?
+    }
+
+    pub fn get_list(&self, id: &str) -> Option<Vec<Value>> {
+        self.with(id, |v| match v {
+            Value::List(l) => Some(l.clone()),
+            _ => None,
+        })?
+    }
+
+    pub fn get_map(&self, id: &str) -> Option<BTreeMap<String, Value>> {
+        self.with(id, |v| match v {
+            Value::Map(m) => Some(m.clone()),
+            _ => None,
+        })?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn conversions() {
+        assert_eq!(i64::try_from(Value::from(2i64)), Ok(2));
+        assert_eq!(
+            bool::try_from(Value::Int(1)),
+            Err(WrongType {
+                expected: "bool",
+                found: "int"
+            })
+        );
+        let l = Value::List(vec![1i64.into(), "a".into(), ().into()]);
+        assert_eq!(l.to_string(), "[1, a, ()]");
+
+        let root: PScope<Value> = PScope::new();
+        root.set_local("f".to_string(), 2i64.into());
+        root.set_local("l".to_string(), l);
+        assert_eq!(root.get_float("f"), Some(2.0));
+        assert_eq!(root.get_list("l").map(|l| l.len()), Some(3));
+        assert_eq!(root.get_bool("f"), None);
+        assert_eq!(root.get_map("missing"), None);
+    }
+}
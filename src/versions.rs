@@ -0,0 +1,121 @@
+use crate::{PScope, Scope, Sym};
+use std::collections::VecDeque;
+
+/// The last values given to one name, oldest first
+pub(crate) struct Versions<T> {
+    cap: usize,
+    copy: fn(&T) -> T,
+    vals: VecDeque<T>,
+}
+
+/// For forks, which keep the names chosen but none of their values
+impl<T> Clone for Versions<T> {
+    fn clone(&self) -> Self {
+        Versions {
+            cap: self.cap,
+            copy: self.copy,
+            vals: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> Scope<T> {
+    /// Keeps a copy of the value k now has here, if k was chosen
+    pub(crate) fn keep_version(&self, k: Sym) {
+        let mut vs = self.hooks().versions.borrow_mut();
+        let h = match vs.get_mut(&k) {
+            Some(h) if h.cap > 0 => h,
+            _ => return,
+        };
+        if let Some(v) = self.data().get(k) {
+            if h.vals.len() == h.cap {
+                h.vals.pop_front();
+            }
+            h.vals.push_back((h.copy)(v));
+        }
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// root.keep_versions("port", 3);
+/// for p in [80, 8080, 443, 22] {
+///     root.set_local("port".to_string(), p);
+/// }
+/// root.child().update("port", |p| *p += 1);
+/// assert_eq!(root.history_of("port"), vec![443, 22, 23]);
+/// assert_eq!(root.get("port"), Some(23));
+/// ```
+impl<T: Clone> PScope<T> {
+    /// Keeps copies of the last n values given to id in any frame of the tree,
+    /// whether set or changed in place, from now on. Choosing id again only
+    /// changes n. Restoring a snapshot or tree is not kept. Forks keep
+    /// the names chosen, but start without their values.
+    pub fn keep_versions(&self, id: &str, n: usize) {
+        let k = self.p.intern(id);
+        let mut vs = self.p.hooks().versions.borrow_mut();
+        let h = vs.entry(k).or_insert_with(|| Versions {
+            cap: n,
+            copy: T::clone,
+            vals: VecDeque::new(),
+        });
+        h.cap = n;
+        while h.vals.len() > n {
+            h.vals.pop_front();
+        }
+    }
+
+    /// The values kept for id, oldest first, ending with the latest.
+    /// Empty if id was not chosen with `keep_versions`.
+    pub fn history_of(&self, id: &str) -> Vec<T> {
+        let k = match self.p.sym(id) {
+            Some(k) => k,
+            None => return Vec::new(),
+        };
+        match self.p.hooks().versions.borrow().get(&k) {
+            Some(h) => h.vals.iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl<T> PScope<T> {
+    /// Stops keeping values for id and drops those kept, false if it was not chosen
+    pub fn forget_versions(&self, id: &str) -> bool {
+        match self.p.sym(id) {
+            Some(k) => self.p.hooks().versions.borrow_mut().remove(&k).is_some(),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn versions_are_kept_per_name() {
+        let root = PScope::new();
+        root.set_local("level".to_string(), 0);
+        root.keep_versions("level", 4);
+        assert!(root.history_of("level").is_empty());
+        assert!(root.history_of("unseen").is_empty());
+
+        let c = root.child();
+        c.set("level".to_string(), 1);
+        c.set_local("level".to_string(), 10);
+        c.set_local("other".to_string(), 5);
+        root.remove_local("level");
+        c.update("level", |n| *n += 1);
+        assert_eq!(root.history_of("level"), vec![1, 10, 11]);
+        assert!(c.fork().history_of("level").is_empty());
+
+        root.keep_versions("level", 1);
+        assert_eq!(c.history_of("level"), vec![11]);
+        assert!(root.forget_versions("level"));
+        assert!(!root.forget_versions("level"));
+        c.set_local("level".to_string(), 12);
+        assert!(c.history_of("level").is_empty());
+    }
+}
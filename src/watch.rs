@@ -0,0 +1,305 @@
+use crate::{PScope, SubId};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+#[cfg(feature = "futures-core")]
+use std::pin::Pin;
+#[cfg(feature = "futures-core")]
+use std::task::{Context, Poll, Waker};
+
+/// Shared with the subscribers of a watcher
+#[derive(Default)]
+struct Flag {
+    dirty: Cell<bool>,
+    #[cfg(feature = "futures-core")]
+    waker: RefCell<Option<Waker>>,
+}
+
+impl Flag {
+    fn set(&self) {
+        self.dirty.set(true);
+        #[cfg(feature = "futures-core")]
+        if let Some(w) = self.waker.borrow_mut().take() {
+            w.wake();
+        }
+    }
+}
+
+/// Hears of changes to one name as seen from one frame, see `PScope::watch`.
+/// Each item is the visible value at the time it is taken, or None once the
+/// name is undefined, and several changes between two takes give one item.
+/// As an `Iterator` it ends whenever nothing has changed, so can be drained
+/// again after the next change. With the `futures-core` feature it is also a
+/// `Stream`, which waits for the next change instead and never ends.
+///
+/// Changes made through an import are not heard, as they belong to another tree.
+pub struct ScopeWatcher<T> {
+    scope: PScope<T>,
+    id: String,
+    flag: Rc<Flag>,
+    /// Each frame of the chain, with the subscription on it
+    subs: Vec<(PScope<T>, SubId)>,
+}
+
+impl<T> ScopeWatcher<T> {
+    /// Whether there is a change not yet taken
+    pub fn changed(&self) -> bool {
+        self.flag.dirty.get()
+    }
+}
+
+impl<T: Clone> ScopeWatcher<T> {
+    /// The visible value, if the name has changed since it was last taken
+    pub fn poll_change(&mut self) -> Option<Option<T>> {
+        if !self.flag.dirty.replace(false) {
+            return None;
+        }
+        Some(self.scope.get(&self.id))
+    }
+}
+
+impl<T: Clone> Iterator for ScopeWatcher<T> {
+    type Item = Option<T>;
+    fn next(&mut self) -> Option<Option<T>> {
+        self.poll_change()
+    }
+}
+
+#[cfg(feature = "futures-core")]
+impl<T: Clone> futures_core::Stream for ScopeWatcher<T> {
+    type Item = Option<T>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Option<T>>> {
+        let w = self.get_mut();
+        match w.poll_change() {
+            Some(v) => Poll::Ready(Some(v)),
+            None => {
+                *w.flag.waker.borrow_mut() = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for ScopeWatcher<T> {
+    fn drop(&mut self) {
+        for (f, id) in &self.subs {
+            f.unsubscribe(*id);
+        }
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// let root = PScope::new();
+/// let c = root.child();
+/// let mut w = c.watch("x");
+/// assert_eq!(w.next(), Some(None));
+/// assert_eq!(w.next(), None);
+///
+/// root.set_local("x".to_string(), 1);
+/// root.set_local("other".to_string(), 0);
+/// assert_eq!(w.next(), Some(Some(1)));
+/// c.set_local("x".to_string(), 2);
+/// c.update("x", |n| *n += 1);
+/// assert_eq!(w.collect::<Vec<_>>(), vec![Some(3)]);
+/// ```
+impl<T: 'static> PScope<T> {
+    /// Watches id as seen from here, by subscribing to this frame and each of
+    /// its parents until the watcher is dropped. The first item is the value
+    /// at the time of the call.
+    pub fn watch(&self, id: &str) -> ScopeWatcher<T> {
+        let flag = Rc::new(Flag::default());
+        flag.dirty.set(true);
+        let subs = self.subscribe_chain(|_| {
+            let (fl, name) = (flag.clone(), id.to_string());
+            move |k, _| {
+                if k == name {
+                    fl.set();
+                }
+            }
+        });
+        ScopeWatcher {
+            scope: self.clone(),
+            id: id.to_string(),
+            flag,
+            subs,
+        }
+    }
+
+    /// Subscribes to this frame and each of its parents, with a callback made by mk
+    fn subscribe_chain<C, F>(&self, mut mk: F) -> Vec<(PScope<T>, SubId)>
+    where
+        C: Fn(&str, &T) + 'static,
+        F: FnMut(&PScope<T>) -> C,
+    {
+        let mut subs = Vec::new();
+        let mut cur = Some(self.clone());
+        while let Some(f) = cur {
+            let sub = f.subscribe(mk(&f));
+            cur = f.parent();
+            subs.push((f, sub));
+        }
+        subs
+    }
+}
+
+/// Calls its callback whenever a name is written where it can be seen from
+/// one frame, until dropped, see `PScope::watchpoint`
+#[must_use = "the watchpoint is removed when dropped"]
+pub struct Watchpoint<T> {
+    subs: Vec<(PScope<T>, SubId)>,
+}
+
+impl<T> Drop for Watchpoint<T> {
+    fn drop(&mut self) {
+        for (f, id) in &self.subs {
+            f.unsubscribe(*id);
+        }
+    }
+}
+
+///
+/// ```rust
+/// use scope_store::PScope;
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+/// let root = PScope::new();
+/// root.set_local("x".to_string(), 1);
+/// let c = root.child();
+///
+/// let seen = Rc::new(RefCell::new(Vec::new()));
+/// let s2 = seen.clone();
+/// let wp = c.watchpoint("x", move |old, new| s2.borrow_mut().push((old.copied(), *new)));
+/// root.set("x".to_string(), 2);
+/// c.set_local("x".to_string(), 3);
+/// root.set_local("x".to_string(), 4);
+/// drop(wp);
+/// c.set_local("x".to_string(), 5);
+/// assert_eq!(*seen.borrow(), vec![(Some(1), 2), (Some(2), 3)]);
+/// ```
+impl<T: Clone + 'static> PScope<T> {
+    /// Calls cb with the old and new value of id whenever it is set or updated
+    /// in the frame it is seen in from here, which may be this one or a parent.
+    /// Writes to an outer binding shadowed from here are not heard, nor are
+    /// removals, though the old value given next is then the one seen.
+    ///
+    /// cb runs once the write is done, before the setter returns,
+    /// so it may read and change the scope.
+    pub fn watchpoint<F: Fn(Option<&T>, &T) + 'static>(&self, id: &str, cb: F) -> Watchpoint<T> {
+        let cb = Rc::new(cb);
+        let last = Rc::new(RefCell::new(self.get(id)));
+        let here = Rc::downgrade(&self.p);
+        let subs = self.subscribe_chain(|f| {
+            let (cb, last, here) = (cb.clone(), last.clone(), here.clone());
+            let (name, depth) = (id.to_string(), f.depth());
+            move |k, v| {
+                if k != name {
+                    return;
+                }
+                let s = match here.upgrade() {
+                    Some(p) => PScope { p },
+                    None => return,
+                };
+                // k was just set in the frame that fired, if it still holds it
+                let written = match s.p.sym(k).and_then(|k| Some((k, s.p.owner(k)?))) {
+                    Some((k, o)) => o.depth == depth && o.data().contains_key(k),
+                    None => false,
+                };
+                let (cb, last, name) = (cb.clone(), last.clone(), name.clone());
+                if written {
+                    let new = v.clone();
+                    s.p.defer(move || {
+                        let old = last.replace(Some(new.clone()));
+                        cb(old.as_ref(), &new);
+                    });
+                } else {
+                    let here = Rc::downgrade(&s.p);
+                    s.p.defer(move || {
+                        if let Some(p) = here.upgrade() {
+                            *last.borrow_mut() = PScope { p }.get(&name);
+                        }
+                    });
+                }
+            }
+        });
+        Watchpoint { subs }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether nothing is subscribed to s any more
+    fn quiet(s: &PScope<i32>) -> bool {
+        (s.p.extras()).is_none_or(|x| x.subs.borrow().is_empty())
+    }
+
+    #[test]
+    fn watchers_hear_the_chain() {
+        let root = PScope::new();
+        root.set_local("x".to_string(), 1);
+        let c = root.child();
+        let mut w = c.watch("x");
+        assert_eq!(w.next(), Some(Some(1)));
+
+        root.child().set_local("x".to_string(), 5);
+        assert!(!w.changed());
+        assert_eq!(c.remove("x"), Some(1));
+        assert_eq!(w.next(), Some(None));
+        root.set_global("x".to_string(), 2);
+        assert_eq!(w.next(), Some(Some(2)));
+
+        drop(w);
+        assert!(quiet(&root) && quiet(&c));
+    }
+
+    #[test]
+    fn watchpoints_see_old_values() {
+        let root = PScope::new();
+        let c = root.child();
+        c.set_local("x".to_string(), 1);
+        let c2 = c.clone();
+        let wp = c.watchpoint("x", move |old, new| {
+            // the frame that was written can be changed again from here
+            c2.set_local("log".to_string(), old.unwrap_or(&0) * 10 + new);
+        });
+        c.update("x", |n| *n += 1);
+        assert_eq!(c.get("log"), Some(12));
+
+        c.remove_local("x");
+        root.set_local("x".to_string(), 7);
+        assert_eq!(c.get("log"), Some(7));
+        drop(wp);
+        assert!(quiet(&root) && quiet(&c));
+    }
+
+    #[cfg(feature = "futures-core")]
+    #[test]
+    fn streams_wait_for_changes() {
+        use futures_core::Stream;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use std::task::Wake;
+
+        struct Count(AtomicUsize);
+        impl Wake for Count {
+            fn wake(self: Arc<Self>) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let count = Arc::new(Count(AtomicUsize::new(0)));
+        let waker = Waker::from(count.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let root = PScope::new();
+        let mut w = root.watch("x");
+        let mut next = |w: &mut ScopeWatcher<i32>| Pin::new(w).poll_next(&mut cx);
+        assert_eq!(next(&mut w), Poll::Ready(Some(None)));
+        assert_eq!(next(&mut w), Poll::Pending);
+        root.set_local("x".to_string(), 1);
+        assert_eq!(count.0.load(Ordering::Relaxed), 1);
+        assert_eq!(next(&mut w), Poll::Ready(Some(Some(1))));
+    }
+}